@@ -14,6 +14,7 @@ const useAuth = () => {
 
   const logout = () => {
     localStorage.removeItem('token');
+    localStorage.removeItem('refresh_token');
     localStorage.removeItem('rol');
     localStorage.removeItem('usuario_id');
     setIsAuthenticated(false);
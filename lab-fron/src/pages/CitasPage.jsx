@@ -44,7 +44,7 @@ const CitasPage = () => {
               <td>{new Date(c.fecha_hora).toLocaleString()}</td>
               <td>
                 <span className={`status status-${c.estado}`}>
-                  {c.estado}
+                  {c.estado.replace('_', ' ')}
                 </span>
               </td>
               <td>{c.motivo || '-'}</td>
@@ -1,6 +1,6 @@
 import { useState } from "react";
 import { useNavigate } from "react-router-dom";
-import { login } from "../services/auth";
+import { login, login2fa, configurar2fa, confirmar2fa } from "../services/auth";
 import ModalMensaje from "../components/ModalMensaje";
 
 const LoginPage = ({ onLoginSuccess }) => { // Recibir la función
@@ -9,6 +9,12 @@ const LoginPage = ({ onLoginSuccess }) => { // Recibir la función
     email: "",
     contrasena: "",
   });
+  // "credenciales", "codigo" (2FA activo) o "enrolamiento" (el rol exige 2FA)
+  const [paso, setPaso] = useState("credenciales");
+  const [token2fa, setToken2fa] = useState("");
+  const [codigo, setCodigo] = useState("");
+  const [configuracion, setConfiguracion] = useState(null);
+  const [codigosRecuperacion, setCodigosRecuperacion] = useState([]);
   const [loading, setLoading] = useState(false);
   const [mensajeModal, setMensajeModal] = useState({
     isOpen: false,
@@ -23,17 +29,29 @@ const LoginPage = ({ onLoginSuccess }) => { // Recibir la función
     });
   };
 
-  // Manejar el envío del formulario de login
- const handleSubmit = async (e) => {
-  e.preventDefault();
-  setLoading(true);
+  const mostrarError = (mensaje) => {
+    setMensajeModal({
+      isOpen: true,
+      tipo: "error",
+      mensaje,
+    });
+  };
+
+  const volverACredenciales = () => {
+    setPaso("credenciales");
+    setToken2fa("");
+    setCodigo("");
+    setConfiguracion(null);
+    setFormData({ ...formData, contrasena: "" });
+  };
 
-  try {
-    const response = await login(formData);
-    const { token, rol, usuario_id, nombre, apellido } = response.data; // Asegúrate de que el backend devuelva nombre y apellido
+  // Guarda la sesión y redirige según el rol
+  const completarLogin = (datos) => {
+    const { token, refresh_token, rol, usuario_id, nombre, apellido } = datos;
 
-    // Almacenar token, rol, usuario_id, nombre y apellido en localStorage
+    // Almacenar tokens, rol, usuario_id, nombre y apellido en localStorage
     localStorage.setItem("token", token);
+    localStorage.setItem("refresh_token", refresh_token);
     localStorage.setItem("rol", rol);
     localStorage.setItem("usuario_id", usuario_id);
     localStorage.setItem("nombre_usuario", nombre);
@@ -57,17 +75,77 @@ const LoginPage = ({ onLoginSuccess }) => { // Recibir la función
       default:
         navigate("/");
     }
-  } catch (err) {
-    console.error("Error de login:", err);
-    setMensajeModal({
-      isOpen: true,
-      tipo: "error",
-      mensaje: "Credenciales inválidas. Por favor intenta de nuevo.",
-    });
-  } finally {
-    setLoading(false);
-  }
-};
+  };
+
+  // Manejar el envío del formulario de login
+  const handleSubmit = async (e) => {
+    e.preventDefault();
+    setLoading(true);
+
+    try {
+      const response = await login(formData);
+
+      // Con 2FA la respuesta trae un token temporal en lugar de la sesión
+      if (response.data.token_2fa) {
+        setToken2fa(response.data.token_2fa);
+        setCodigo("");
+        if (response.data.enrolamiento_requerido) {
+          const configuracionTotp = await configurar2fa(response.data.token_2fa);
+          setConfiguracion(configuracionTotp.data);
+          setPaso("enrolamiento");
+        } else {
+          setPaso("codigo");
+        }
+        return;
+      }
+
+      completarLogin(response.data);
+    } catch (err) {
+      console.error("Error de login:", err);
+      mostrarError(err.response?.data?.mensaje || "Credenciales inválidas. Por favor intenta de nuevo.");
+    } finally {
+      setLoading(false);
+    }
+  };
+
+  // Segundo paso: código TOTP o de recuperación
+  const handleCodigoSubmit = async (e) => {
+    e.preventDefault();
+    setLoading(true);
+
+    try {
+      const response = await login2fa(token2fa, codigo);
+      completarLogin(response.data);
+    } catch (err) {
+      console.error("Error en la verificación en dos pasos:", err);
+      setCodigo("");
+      // El token de 2FA vence a los pocos minutos: hay que volver a empezar
+      if (err.response?.data?.mensaje?.includes("Token de verificación")) {
+        volverACredenciales();
+      }
+      mostrarError(err.response?.data?.mensaje || "Código incorrecto. Por favor intenta de nuevo.");
+    } finally {
+      setLoading(false);
+    }
+  };
+
+  // Enrolamiento: confirmar la app autenticadora con un primer código
+  const handleEnrolamientoSubmit = async (e) => {
+    e.preventDefault();
+    setLoading(true);
+
+    try {
+      const response = await confirmar2fa(token2fa, codigo);
+      setCodigosRecuperacion(response.data.codigos_recuperacion);
+      volverACredenciales();
+    } catch (err) {
+      console.error("Error al activar la verificación en dos pasos:", err);
+      setCodigo("");
+      mostrarError(err.response?.data?.mensaje || "Código incorrecto. Por favor intenta de nuevo.");
+    } finally {
+      setLoading(false);
+    }
+  };
 
   const handleMensajeModalClose = () => {
     setMensajeModal({
@@ -77,45 +155,130 @@ const LoginPage = ({ onLoginSuccess }) => { // Recibir la función
     });
   };
 
+  const campoCodigo = (
+    <div className="form-group">
+      <label htmlFor="codigo">Código de verificación:</label>
+      <input
+        type="text"
+        id="codigo"
+        name="codigo"
+        inputMode="numeric"
+        autoComplete="one-time-code"
+        value={codigo}
+        onChange={(e) => setCodigo(e.target.value)}
+        required
+        autoFocus
+      />
+    </div>
+  );
+
   return (
     <div className="login-container">
       <div className="login-form">
-        <h2>Iniciar Sesión</h2>
-        <form onSubmit={handleSubmit}>
-          <div className="form-group">
-            <label htmlFor="email">Email:</label>
-            <input
-              type="email"
-              id="email"
-              name="email"
-              value={formData.email}
-              onChange={handleChange}
-              required
-            />
-          </div>
-          <div className="form-group">
-            <label htmlFor="contrasena">Contraseña:</label>
-            <input
-              type="password"
-              id="contrasena"
-              name="contrasena"
-              value={formData.contrasena}
-              onChange={handleChange}
-              required
-            />
-          </div>
-          <button type="submit" className="btn btn-primary" disabled={loading}>
-            {loading ? "Iniciando sesión..." : "Iniciar Sesión"}
-          </button>
-          <div className="login-actions">
-            <button
-              onClick={() => navigate("/recuperar-contrasena")}
-              className="btn btn-link"
-            >
-              ¿Olvidaste tu contraseña?
-            </button>
-          </div>
-        </form>
+        {paso === "credenciales" && (
+          <>
+            <h2>Iniciar Sesión</h2>
+            {codigosRecuperacion.length > 0 && (
+              <div className="login-2fa-info">
+                <p>
+                  La verificación en dos pasos quedó activada. Guarda estos códigos de recuperación en un lugar
+                  seguro; cada uno sirve una sola vez si pierdes acceso a tu app autenticadora. Luego inicia sesión
+                  de nuevo con tu código.
+                </p>
+                <ul className="codigos-recuperacion">
+                  {codigosRecuperacion.map((codigoRecuperacion) => (
+                    <li key={codigoRecuperacion}>{codigoRecuperacion}</li>
+                  ))}
+                </ul>
+              </div>
+            )}
+            <form onSubmit={handleSubmit}>
+              <div className="form-group">
+                <label htmlFor="email">Email:</label>
+                <input
+                  type="email"
+                  id="email"
+                  name="email"
+                  value={formData.email}
+                  onChange={handleChange}
+                  required
+                />
+              </div>
+              <div className="form-group">
+                <label htmlFor="contrasena">Contraseña:</label>
+                <input
+                  type="password"
+                  id="contrasena"
+                  name="contrasena"
+                  value={formData.contrasena}
+                  onChange={handleChange}
+                  required
+                />
+              </div>
+              <button type="submit" className="btn btn-primary" disabled={loading}>
+                {loading ? "Iniciando sesión..." : "Iniciar Sesión"}
+              </button>
+              <div className="login-actions">
+                <button
+                  type="button"
+                  onClick={() => navigate("/recuperar-contrasena")}
+                  className="btn btn-link"
+                >
+                  ¿Olvidaste tu contraseña?
+                </button>
+              </div>
+            </form>
+          </>
+        )}
+
+        {paso === "codigo" && (
+          <>
+            <h2>Verificación en dos pasos</h2>
+            <p className="login-2fa-info">
+              Ingresa el código de 6 dígitos de tu app autenticadora o uno de tus códigos de recuperación.
+            </p>
+            <form onSubmit={handleCodigoSubmit}>
+              {campoCodigo}
+              <button type="submit" className="btn btn-primary" disabled={loading}>
+                {loading ? "Verificando..." : "Verificar"}
+              </button>
+              <div className="login-actions">
+                <button type="button" onClick={volverACredenciales} className="btn btn-link">
+                  Volver
+                </button>
+              </div>
+            </form>
+          </>
+        )}
+
+        {paso === "enrolamiento" && configuracion && (
+          <>
+            <h2>Configurar verificación en dos pasos</h2>
+            <div className="login-2fa-info">
+              <p>
+                Tu rol exige verificación en dos pasos. Agrega esta cuenta en tu app autenticadora (Google
+                Authenticator, Authy, etc.) con la clave o el enlace, y escribe el código que muestra.
+              </p>
+              <p>
+                Clave: <code>{configuracion.secreto}</code>
+              </p>
+              <p>
+                <a href={configuracion.otpauth_uri}>Abrir en la app autenticadora</a>
+              </p>
+            </div>
+            <form onSubmit={handleEnrolamientoSubmit}>
+              {campoCodigo}
+              <button type="submit" className="btn btn-primary" disabled={loading}>
+                {loading ? "Activando..." : "Activar"}
+              </button>
+              <div className="login-actions">
+                <button type="button" onClick={volverACredenciales} className="btn btn-link">
+                  Cancelar
+                </button>
+              </div>
+            </form>
+          </>
+        )}
       </div>
 
       <ModalMensaje
@@ -128,4 +291,4 @@ const LoginPage = ({ onLoginSuccess }) => { // Recibir la función
   );
 };
 
-export default LoginPage;
\ No newline at end of file
+export default LoginPage;
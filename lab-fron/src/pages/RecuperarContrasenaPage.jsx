@@ -31,7 +31,7 @@ const RecuperarContrasenaPage = () => {
       setMensajeModal({
         isOpen: true,
         tipo: 'exito',
-        mensaje: 'Se ha enviado un token de recuperación a tu correo. Revisa la consola para ver el token.',
+        mensaje: 'Si el email corresponde a una cuenta activa, recibirás un token de recuperación en tu correo.',
       });
     } catch (err) {
       console.error('Error al recuperar contraseña:', err);
@@ -6,4 +6,83 @@ const api = axios.create({
   baseURL: API_BASE_URL,
 });
 
+// Rutas públicas: no llevan token ni intentan refrescarlo ante un 401
+const RUTAS_PUBLICAS = ['/login', '/login/2fa', '/token/refresh', '/logout', '/recuperar-contrasena', '/cambiar-contrasena'];
+
+const esPublica = (url = '') => RUTAS_PUBLICAS.some((ruta) => url === ruta || url.startsWith(`${ruta}?`));
+
+// Todas las rutas protegidas exigen `Authorization: Bearer <token>`
+api.interceptors.request.use((config) => {
+  const token = localStorage.getItem('token');
+  // Respeta un Authorization explícito (token de 2FA durante el login)
+  if (token && !esPublica(config.url) && !config.headers.Authorization) {
+    config.headers.Authorization = `Bearer ${token}`;
+  }
+  return config;
+});
+
+// Un solo refresco en curso aunque varias peticiones reciban 401 a la vez
+let refrescoEnCurso = null;
+
+const refrescarToken = () => {
+  if (!refrescoEnCurso) {
+    const refreshToken = localStorage.getItem('refresh_token');
+    refrescoEnCurso = (refreshToken
+      ? axios.post(`${API_BASE_URL}/token/refresh`, { refresh_token: refreshToken })
+      : Promise.reject(new Error('Sin refresh token'))
+    )
+      .then((res) => {
+        localStorage.setItem('token', res.data.token);
+        localStorage.setItem('refresh_token', res.data.refresh_token);
+        return res.data.token;
+      })
+      .finally(() => {
+        refrescoEnCurso = null;
+      });
+  }
+  return refrescoEnCurso;
+};
+
+const cerrarSesionLocal = () => {
+  ['token', 'refresh_token', 'rol', 'usuario_id', 'nombre_usuario', 'apellido_usuario'].forEach((clave) =>
+    localStorage.removeItem(clave)
+  );
+  if (window.location.pathname !== '/login') {
+    window.location.assign('/login');
+  }
+};
+
+// Ante un 401 se refresca el token y se repite la petición una vez; si el
+// refresco falla, la sesión terminó y se vuelve al login
+api.interceptors.response.use(
+  (res) => res,
+  async (error) => {
+    const original = error.config;
+    if (error.response?.status !== 401 || !original || esPublica(original.url) || original._reintentado) {
+      return Promise.reject(error);
+    }
+
+    original._reintentado = true;
+    try {
+      const token = await refrescarToken();
+      original.headers.Authorization = `Bearer ${token}`;
+      return api(original);
+    } catch (errorRefresco) {
+      cerrarSesionLocal();
+      return Promise.reject(error);
+    }
+  }
+);
+
+// Las ediciones (PUT/PATCH) de pacientes, citas y horarios exigen el ETag
+// recibido al cargar o guardar el registro (respuesta 428 si falta)
+export const conVersion = (etag) => ({ headers: { 'If-Match': etag } });
+
+// Los listados llegan paginados ({ datos, total, pagina, por_pagina, paginas });
+// se devuelve `data` con la lista para que las páginas sigan usando res.data
+export const listar = (url, params) =>
+  api
+    .get(url, { params: { per_page: 100, ...params } })
+    .then((res) => ({ ...res, data: res.data.datos, paginacion: res.data }));
+
 export default api;
\ No newline at end of file
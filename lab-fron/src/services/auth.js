@@ -3,15 +3,28 @@ import api from './api';
 //Login
 export const login = (data) => api.post('/login', data);
 
+// Segundo paso del login con el código TOTP o uno de recuperación
+export const login2fa = (token_2fa, codigo) => api.post('/login/2fa', { token_2fa, codigo });
+
+// Enrolamiento en 2FA con el token de enrolamiento que devuelve /login
+const conToken = (token) => ({ headers: { Authorization: `Bearer ${token}` } });
+export const configurar2fa = (token) => api.post('/usuarios/me/2fa/setup', null, conToken(token));
+export const confirmar2fa = (token, codigo) => api.post('/usuarios/me/2fa/confirm', { codigo }, conToken(token));
+
 //Recuperar Contraseña
 export const recuperarContrasena = (data) => api.post('/recuperar-contrasena', data);
 
 //Cambiar Contraseña
 export const cambiarContrasena = (data) => api.post('/cambiar-contrasena', data);
 
-// Función de logout
+// Función de logout: revoca la sesión en el servidor y limpia el almacenamiento
 export const logout = () => {
+  const refreshToken = localStorage.getItem('refresh_token');
+  if (refreshToken) {
+    api.post('/logout', { refresh_token: refreshToken }).catch((err) => console.error('Error al cerrar sesión:', err));
+  }
   localStorage.removeItem('token');
+  localStorage.removeItem('refresh_token');
   localStorage.removeItem('rol');
   localStorage.removeItem('usuario_id');
 };
\ No newline at end of file
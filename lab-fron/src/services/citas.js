@@ -1,6 +1,19 @@
-import api from './api';
+import api, { conVersion, listar } from './api';
 
-export const getCitas = () => api.get('/citas');
+export const getCitas = (params) => listar('/citas', params);
+export const getCita = (id) => api.get(`/citas/${id}`);
 export const createCita = (data) => api.post('/citas', data);
-export const updateCita = (id, data) => api.put(`/citas/${id}`, data);
-export const deleteCita = (id) => api.delete(`/citas/${id}`);
\ No newline at end of file
+// `etag`: cabecera ETag de la respuesta con la que se cargó la cita
+export const updateCita = (id, data, etag) => api.put(`/citas/${id}`, data, conVersion(etag));
+export const deleteCita = (id) => api.delete(`/citas/${id}`);
+export const confirmarCita = (id) => api.post(`/citas/${id}/confirmar`);
+export const cancelarCita = (id, motivo) => api.post(`/citas/${id}/cancelar`, { motivo });
+export const checkinCita = (id) => api.post(`/citas/${id}/checkin`);
+export const completarCita = (id) => api.post(`/citas/${id}/completar`);
+export const getEstadosCita = (id) => api.get(`/citas/${id}/estados`);
+export const createSerieCitas = (data) => api.post('/citas/series', data);
+export const getSerieCitas = (id) => api.get(`/citas/series/${id}`);
+// alcance: 'esta' | 'siguientes' | 'todas'
+export const editarSerieCitas = (id, alcance, data) => api.patch(`/citas/${id}/serie`, data, { params: { alcance } });
+export const cancelarSerieCitas = (id, alcance, motivo) =>
+  api.post(`/citas/${id}/serie/cancelar`, { motivo }, { params: { alcance } });
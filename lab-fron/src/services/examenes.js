@@ -1,10 +1,10 @@
-import api from './api';
+import api, { listar } from './api';
 
 // Obtener todos los perfiles de exámenes
 export const getPerfilesExamenes = () => api.get('/perfiles_examenes');
 
 // Obtener todos los exámenes
-export const getExamenes = () => api.get('/examenes');
+export const getExamenes = (params) => listar('/examenes', params);
 
 // Obtener exámenes por diagnóstico
 export const getExamenesPorDiagnostico = (diagnosticoId) => api.get(`/examenes_por_diagnostico/${diagnosticoId}`);
@@ -1,6 +1,8 @@
-import api from './api';
+import api, { conVersion, listar } from './api';
 
-export const getHorarios = () => api.get('/horarios');
+export const getHorarios = (params) => listar('/horarios', params);
+export const getHorario = (id) => api.get(`/horarios/${id}`);
 export const createHorario = (data) => api.post('/horarios', data);
-export const updateHorario = (id, data) => api.put(`/horarios/${id}`, data);
+// `etag`: cabecera ETag de la respuesta con la que se cargó el horario
+export const updateHorario = (id, data, etag) => api.put(`/horarios/${id}`, data, conVersion(etag));
 export const deleteHorario = (id) => api.delete(`/horarios/${id}`);
\ No newline at end of file
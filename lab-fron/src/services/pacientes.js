@@ -1,6 +1,9 @@
-import api from './api';
+import api, { conVersion, listar } from './api';
 
-export const getPacientes = () => api.get('/pacientes');
+export const getPacientes = (params) => listar('/pacientes', params);
+export const getPaciente = (id) => api.get(`/pacientes/${id}`);
 export const createPaciente = (data) => api.post('/pacientes', data);
-export const updatePaciente = (id, data) => api.put(`/pacientes/${id}`, data);
+// `etag`: cabecera ETag de la respuesta con la que se cargó el paciente
+export const updatePaciente = (id, data, etag) => api.put(`/pacientes/${id}`, data, conVersion(etag));
+export const patchPaciente = (id, data, etag) => api.patch(`/pacientes/${id}`, data, conVersion(etag));
 export const deletePaciente = (id) => api.delete(`/pacientes/${id}`);
\ No newline at end of file
@@ -1,6 +1,6 @@
-import api from './api';
+import api, { listar } from './api';
 
-export const getUsuarios = () => api.get('/usuarios');
+export const getUsuarios = (params) => listar('/usuarios', params);
 export const createUsuario = (data) => api.post('/usuarios', data);
 export const updateUsuario = (id, data) => api.put(`/usuarios/${id}`, data);
 export const deleteUsuario = (id) => api.delete(`/usuarios/${id}`);
\ No newline at end of file
@@ -0,0 +1,15 @@
+-- Sesiones de refresco de token. Cada login abre una familia de sesiones;
+-- cada refresco rota el token dentro de la misma familia.
+CREATE TABLE sesiones (
+    id UUID PRIMARY KEY,
+    familia_id UUID NOT NULL,
+    usuario_id INTEGER NOT NULL REFERENCES usuarios(id) ON DELETE CASCADE,
+    token_hash TEXT NOT NULL UNIQUE,
+    creado_en TIMESTAMP NOT NULL DEFAULT NOW(),
+    expira_en TIMESTAMP NOT NULL,
+    usado_en TIMESTAMP,
+    revocado_en TIMESTAMP
+);
+
+CREATE INDEX sesiones_usuario_id_idx ON sesiones (usuario_id);
+CREATE INDEX sesiones_familia_id_idx ON sesiones (familia_id);
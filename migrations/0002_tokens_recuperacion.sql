@@ -0,0 +1,11 @@
+-- Tokens de recuperación de contraseña de un solo uso. Solo se guarda el hash.
+CREATE TABLE tokens_recuperacion (
+    id SERIAL PRIMARY KEY,
+    usuario_id INTEGER NOT NULL REFERENCES usuarios(id) ON DELETE CASCADE,
+    token_hash TEXT NOT NULL UNIQUE,
+    creado_en TIMESTAMP NOT NULL DEFAULT NOW(),
+    expira_en TIMESTAMP NOT NULL,
+    used_at TIMESTAMP
+);
+
+CREATE INDEX tokens_recuperacion_usuario_id_idx ON tokens_recuperacion (usuario_id);
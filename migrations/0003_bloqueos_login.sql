@@ -0,0 +1,7 @@
+-- Intentos fallidos de login por cuenta ('cuenta:<email>') y por IP ('ip:<direccion>')
+CREATE TABLE bloqueos_login (
+    clave TEXT PRIMARY KEY,
+    fallos INTEGER NOT NULL DEFAULT 0,
+    ultimo_fallo TIMESTAMP NOT NULL DEFAULT NOW(),
+    bloqueado_hasta TIMESTAMP
+);
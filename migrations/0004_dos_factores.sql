@@ -0,0 +1,18 @@
+-- Autenticación de dos factores (TOTP)
+ALTER TABLE usuarios
+    ADD COLUMN totp_secreto TEXT,
+    ADD COLUMN totp_activo BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN totp_ultimo_paso BIGINT;
+
+ALTER TABLE roles
+    ADD COLUMN requiere_2fa BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Códigos de recuperación de un solo uso. Solo se guarda el hash.
+CREATE TABLE codigos_recuperacion_2fa (
+    id SERIAL PRIMARY KEY,
+    usuario_id INTEGER NOT NULL REFERENCES usuarios(id) ON DELETE CASCADE,
+    codigo_hash TEXT NOT NULL,
+    usado_en TIMESTAMP
+);
+
+CREATE INDEX codigos_recuperacion_2fa_usuario_id_idx ON codigos_recuperacion_2fa (usuario_id);
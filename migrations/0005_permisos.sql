@@ -0,0 +1,48 @@
+-- Permisos granulares asignados a roles
+CREATE TABLE permisos (
+    id SERIAL PRIMARY KEY,
+    codigo TEXT NOT NULL UNIQUE,
+    descripcion TEXT NOT NULL
+);
+
+CREATE TABLE roles_permisos (
+    rol_id INTEGER NOT NULL REFERENCES roles(id) ON DELETE CASCADE,
+    permiso_id INTEGER NOT NULL REFERENCES permisos(id) ON DELETE CASCADE,
+    PRIMARY KEY (rol_id, permiso_id)
+);
+
+INSERT INTO permisos (codigo, descripcion) VALUES
+    ('pacientes:read', 'Consultar pacientes'),
+    ('pacientes:write', 'Registrar y modificar pacientes'),
+    ('pacientes:delete', 'Eliminar pacientes'),
+    ('expedientes:read', 'Consultar expedientes y diagnósticos'),
+    ('expedientes:write', 'Registrar diagnósticos'),
+    ('examenes:read', 'Consultar el catálogo de exámenes'),
+    ('examenes:resultados:read', 'Consultar resultados de exámenes'),
+    ('examenes:resultados:write', 'Registrar resultados de exámenes'),
+    ('examenes:resultados:validar', 'Validar resultados de exámenes'),
+    ('usuarios:read', 'Consultar usuarios'),
+    ('usuarios:write', 'Crear y modificar usuarios'),
+    ('horarios:read', 'Consultar horarios'),
+    ('horarios:write', 'Crear y modificar horarios'),
+    ('citas:read', 'Consultar citas'),
+    ('citas:write', 'Crear y modificar citas'),
+    ('roles:admin', 'Administrar roles y permisos');
+
+-- Equivalente a la política por rol anterior
+INSERT INTO roles_permisos (rol_id, permiso_id)
+SELECT r.id, p.id
+FROM roles r
+JOIN permisos p ON
+    (r.nombre = 'admin' AND p.codigo NOT IN ('expedientes:write', 'examenes:resultados:write', 'examenes:resultados:validar'))
+    OR (r.nombre = 'jefe_medico' AND p.codigo IN (
+        'pacientes:read', 'pacientes:write', 'expedientes:read', 'expedientes:write',
+        'examenes:read', 'examenes:resultados:read', 'examenes:resultados:write', 'examenes:resultados:validar',
+        'usuarios:read', 'horarios:read', 'horarios:write', 'citas:read', 'citas:write'))
+    OR (r.nombre = 'medico' AND p.codigo IN (
+        'pacientes:read', 'expedientes:read', 'expedientes:write',
+        'examenes:read', 'examenes:resultados:read', 'examenes:resultados:write',
+        'horarios:read', 'citas:read', 'citas:write'))
+    OR (r.nombre = 'asistente' AND p.codigo IN (
+        'pacientes:read', 'pacientes:write', 'examenes:read',
+        'horarios:read', 'citas:read', 'citas:write'));
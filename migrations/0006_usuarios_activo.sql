@@ -0,0 +1,2 @@
+-- Desactivación lógica de usuarios (p. ej. empleados que dejaron la clínica)
+ALTER TABLE usuarios ADD COLUMN activo BOOLEAN NOT NULL DEFAULT TRUE;
@@ -0,0 +1,36 @@
+-- Bitácora de auditoría de accesos y cambios sobre datos clínicos. Solo admite INSERT.
+CREATE TABLE auditoria (
+    id BIGSERIAL PRIMARY KEY,
+    fecha TIMESTAMP NOT NULL DEFAULT NOW(),
+    usuario_id INTEGER REFERENCES usuarios(id),
+    ip TEXT,
+    accion TEXT NOT NULL,
+    entidad TEXT NOT NULL,
+    entidad_id INTEGER,
+    paciente_id INTEGER,
+    cambios JSONB
+);
+
+CREATE INDEX auditoria_fecha_idx ON auditoria (fecha);
+CREATE INDEX auditoria_usuario_id_idx ON auditoria (usuario_id);
+CREATE INDEX auditoria_entidad_idx ON auditoria (entidad, entidad_id);
+CREATE INDEX auditoria_paciente_id_idx ON auditoria (paciente_id);
+
+CREATE FUNCTION auditoria_solo_insercion() RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'La tabla auditoria no admite modificaciones';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER auditoria_solo_insercion
+    BEFORE UPDATE OR DELETE ON auditoria
+    FOR EACH ROW EXECUTE FUNCTION auditoria_solo_insercion();
+
+CREATE TRIGGER auditoria_sin_truncate
+    BEFORE TRUNCATE ON auditoria
+    FOR EACH STATEMENT EXECUTE FUNCTION auditoria_solo_insercion();
+
+INSERT INTO permisos (codigo, descripcion) VALUES ('auditoria:read', 'Consultar la bitácora de auditoría');
+
+INSERT INTO roles_permisos (rol_id, permiso_id)
+SELECT r.id, p.id FROM roles r, permisos p WHERE r.nombre = 'admin' AND p.codigo = 'auditoria:read';
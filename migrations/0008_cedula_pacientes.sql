@@ -0,0 +1,41 @@
+-- Cédula del representante para menores sin cédula propia
+ALTER TABLE pacientes ADD COLUMN ci_representante TEXT;
+
+-- Lleva a la forma canónica (V-12345678) las cédulas existentes con un
+-- formato reconocible; las demás quedan como están para revisión manual
+UPDATE pacientes p
+SET ci = CASE WHEN n.limpia ~ '^[VEJ]' THEN left(n.limpia, 1) ELSE 'V' END
+    || '-' || ltrim(regexp_replace(n.limpia, '^[VEJ]', ''), '0')
+FROM (
+    SELECT id, upper(regexp_replace(ci, '[\s.-]', '', 'g')) AS limpia FROM pacientes
+) n
+WHERE n.id = p.id
+  AND n.limpia ~ '^[VEJ]?[0-9]{1,9}$'
+  AND n.limpia !~ '^[VEJ]?0+$';
+
+-- Pacientes cuya cédula coincide con la de otro una vez normalizada. Se
+-- conserva la cédula en el registro más antiguo y los demás se apartan aquí
+-- para revisión (y, si corresponde, fusión) en vez de impedir el índice
+CREATE TABLE pacientes_ci_revision (
+    paciente_id INTEGER PRIMARY KEY REFERENCES pacientes(id) ON DELETE CASCADE,
+    ci_anterior TEXT NOT NULL,
+    paciente_conservado_id INTEGER REFERENCES pacientes(id) ON DELETE SET NULL,
+    fecha TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+INSERT INTO pacientes_ci_revision (paciente_id, ci_anterior, paciente_conservado_id)
+SELECT id, ci, primero
+FROM (
+    SELECT id, ci, MIN(id) OVER (PARTITION BY ci) AS primero FROM pacientes WHERE ci IS NOT NULL
+) repetidas
+WHERE id <> primero;
+
+-- Cédula provisional única con un formato que no valida, de modo que la
+-- próxima edición del paciente obliga a corregirla
+UPDATE pacientes p
+SET ci = r.ci_anterior || '-REVISAR-' || p.id
+FROM pacientes_ci_revision r
+WHERE r.paciente_id = p.id;
+
+CREATE UNIQUE INDEX pacientes_ci_unico ON pacientes (ci);
+CREATE INDEX pacientes_ci_representante_idx ON pacientes (ci_representante);
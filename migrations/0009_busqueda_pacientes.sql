@@ -0,0 +1,19 @@
+-- Búsqueda aproximada de pacientes sin distinguir acentos ni mayúsculas
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+CREATE EXTENSION IF NOT EXISTS unaccent;
+
+-- unaccent() no es IMMUTABLE; este envoltorio fija el diccionario para
+-- poder usarlo en índices
+CREATE FUNCTION sin_acentos(texto TEXT) RETURNS TEXT AS $$
+    SELECT public.unaccent('public.unaccent'::regdictionary, texto)
+$$ LANGUAGE sql IMMUTABLE PARALLEL SAFE STRICT;
+
+-- Texto en el que se busca: nombre, apellido, cédula, teléfono y email
+CREATE FUNCTION texto_busqueda_paciente(
+    nombre TEXT, apellido TEXT, ci TEXT, telefono TEXT, email TEXT
+) RETURNS TEXT AS $$
+    SELECT lower(sin_acentos(concat_ws(' ', nombre, apellido, replace(ci, '-', ''), telefono, email)))
+$$ LANGUAGE sql IMMUTABLE PARALLEL SAFE;
+
+CREATE INDEX pacientes_busqueda_trgm_idx ON pacientes
+    USING gin (texto_busqueda_paciente(nombre, apellido, ci, telefono, email) gin_trgm_ops);
@@ -0,0 +1,31 @@
+-- Cédula comparable entre formatos antiguos: sin puntos, espacios ni
+-- guiones, en mayúsculas y con prefijo V por defecto
+CREATE FUNCTION ci_normalizada(ci TEXT) RETURNS TEXT AS $$
+    SELECT CASE
+        WHEN limpia ~ '^[0-9]' THEN 'V' || ltrim(limpia, '0')
+        ELSE left(limpia, 1) || ltrim(substr(limpia, 2), '0')
+    END
+    FROM (SELECT upper(regexp_replace(ci, '[\s.-]', '', 'g')) AS limpia) n
+$$ LANGUAGE sql IMMUTABLE PARALLEL SAFE STRICT;
+
+-- Registro de cada fusión de pacientes duplicados
+CREATE TABLE fusiones_pacientes (
+    id SERIAL PRIMARY KEY,
+    paciente_conservado_id INTEGER NOT NULL REFERENCES pacientes(id),
+    -- El paciente fusionado se archiva con fusionado_en apuntando al
+    -- conservado; además se guarda su ficha tal como estaba
+    paciente_fusionado_id INTEGER NOT NULL,
+    datos_fusionado JSONB NOT NULL,
+    citas_movidas INTEGER NOT NULL,
+    diagnosticos_movidos INTEGER NOT NULL,
+    usuario_id INTEGER REFERENCES usuarios(id),
+    fecha TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX fusiones_pacientes_conservado_idx ON fusiones_pacientes (paciente_conservado_id);
+
+INSERT INTO permisos (codigo, descripcion) VALUES ('pacientes:merge', 'Fusionar pacientes duplicados');
+
+INSERT INTO roles_permisos (rol_id, permiso_id)
+SELECT r.id, p.id FROM roles r, permisos p
+WHERE r.nombre IN ('admin', 'jefe_medico') AND p.codigo = 'pacientes:merge';
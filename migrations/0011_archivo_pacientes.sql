@@ -0,0 +1,14 @@
+-- Archivado lógico de pacientes: la historia clínica se conserva durante el
+-- período de retención y solo después puede purgarse
+ALTER TABLE pacientes ADD COLUMN archivado_en TIMESTAMP;
+ALTER TABLE pacientes ADD COLUMN archivado_por INTEGER REFERENCES usuarios(id);
+
+CREATE INDEX pacientes_archivado_en_idx ON pacientes (archivado_en) WHERE archivado_en IS NOT NULL;
+
+UPDATE permisos SET descripcion = 'Archivar y restaurar pacientes' WHERE codigo = 'pacientes:delete';
+
+INSERT INTO permisos (codigo, descripcion) VALUES ('pacientes:purge', 'Purgar pacientes archivados cuya retención expiró');
+
+INSERT INTO roles_permisos (rol_id, permiso_id)
+SELECT r.id, p.id FROM roles r, permisos p
+WHERE r.nombre = 'admin' AND p.codigo = 'pacientes:purge';
@@ -0,0 +1,14 @@
+-- Datos complementarios de los pacientes, todos opcionales
+ALTER TABLE pacientes
+    ADD COLUMN grupo_sanguineo TEXT
+        CHECK (grupo_sanguineo IN ('A+', 'A-', 'B+', 'B-', 'AB+', 'AB-', 'O+', 'O-')),
+    ADD COLUMN alergias TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN direccion_estado TEXT,
+    ADD COLUMN direccion_municipio TEXT,
+    ADD COLUMN direccion_parroquia TEXT,
+    ADD COLUMN direccion_detalle TEXT,
+    ADD COLUMN contacto_emergencia_nombre TEXT,
+    ADD COLUMN contacto_emergencia_telefono TEXT,
+    ADD COLUMN contacto_emergencia_parentesco TEXT,
+    ADD COLUMN aseguradora TEXT,
+    ADD COLUMN numero_poliza TEXT;
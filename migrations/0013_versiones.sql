@@ -0,0 +1,19 @@
+-- Concurrencia optimista: cada UPDATE incrementa la versión de la fila,
+-- que la API expone como ETag y compara con If-Match
+CREATE FUNCTION incrementar_version() RETURNS TRIGGER AS $$
+BEGIN
+    NEW.version := OLD.version + 1;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+ALTER TABLE pacientes ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE citas ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE horarios ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+
+CREATE TRIGGER pacientes_version BEFORE UPDATE ON pacientes
+    FOR EACH ROW EXECUTE FUNCTION incrementar_version();
+CREATE TRIGGER citas_version BEFORE UPDATE ON citas
+    FOR EACH ROW EXECUTE FUNCTION incrementar_version();
+CREATE TRIGGER horarios_version BEFORE UPDATE ON horarios
+    FOR EACH ROW EXECUTE FUNCTION incrementar_version();
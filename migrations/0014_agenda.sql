@@ -0,0 +1,3 @@
+-- Búsqueda de citas y horarios por médico al validar la agenda
+CREATE INDEX citas_usuario_fecha_idx ON citas (usuario_id, fecha_hora);
+CREATE INDEX horarios_usuario_idx ON horarios (usuario_id);
@@ -0,0 +1,20 @@
+-- Especialidad de los médicos, para buscar el primer hueco libre por área
+ALTER TABLE usuarios ADD COLUMN especialidad TEXT;
+
+-- Días sin consulta para toda la clínica
+CREATE TABLE feriados (
+    fecha DATE PRIMARY KEY,
+    descripcion TEXT NOT NULL
+);
+
+-- Ausencias puntuales de un médico (vacaciones, congresos, reposos)
+CREATE TABLE periodos_bloqueados (
+    id SERIAL PRIMARY KEY,
+    usuario_id INTEGER NOT NULL REFERENCES usuarios(id) ON DELETE CASCADE,
+    desde TIMESTAMP NOT NULL,
+    hasta TIMESTAMP NOT NULL,
+    motivo TEXT,
+    CHECK (hasta > desde)
+);
+
+CREATE INDEX periodos_bloqueados_usuario_idx ON periodos_bloqueados (usuario_id, desde);
@@ -0,0 +1,36 @@
+-- Estados de cita cerrados: los valores libres que se guardaban hasta ahora
+-- ("pendiente", "Confirmada ", "realizada"...) se llevan al estado equivalente
+-- y los cambios de estado posteriores quedan registrados con autor y fecha
+UPDATE citas SET estado = CASE regexp_replace(lower(sin_acentos(trim(estado))), '[\s-]+', '_', 'g')
+    WHEN 'confirmada' THEN 'confirmada'
+    WHEN 'confirmado' THEN 'confirmada'
+    WHEN 'en_sala' THEN 'en_sala'
+    WHEN 'en_espera' THEN 'en_sala'
+    WHEN 'atendida' THEN 'atendida'
+    WHEN 'atendido' THEN 'atendida'
+    WHEN 'realizada' THEN 'atendida'
+    WHEN 'completada' THEN 'atendida'
+    WHEN 'cancelada' THEN 'cancelada'
+    WHEN 'cancelado' THEN 'cancelada'
+    WHEN 'no_asistio' THEN 'no_asistio'
+    WHEN 'ausente' THEN 'no_asistio'
+    ELSE 'programada'
+END;
+
+ALTER TABLE citas ALTER COLUMN estado SET DEFAULT 'programada';
+ALTER TABLE citas ADD CONSTRAINT citas_estado_valido
+    CHECK (estado IN ('programada', 'confirmada', 'en_sala', 'atendida', 'cancelada', 'no_asistio'));
+
+ALTER TABLE citas ADD COLUMN motivo_cancelacion TEXT;
+
+CREATE TABLE citas_estados (
+    id SERIAL PRIMARY KEY,
+    cita_id INTEGER NOT NULL REFERENCES citas(id) ON DELETE CASCADE,
+    estado_anterior TEXT NOT NULL,
+    estado_nuevo TEXT NOT NULL,
+    motivo TEXT,
+    usuario_id INTEGER REFERENCES usuarios(id),
+    fecha TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX citas_estados_cita_idx ON citas_estados (cita_id, fecha);
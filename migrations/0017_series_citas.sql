@@ -0,0 +1,23 @@
+-- Series de citas recurrentes (controles crónicos). La regla se guarda tal
+-- como se pidió y cada ocurrencia es una cita normal con su posición en la
+-- serie, para poder editar o cancelar "esta", "esta y las siguientes" o
+-- "todas"
+CREATE TABLE series_citas (
+    id SERIAL PRIMARY KEY,
+    paciente_id INTEGER NOT NULL REFERENCES pacientes(id) ON DELETE CASCADE,
+    usuario_id INTEGER NOT NULL REFERENCES usuarios(id),
+    frecuencia TEXT NOT NULL CHECK (frecuencia IN ('semanal', 'mensual')),
+    intervalo INTEGER NOT NULL DEFAULT 1 CHECK (intervalo BETWEEN 1 AND 12),
+    inicio TIMESTAMP NOT NULL,
+    repeticiones INTEGER CHECK (repeticiones > 0),
+    hasta DATE,
+    motivo TEXT,
+    creada_por INTEGER REFERENCES usuarios(id),
+    creada_en TIMESTAMP NOT NULL DEFAULT NOW(),
+    CHECK ((repeticiones IS NULL) <> (hasta IS NULL))
+);
+
+ALTER TABLE citas ADD COLUMN serie_id INTEGER REFERENCES series_citas(id) ON DELETE SET NULL;
+ALTER TABLE citas ADD COLUMN serie_indice INTEGER;
+
+CREATE INDEX citas_serie_idx ON citas (serie_id, serie_indice) WHERE serie_id IS NOT NULL;
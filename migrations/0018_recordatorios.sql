@@ -0,0 +1,22 @@
+-- Cola de recordatorios de citas. Se encola uno por cita, antelación y
+-- canal cuando llega su momento; `fecha_cita` es la hora de la cita al
+-- encolarlo, para que una cita movida genere recordatorios nuevos y los
+-- pendientes de la hora anterior se descarten
+CREATE TABLE recordatorios (
+    id SERIAL PRIMARY KEY,
+    cita_id INTEGER NOT NULL REFERENCES citas(id) ON DELETE CASCADE,
+    fecha_cita TIMESTAMP NOT NULL,
+    antelacion_minutos INTEGER NOT NULL,
+    canal TEXT NOT NULL,
+    destino TEXT NOT NULL,
+    estado TEXT NOT NULL DEFAULT 'pendiente'
+        CHECK (estado IN ('pendiente', 'enviado', 'fallido', 'descartado')),
+    intentos INTEGER NOT NULL DEFAULT 0,
+    ultimo_error TEXT,
+    proximo_intento TIMESTAMP NOT NULL DEFAULT NOW(),
+    enviado_en TIMESTAMP,
+    creado_en TIMESTAMP NOT NULL DEFAULT NOW(),
+    UNIQUE (cita_id, fecha_cita, antelacion_minutos, canal)
+);
+
+CREATE INDEX recordatorios_pendientes_idx ON recordatorios (proximo_intento) WHERE estado = 'pendiente';
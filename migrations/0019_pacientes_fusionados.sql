@@ -0,0 +1,6 @@
+-- Al fusionar pacientes el duplicado ya no se elimina: se archiva y apunta al
+-- paciente conservado, de modo que la fusión puede revisarse o deshacerse
+-- mientras dure la retención
+ALTER TABLE pacientes ADD COLUMN fusionado_en INTEGER REFERENCES pacientes(id) ON DELETE SET NULL;
+
+CREATE INDEX pacientes_fusionado_en_idx ON pacientes (fusionado_en) WHERE fusionado_en IS NOT NULL;
@@ -0,0 +1,476 @@
+// Reglas de agenda de los médicos: una cita debe caer completa dentro de
+// un horario del médico para ese día de la semana, fuera de feriados y
+// periodos bloqueados, y no puede solaparse con otra cita vigente del mismo
+// médico.
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+use serde::Serialize;
+use serde_json::json;
+use sqlx::{PgConnection, PgPool, Row};
+use std::collections::HashSet;
+
+use crate::errores::ErrorApi;
+
+// Nombre del día tal como se compara con `horarios.dia_semana`, que se
+// normaliza en SQL con lower(sin_acentos(trim(...)))
+pub fn dia_semana(dia: Weekday) -> &'static str {
+    match dia {
+        Weekday::Mon => "lunes",
+        Weekday::Tue => "martes",
+        Weekday::Wed => "miercoles",
+        Weekday::Thu => "jueves",
+        Weekday::Fri => "viernes",
+        Weekday::Sat => "sabado",
+        Weekday::Sun => "domingo",
+    }
+}
+
+// Forma comparable de un `dia_semana` guardado: "Miércoles " -> "miercoles"
+fn normalizar_dia(dia: &str) -> String {
+    dia.trim()
+        .to_lowercase()
+        .chars()
+        .map(|c| match c {
+            'á' => 'a',
+            'é' => 'e',
+            'í' => 'i',
+            'ó' => 'o',
+            'ú' => 'u',
+            otro => otro,
+        })
+        .collect()
+}
+
+// Comprueba que el médico puede atender una cita de `duracion_minutos` en
+// `fecha_hora`. `excluir_cita` es la cita que se está moviendo, que no
+// choca consigo misma.
+//
+// Toma un bloqueo consultivo por médico hasta el fin de la transacción para
+// que dos reservas simultáneas no ocupen el mismo hueco.
+pub async fn verificar_cita(
+    conn: &mut PgConnection,
+    usuario_id: i32,
+    fecha_hora: NaiveDateTime,
+    duracion_minutos: i32,
+    excluir_cita: Option<i32>,
+) -> Result<(), ErrorApi> {
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext('agenda:' || $1::TEXT))")
+        .bind(usuario_id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al bloquear la agenda del médico {}: {}", usuario_id, e);
+            ErrorApi::from(e)
+        })?;
+
+    verificar_horario(&mut *conn, usuario_id, fecha_hora, duracion_minutos).await?;
+    verificar_ausencias(&mut *conn, usuario_id, fecha_hora, duracion_minutos).await?;
+    verificar_solapamiento(&mut *conn, usuario_id, fecha_hora, duracion_minutos, excluir_cita).await
+}
+
+async fn verificar_horario(
+    conn: &mut PgConnection,
+    usuario_id: i32,
+    fecha_hora: NaiveDateTime,
+    duracion_minutos: i32,
+) -> Result<(), ErrorApi> {
+    let dia = dia_semana(fecha_hora.weekday());
+
+    let horarios: Vec<(NaiveTime, NaiveTime)> = sqlx::query(
+        "SELECT hora_inicio, hora_fin FROM horarios WHERE usuario_id = $1 AND lower(sin_acentos(trim(dia_semana))) = $2 ORDER BY hora_inicio"
+    )
+    .bind(usuario_id)
+    .bind(dia)
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al obtener horarios del médico {}: {}", usuario_id, e);
+        ErrorApi::from(e)
+    })?
+    .iter()
+    .map(|row| (row.get("hora_inicio"), row.get("hora_fin")))
+    .collect();
+
+    let inicio = fecha_hora.time();
+    let (fin, desborda) = inicio.overflowing_add_signed(Duration::minutes(duracion_minutos.into()));
+    let cabe = desborda == 0
+        && horarios
+            .iter()
+            .any(|(hora_inicio, hora_fin)| *hora_inicio <= inicio && fin <= *hora_fin);
+
+    if cabe {
+        return Ok(());
+    }
+
+    let mensaje = if horarios.is_empty() {
+        format!("El médico no atiende los {}", dia)
+    } else {
+        format!("La cita cae fuera del horario de atención del médico para los {}", dia)
+    };
+
+    Err(ErrorApi::ConflictoAgenda {
+        mensaje,
+        detalles: Some(json!({
+            "motivo": "fuera_de_horario",
+            "dia_semana": dia,
+            "hora_inicio": inicio,
+            "hora_fin": fin,
+            "horarios": horarios
+                .iter()
+                .map(|(hora_inicio, hora_fin)| json!({ "hora_inicio": hora_inicio, "hora_fin": hora_fin }))
+                .collect::<Vec<_>>(),
+        })),
+    })
+}
+
+async fn verificar_ausencias(
+    conn: &mut PgConnection,
+    usuario_id: i32,
+    fecha_hora: NaiveDateTime,
+    duracion_minutos: i32,
+) -> Result<(), ErrorApi> {
+    let feriado = sqlx::query("SELECT descripcion FROM feriados WHERE fecha = $1")
+        .bind(fecha_hora.date())
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al consultar feriados: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    if let Some(row) = feriado {
+        let descripcion: String = row.get("descripcion");
+        return Err(ErrorApi::ConflictoAgenda {
+            mensaje: format!("El {} es feriado ({})", fecha_hora.format("%d/%m/%Y"), descripcion),
+            detalles: Some(json!({
+                "motivo": "feriado",
+                "fecha": fecha_hora.date(),
+                "descripcion": descripcion,
+            })),
+        });
+    }
+
+    let bloqueo = sqlx::query(
+        "SELECT id, desde, hasta, motivo FROM periodos_bloqueados WHERE usuario_id = $1 AND desde < $2 + make_interval(mins => $3) AND hasta > $2 ORDER BY desde LIMIT 1"
+    )
+    .bind(usuario_id)
+    .bind(fecha_hora)
+    .bind(duracion_minutos)
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al consultar periodos bloqueados del médico {}: {}", usuario_id, e);
+        ErrorApi::from(e)
+    })?;
+
+    match bloqueo {
+        None => Ok(()),
+        Some(row) => Err(ErrorApi::ConflictoAgenda {
+            mensaje: "El médico no está disponible en ese horario".to_string(),
+            detalles: Some(json!({
+                "motivo": "periodo_bloqueado",
+                "bloqueo_id": row.get::<i32, _>("id"),
+                "desde": row.get::<NaiveDateTime, _>("desde"),
+                "hasta": row.get::<NaiveDateTime, _>("hasta"),
+                "motivo_bloqueo": row.get::<Option<String>, _>("motivo"),
+            })),
+        }),
+    }
+}
+
+async fn verificar_solapamiento(
+    conn: &mut PgConnection,
+    usuario_id: i32,
+    fecha_hora: NaiveDateTime,
+    duracion_minutos: i32,
+    excluir_cita: Option<i32>,
+) -> Result<(), ErrorApi> {
+    let choque = sqlx::query(
+        "SELECT id, fecha_hora FROM citas WHERE usuario_id = $1 AND estado <> 'cancelada' AND ($4::INTEGER IS NULL OR id <> $4) AND fecha_hora < $2 + make_interval(mins => $3) AND fecha_hora + make_interval(mins => $3) > $2 ORDER BY fecha_hora LIMIT 1"
+    )
+    .bind(usuario_id)
+    .bind(fecha_hora)
+    .bind(duracion_minutos)
+    .bind(excluir_cita)
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al buscar citas solapadas del médico {}: {}", usuario_id, e);
+        ErrorApi::from(e)
+    })?;
+
+    match choque {
+        None => Ok(()),
+        Some(row) => {
+            let cita_id: i32 = row.get("id");
+            let ocupada: NaiveDateTime = row.get("fecha_hora");
+            Err(ErrorApi::ConflictoAgenda {
+                mensaje: format!(
+                    "El médico ya tiene una cita el {} a las {}",
+                    ocupada.format("%d/%m/%Y"),
+                    ocupada.format("%H:%M")
+                ),
+                detalles: Some(json!({
+                    "motivo": "cita_solapada",
+                    "cita_id": cita_id,
+                    "fecha_hora": ocupada,
+                    "duracion_minutos": duracion_minutos,
+                })),
+            })
+        }
+    }
+}
+
+// Hueco libre en la agenda de un médico
+#[derive(Serialize)]
+pub struct Hueco {
+    pub inicio: NaiveDateTime,
+    pub fin: NaiveDateTime,
+}
+
+// Lo que define y ocupa la agenda de un médico entre dos fechas
+pub struct Agenda {
+    horarios: Vec<(String, NaiveTime, NaiveTime)>,
+    feriados: HashSet<NaiveDate>,
+    bloqueos: Vec<(NaiveDateTime, NaiveDateTime)>,
+    citas: Vec<NaiveDateTime>,
+    duracion_cita_minutos: i32,
+}
+
+impl Agenda {
+    // `duracion_cita_minutos` es lo que ocupa cada cita ya reservada
+    pub async fn cargar(
+        pool: &PgPool,
+        usuario_id: i32,
+        desde: NaiveDate,
+        hasta: NaiveDate,
+        duracion_cita_minutos: i32,
+    ) -> Result<Self, ErrorApi> {
+        let inicio = desde.and_time(NaiveTime::MIN);
+        let fin = (hasta + Duration::days(1)).and_time(NaiveTime::MIN);
+
+        let horarios = sqlx::query("SELECT dia_semana, hora_inicio, hora_fin FROM horarios WHERE usuario_id = $1")
+            .bind(usuario_id)
+            .fetch_all(pool)
+            .await
+            .map_err(|e| {
+                eprintln!("Error al obtener horarios del médico {}: {}", usuario_id, e);
+                ErrorApi::from(e)
+            })?
+            .iter()
+            .map(|row| {
+                (
+                    normalizar_dia(row.get("dia_semana")),
+                    row.get("hora_inicio"),
+                    row.get("hora_fin"),
+                )
+            })
+            .collect();
+
+        let feriados = sqlx::query("SELECT fecha FROM feriados WHERE fecha BETWEEN $1 AND $2")
+            .bind(desde)
+            .bind(hasta)
+            .fetch_all(pool)
+            .await
+            .map_err(|e| {
+                eprintln!("Error al consultar feriados: {}", e);
+                ErrorApi::from(e)
+            })?
+            .iter()
+            .map(|row| row.get("fecha"))
+            .collect();
+
+        let bloqueos = sqlx::query(
+            "SELECT desde, hasta FROM periodos_bloqueados WHERE usuario_id = $1 AND desde < $3 AND hasta > $2"
+        )
+        .bind(usuario_id)
+        .bind(inicio)
+        .bind(fin)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al consultar periodos bloqueados del médico {}: {}", usuario_id, e);
+            ErrorApi::from(e)
+        })?
+        .iter()
+        .map(|row| (row.get("desde"), row.get("hasta")))
+        .collect();
+
+        let citas = sqlx::query(
+            "SELECT fecha_hora FROM citas WHERE usuario_id = $1 AND estado <> 'cancelada' AND fecha_hora > $2 - make_interval(mins => $4) AND fecha_hora < $3"
+        )
+        .bind(usuario_id)
+        .bind(inicio)
+        .bind(fin)
+        .bind(duracion_cita_minutos)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al obtener citas del médico {}: {}", usuario_id, e);
+            ErrorApi::from(e)
+        })?
+        .iter()
+        .map(|row| row.get("fecha_hora"))
+        .collect();
+
+        Ok(Agenda {
+            horarios,
+            feriados,
+            bloqueos,
+            citas,
+            duracion_cita_minutos,
+        })
+    }
+
+    // Huecos de `duracion_minutos` entre `desde` y `hasta` (ambos incluidos)
+    // que empiezan después de `ahora`. Cada horario se recorre desde su hora
+    // de inicio en pasos de la duración pedida.
+    pub fn huecos(
+        &self,
+        desde: NaiveDate,
+        hasta: NaiveDate,
+        duracion_minutos: i32,
+        ahora: NaiveDateTime,
+    ) -> Vec<Hueco> {
+        let duracion = Duration::minutes(duracion_minutos.into());
+        let mut huecos = Vec::new();
+
+        for fecha in desde.iter_days().take_while(|fecha| *fecha <= hasta) {
+            if self.feriados.contains(&fecha) {
+                continue;
+            }
+            let dia = dia_semana(fecha.weekday());
+
+            for (_, hora_inicio, hora_fin) in self.horarios.iter().filter(|(d, _, _)| d == dia) {
+                let cierre = fecha.and_time(*hora_fin);
+                let mut inicio = fecha.and_time(*hora_inicio);
+
+                while inicio + duracion <= cierre {
+                    let fin = inicio + duracion;
+                    if inicio >= ahora && self.libre(inicio, fin) {
+                        huecos.push(Hueco { inicio, fin });
+                    }
+                    inicio = fin;
+                }
+            }
+        }
+
+        // Dos horarios solapados del mismo día producirían huecos repetidos
+        huecos.sort_by_key(|hueco| hueco.inicio);
+        huecos.dedup_by_key(|hueco| hueco.inicio);
+        huecos
+    }
+
+    fn libre(&self, inicio: NaiveDateTime, fin: NaiveDateTime) -> bool {
+        let ocupa = Duration::minutes(self.duracion_cita_minutos.into());
+        !self.citas.iter().any(|cita| *cita < fin && *cita + ocupa > inicio)
+            && !self.bloqueos.iter().any(|(desde, hasta)| *desde < fin && *hasta > inicio)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 3 de marzo de 2025 es lunes
+    fn lunes() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2025, 3, 3).unwrap()
+    }
+
+    fn hora(h: u32, m: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, 0).unwrap()
+    }
+
+    fn el_lunes(h: u32, m: u32) -> NaiveDateTime {
+        lunes().and_time(hora(h, m))
+    }
+
+    // Lunes de 08:00 a 10:00, citas de 30 minutos
+    fn manana_del_lunes() -> Agenda {
+        Agenda {
+            horarios: vec![("lunes".to_string(), hora(8, 0), hora(10, 0))],
+            feriados: HashSet::new(),
+            bloqueos: Vec::new(),
+            citas: Vec::new(),
+            duracion_cita_minutos: 30,
+        }
+    }
+
+    fn inicios(agenda: &Agenda) -> Vec<NaiveDateTime> {
+        let antes = el_lunes(0, 0) - Duration::days(1);
+        agenda
+            .huecos(lunes(), lunes(), 30, antes)
+            .into_iter()
+            .map(|hueco| hueco.inicio)
+            .collect()
+    }
+
+    #[test]
+    fn el_ultimo_hueco_termina_justo_al_cierre() {
+        let huecos = manana_del_lunes().huecos(lunes(), lunes(), 30, el_lunes(0, 0));
+        assert_eq!(huecos.len(), 4);
+        assert_eq!(huecos[0].inicio, el_lunes(8, 0));
+        assert_eq!(huecos[3].inicio, el_lunes(9, 30));
+        assert_eq!(huecos[3].fin, el_lunes(10, 0));
+
+        // Un hueco que se pasaría del cierre no se ofrece
+        let agenda = Agenda {
+            horarios: vec![("lunes".to_string(), hora(8, 0), hora(10, 15))],
+            ..manana_del_lunes()
+        };
+        assert_eq!(inicios(&agenda).last(), Some(&el_lunes(9, 30)));
+    }
+
+    #[test]
+    fn dia_completo_no_tiene_huecos() {
+        let agenda = Agenda {
+            citas: vec![el_lunes(8, 0), el_lunes(8, 30), el_lunes(9, 0), el_lunes(9, 30)],
+            ..manana_del_lunes()
+        };
+        assert!(inicios(&agenda).is_empty());
+    }
+
+    #[test]
+    fn una_cita_ocupa_solo_su_hueco() {
+        let agenda = Agenda {
+            citas: vec![el_lunes(8, 30)],
+            ..manana_del_lunes()
+        };
+        assert_eq!(inicios(&agenda), vec![el_lunes(8, 0), el_lunes(9, 0), el_lunes(9, 30)]);
+    }
+
+    #[test]
+    fn feriado_no_tiene_huecos() {
+        let agenda = Agenda {
+            feriados: HashSet::from([lunes()]),
+            ..manana_del_lunes()
+        };
+        assert!(inicios(&agenda).is_empty());
+    }
+
+    #[test]
+    fn bloqueo_parcial_quita_los_huecos_que_toca() {
+        // 08:45-09:10 pisa parte de los huecos de 08:30 y de 09:00
+        let agenda = Agenda {
+            bloqueos: vec![(el_lunes(8, 45), el_lunes(9, 10))],
+            ..manana_del_lunes()
+        };
+        assert_eq!(inicios(&agenda), vec![el_lunes(8, 0), el_lunes(9, 30)]);
+
+        // Un bloqueo que termina justo cuando empieza el hueco no lo quita
+        let agenda = Agenda {
+            bloqueos: vec![(el_lunes(7, 0), el_lunes(8, 0))],
+            ..manana_del_lunes()
+        };
+        assert_eq!(inicios(&agenda).len(), 4);
+    }
+
+    #[test]
+    fn no_ofrece_huecos_pasados_ni_de_otros_dias() {
+        let huecos = manana_del_lunes().huecos(lunes(), lunes(), 30, el_lunes(8, 45));
+        assert_eq!(huecos.iter().map(|hueco| hueco.inicio).collect::<Vec<_>>(), vec![el_lunes(9, 0), el_lunes(9, 30)]);
+
+        let martes = lunes() + Duration::days(1);
+        assert!(manana_del_lunes().huecos(martes, martes, 30, el_lunes(0, 0)).is_empty());
+    }
+}
@@ -0,0 +1,246 @@
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts, Query, State},
+    http::request::Parts,
+    response::Json,
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::{PgExecutor, PgPool, Postgres, QueryBuilder, Row};
+use std::sync::Arc;
+
+use crate::auth::AuthUser;
+use crate::cliente::IpCliente;
+use crate::config::Config;
+use crate::errores::ErrorApi;
+
+// Quién realiza la operación: usuario del token e IP de origen
+pub struct Actor {
+    pub usuario_id: i32,
+    pub ip: String,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Actor
+where
+    S: Send + Sync,
+    Arc<Config>: FromRef<S>,
+{
+    type Rejection = ErrorApi;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let usuario = AuthUser::from_request_parts(parts, state).await?;
+        let IpCliente(ip) = IpCliente::from_request_parts(parts, state).await?;
+
+        Ok(Actor {
+            usuario_id: usuario.usuario_id,
+            ip: ip.to_string(),
+        })
+    }
+}
+
+pub struct Evento<'a> {
+    pub accion: &'a str,
+    pub entidad: &'a str,
+    pub entidad_id: Option<i32>,
+    pub paciente_id: Option<i32>,
+    pub antes: Option<Value>,
+    pub despues: Option<Value>,
+}
+
+impl<'a> Evento<'a> {
+    pub fn lectura(entidad: &'a str, entidad_id: Option<i32>, paciente_id: Option<i32>) -> Self {
+        Evento {
+            accion: "leer",
+            entidad,
+            entidad_id,
+            paciente_id,
+            antes: None,
+            despues: None,
+        }
+    }
+
+    pub fn cambio(
+        accion: &'a str,
+        entidad: &'a str,
+        entidad_id: i32,
+        paciente_id: Option<i32>,
+        antes: Option<Value>,
+        despues: Option<Value>,
+    ) -> Self {
+        Evento {
+            accion,
+            entidad,
+            entidad_id: Some(entidad_id),
+            paciente_id,
+            antes,
+            despues,
+        }
+    }
+}
+
+// Serializa un valor para la bitácora
+pub fn valor<T: Serialize>(dato: &T) -> Option<Value> {
+    serde_json::to_value(dato).ok()
+}
+
+// Diferencia campo a campo: {"campo": {"antes": x, "despues": y}}.
+// En creaciones y eliminaciones uno de los lados es null.
+fn diferencia(antes: Option<&Value>, despues: Option<&Value>) -> Option<Value> {
+    let vacio = Map::new();
+    let antes_obj = antes.and_then(Value::as_object).unwrap_or(&vacio);
+    let despues_obj = despues.and_then(Value::as_object).unwrap_or(&vacio);
+
+    let mut cambios = Map::new();
+    for campo in antes_obj.keys().chain(despues_obj.keys()) {
+        let valor_antes = antes_obj.get(campo).unwrap_or(&Value::Null);
+        let valor_despues = despues_obj.get(campo).unwrap_or(&Value::Null);
+        if valor_antes != valor_despues && !cambios.contains_key(campo) {
+            let mut par = Map::new();
+            par.insert("antes".to_string(), valor_antes.clone());
+            par.insert("despues".to_string(), valor_despues.clone());
+            cambios.insert(campo.clone(), Value::Object(par));
+        }
+    }
+
+    if cambios.is_empty() {
+        None
+    } else {
+        Some(Value::Object(cambios))
+    }
+}
+
+pub async fn registrar<'e, E: PgExecutor<'e>>(
+    executor: E,
+    actor: &Actor,
+    evento: Evento<'_>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO auditoria (usuario_id, ip, accion, entidad, entidad_id, paciente_id, cambios) VALUES ($1, $2, $3, $4, $5, $6, $7)"
+    )
+    .bind(actor.usuario_id)
+    .bind(&actor.ip)
+    .bind(evento.accion)
+    .bind(evento.entidad)
+    .bind(evento.entidad_id)
+    .bind(evento.paciente_id)
+    .bind(diferencia(evento.antes.as_ref(), evento.despues.as_ref()))
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+// Lectura de un listado o búsqueda: una fila por registro devuelto, como
+// pares (entidad_id, paciente_id), para que filtrar la bitácora por paciente
+// también muestre quién lo vio en un listado. Un listado vacío deja una
+// sola fila sin ids.
+pub async fn registrar_lecturas<'e, E: PgExecutor<'e>>(
+    executor: E,
+    actor: &Actor,
+    entidad: &str,
+    lecturas: &[(i32, i32)],
+) -> Result<(), sqlx::Error> {
+    if lecturas.is_empty() {
+        return registrar(executor, actor, Evento::lectura(entidad, None, None)).await;
+    }
+
+    let (entidad_ids, paciente_ids): (Vec<i32>, Vec<i32>) = lecturas.iter().copied().unzip();
+
+    sqlx::query(
+        "INSERT INTO auditoria (usuario_id, ip, accion, entidad, entidad_id, paciente_id) SELECT $1, $2, 'leer', $3, l.entidad_id, l.paciente_id FROM UNNEST($4::INTEGER[], $5::INTEGER[]) AS l(entidad_id, paciente_id)"
+    )
+    .bind(actor.usuario_id)
+    .bind(&actor.ip)
+    .bind(entidad)
+    .bind(&entidad_ids)
+    .bind(&paciente_ids)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RegistroAuditoria {
+    id: i64,
+    fecha: NaiveDateTime,
+    usuario_id: Option<i32>,
+    ip: Option<String>,
+    accion: String,
+    entidad: String,
+    entidad_id: Option<i32>,
+    paciente_id: Option<i32>,
+    cambios: Option<Value>,
+}
+
+#[derive(Deserialize)]
+pub struct FiltroAuditoria {
+    usuario_id: Option<i32>,
+    accion: Option<String>,
+    entidad: Option<String>,
+    entidad_id: Option<i32>,
+    paciente_id: Option<i32>,
+    desde: Option<NaiveDateTime>,
+    hasta: Option<NaiveDateTime>,
+    limite: Option<i64>,
+}
+
+// GET /auditoria
+pub async fn get_auditoria(
+    State(pool): State<PgPool>,
+    Query(filtro): Query<FiltroAuditoria>,
+) -> Result<Json<Vec<RegistroAuditoria>>, ErrorApi> {
+    let mut consulta: QueryBuilder<Postgres> = QueryBuilder::new(
+        "SELECT id, fecha, usuario_id, ip, accion, entidad, entidad_id, paciente_id, cambios FROM auditoria WHERE TRUE"
+    );
+
+    if let Some(usuario_id) = filtro.usuario_id {
+        consulta.push(" AND usuario_id = ").push_bind(usuario_id);
+    }
+    if let Some(accion) = filtro.accion {
+        consulta.push(" AND accion = ").push_bind(accion);
+    }
+    if let Some(entidad) = filtro.entidad {
+        consulta.push(" AND entidad = ").push_bind(entidad);
+    }
+    if let Some(entidad_id) = filtro.entidad_id {
+        consulta.push(" AND entidad_id = ").push_bind(entidad_id);
+    }
+    if let Some(paciente_id) = filtro.paciente_id {
+        consulta.push(" AND paciente_id = ").push_bind(paciente_id);
+    }
+    if let Some(desde) = filtro.desde {
+        consulta.push(" AND fecha >= ").push_bind(desde);
+    }
+    if let Some(hasta) = filtro.hasta {
+        consulta.push(" AND fecha <= ").push_bind(hasta);
+    }
+
+    consulta
+        .push(" ORDER BY fecha DESC, id DESC LIMIT ")
+        .push_bind(filtro.limite.unwrap_or(100).clamp(1, 1000));
+
+    let rows = consulta.build().fetch_all(&pool).await.map_err(|e| {
+        eprintln!("Error al consultar auditoría: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    let registros: Vec<RegistroAuditoria> = rows
+        .into_iter()
+        .map(|row| RegistroAuditoria {
+            id: row.get("id"),
+            fecha: row.get("fecha"),
+            usuario_id: row.get("usuario_id"),
+            ip: row.get("ip"),
+            accion: row.get("accion"),
+            entidad: row.get("entidad"),
+            entidad_id: row.get("entidad_id"),
+            paciente_id: row.get("paciente_id"),
+            cambios: row.get("cambios"),
+        })
+        .collect();
+
+    Ok(Json(registros))
+}
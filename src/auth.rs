@@ -0,0 +1,213 @@
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts, MatchedPath, Request},
+    http::{header::AUTHORIZATION, request::Parts, Method},
+    middleware::Next,
+    response::Response,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+use crate::config::{Config, JwtConfig};
+use crate::errores::ErrorApi;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TipoToken {
+    // Token de acceso normal
+    #[default]
+    Acceso,
+    // Contraseña verificada, falta el código TOTP (solo sirve en /login/2fa)
+    Pendiente2fa,
+    // El rol exige 2FA y el usuario aún no lo configuró (solo sirve para enrolarse)
+    Enrolamiento2fa,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Claims {
+    pub sub: String,
+    pub exp: usize,
+    pub rol: String,
+    pub usuario_id: i32,
+    pub iss: String,
+    pub aud: String,
+    #[serde(default)]
+    pub tipo: TipoToken,
+    #[serde(default)]
+    pub permisos: Vec<String>,
+}
+
+// Token opaco de 256 bits; en la base de datos solo se guarda su hash
+pub fn generar_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+// Usuario autenticado a partir del header `Authorization: Bearer <token>`
+#[derive(Clone)]
+pub struct AuthUser {
+    pub usuario_id: i32,
+    pub tipo: TipoToken,
+    pub permisos: Vec<String>,
+}
+
+impl AuthUser {
+    fn desde_token(jwt: &JwtConfig, token: &str) -> Result<Self, ErrorApi> {
+        let claims = jwt.verificar(token).map_err(|e| {
+            eprintln!("Token inválido o expirado: {}", e);
+            ErrorApi::NoAutenticado("Token inválido o expirado".to_string())
+        })?;
+
+        // Un token de 2FA pendiente solo se canjea en /login/2fa
+        if claims.tipo == TipoToken::Pendiente2fa {
+            return Err(ErrorApi::NoAutenticado(
+                "Falta completar la verificación en dos pasos".to_string(),
+            ));
+        }
+
+        Ok(AuthUser {
+            usuario_id: claims.usuario_id,
+            tipo: claims.tipo,
+            permisos: claims.permisos,
+        })
+    }
+
+    pub fn tiene_permiso(&self, permiso: &str) -> bool {
+        self.permisos.iter().any(|p| p == permiso)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthUser
+where
+    S: Send + Sync,
+    Arc<Config>: FromRef<S>,
+{
+    type Rejection = ErrorApi;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        // Si el middleware ya validó el token, reutilizar el resultado
+        if let Some(usuario) = parts.extensions.get::<AuthUser>() {
+            return Ok(usuario.clone());
+        }
+
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|valor| valor.to_str().ok())
+            .and_then(|valor| valor.strip_prefix("Bearer "))
+            .ok_or_else(|| ErrorApi::NoAutenticado("Falta el token de acceso".to_string()))?;
+
+        let config = Arc::<Config>::from_ref(state);
+        AuthUser::desde_token(&config.jwt, token.trim())
+    }
+}
+
+// Permiso requerido por ruta. `None` significa cualquier usuario autenticado.
+fn permiso_requerido(metodo: &Method, ruta: &str) -> Option<&'static str> {
+    let permiso = match (metodo.as_str(), ruta) {
+        ("GET", "/pacientes")
+        | ("GET", "/pacientes/buscar")
+        | ("GET", "/pacientes/duplicados")
+        | ("GET", "/pacientes/:id")
+        | ("GET", "/pacientes/:id/fusiones") => "pacientes:read",
+        ("POST", "/pacientes") | ("PUT", "/pacientes/:id") | ("PATCH", "/pacientes/:id") => "pacientes:write",
+        ("DELETE", "/pacientes/:id") | ("POST", "/pacientes/:id/restaurar") => "pacientes:delete",
+        ("DELETE", "/pacientes/:id/purgar") => "pacientes:purge",
+        ("POST", "/pacientes/:id/fusionar") => "pacientes:merge",
+        ("GET", "/expedientes/:paciente_id") | ("GET", "/expedientes/:paciente_id/diagnosticos") => {
+            "expedientes:read"
+        }
+        ("POST", "/expedientes/:paciente_id/diagnosticos") => "expedientes:write",
+        ("GET", "/perfiles_examenes")
+        | ("GET", "/examenes")
+        | ("GET", "/examenes_por_perfil/:perfil_id") => "examenes:read",
+        ("GET", "/examenes_por_diagnostico/:diagnostico_id") => "examenes:resultados:read",
+        ("POST", "/examenes_por_diagnostico/:diagnostico_id") => "examenes:resultados:write",
+        ("GET", "/usuarios") | ("GET", "/usuarios/:id") => "usuarios:read",
+        ("POST", "/usuarios") | ("PUT", "/usuarios/:id") | ("POST", "/usuarios/:id/desbloquear") => {
+            "usuarios:write"
+        }
+        ("GET", "/horarios")
+        | ("GET", "/horarios/:id")
+        | ("GET", "/medicos/primer_hueco")
+        | ("GET", "/medicos/:usuario_id/disponibilidad")
+        | ("GET", "/medicos/:usuario_id/bloqueos")
+        | ("GET", "/feriados") => "horarios:read",
+        ("POST", "/horarios")
+        | ("PUT", "/horarios/:id")
+        | ("PATCH", "/horarios/:id")
+        | ("DELETE", "/horarios/:id")
+        | ("POST", "/medicos/:usuario_id/bloqueos")
+        | ("DELETE", "/medicos/:usuario_id/bloqueos/:id")
+        | ("POST", "/feriados")
+        | ("DELETE", "/feriados/:fecha") => "horarios:write",
+        ("GET", "/citas")
+        | ("GET", "/citas/:id")
+        | ("GET", "/citas/:id/estados")
+        | ("GET", "/citas/:id/recordatorios")
+        | ("GET", "/citas/series/:id") => "citas:read",
+        ("POST", "/citas")
+        | ("PUT", "/citas/:id")
+        | ("PATCH", "/citas/:id")
+        | ("DELETE", "/citas/:id")
+        | ("POST", "/citas/:id/confirmar")
+        | ("POST", "/citas/:id/cancelar")
+        | ("POST", "/citas/:id/checkin")
+        | ("POST", "/citas/:id/completar")
+        | ("POST", "/citas/:id/no_asistio")
+        | ("POST", "/citas/series")
+        | ("PATCH", "/citas/:id/serie")
+        | ("POST", "/citas/:id/serie/cancelar") => "citas:write",
+        (_, "/roles")
+        | (_, "/roles/:id")
+        | (_, "/roles/:id/permisos")
+        | (_, "/roles/:id/2fa")
+        | (_, "/permisos") => "roles:admin",
+        ("GET", "/auditoria") => "auditoria:read",
+        _ => return None,
+    };
+    Some(permiso)
+}
+
+// Middleware para las rutas protegidas: exige un token válido (401) y
+// exige el permiso de la ruta (403).
+pub async fn requerir_autenticacion(
+    usuario: AuthUser,
+    ruta: MatchedPath,
+    mut req: Request,
+    next: Next,
+) -> Result<Response, ErrorApi> {
+    // Con un token de enrolamiento solo se puede configurar el 2FA
+    if usuario.tipo == TipoToken::Enrolamiento2fa
+        && !matches!(ruta.as_str(), "/usuarios/me/2fa/setup" | "/usuarios/me/2fa/confirm")
+    {
+        return Err(ErrorApi::Prohibido(
+            "Configura la verificación en dos pasos para continuar".to_string(),
+        ));
+    }
+
+    if let Some(permiso) = permiso_requerido(req.method(), ruta.as_str()) {
+        if !usuario.tiene_permiso(permiso) {
+            eprintln!(
+                "Acceso denegado a {} {} para el usuario {}: falta el permiso {}",
+                req.method(),
+                ruta.as_str(),
+                usuario.usuario_id,
+                permiso
+            );
+            return Err(ErrorApi::Prohibido(format!("Falta el permiso {}", permiso)));
+        }
+    }
+
+    req.extensions_mut().insert(usuario);
+    Ok(next.run(req).await)
+}
@@ -0,0 +1,100 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+use sqlx::{PgPool, Row};
+
+use crate::config::LoginConfig;
+use crate::errores::ErrorApi;
+
+pub fn clave_cuenta(email: &str) -> String {
+    format!("cuenta:{}", email.trim().to_lowercase())
+}
+
+pub fn clave_ip(ip: &std::net::IpAddr) -> String {
+    format!("ip:{}", ip)
+}
+
+// Indica si alguna de las claves está bloqueada en este momento
+pub async fn esta_bloqueado(pool: &PgPool, claves: &[String]) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query(
+        "SELECT EXISTS (SELECT 1 FROM bloqueos_login WHERE clave = ANY($1) AND bloqueado_hasta > NOW()) AS bloqueado"
+    )
+    .bind(claves)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.get("bloqueado"))
+}
+
+// Suma un fallo a la clave y, al superar el umbral, la bloquea con
+// backoff exponencial: base, 2*base, 4*base... hasta el máximo configurado.
+pub async fn registrar_fallo(
+    pool: &PgPool,
+    clave: &str,
+    umbral: i32,
+    config: &LoginConfig,
+) -> Result<(), sqlx::Error> {
+    let row = sqlx::query(
+        "INSERT INTO bloqueos_login (clave, fallos, ultimo_fallo) VALUES ($1, 1, NOW()) ON CONFLICT (clave) DO UPDATE SET fallos = CASE WHEN bloqueos_login.ultimo_fallo < NOW() - make_interval(secs => $2) THEN 1 ELSE bloqueos_login.fallos + 1 END, ultimo_fallo = NOW() RETURNING fallos"
+    )
+    .bind(clave)
+    .bind(config.ventana as f64)
+    .fetch_one(pool)
+    .await?;
+
+    let fallos: i32 = row.get("fallos");
+    if fallos < umbral {
+        return Ok(());
+    }
+
+    let exponente = (fallos - umbral).min(30) as u32;
+    let segundos = config
+        .bloqueo_base
+        .saturating_mul(2u64.saturating_pow(exponente))
+        .min(config.bloqueo_maximo);
+
+    eprintln!("Bloqueando {} por {} segundos tras {} fallos", clave, segundos, fallos);
+
+    sqlx::query("UPDATE bloqueos_login SET bloqueado_hasta = NOW() + make_interval(secs => $2) WHERE clave = $1")
+        .bind(clave)
+        .bind(segundos as f64)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn limpiar(pool: &PgPool, clave: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM bloqueos_login WHERE clave = $1")
+        .bind(clave)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+// POST /usuarios/:id/desbloquear
+pub async fn desbloquear_usuario(
+    Path(id): Path<i32>,
+    State(pool): State<PgPool>,
+) -> Result<StatusCode, ErrorApi> {
+    let row = sqlx::query("SELECT email FROM usuarios WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al obtener usuario: {}", e);
+            ErrorApi::from(e)
+        })?
+        .ok_or_else(|| ErrorApi::no_encontrado("Usuario"))?;
+
+    let email: String = row.get("email");
+
+    limpiar(&pool, &clave_cuenta(&email)).await.map_err(|e| {
+        eprintln!("Error al desbloquear usuario: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,182 @@
+// Cédulas de identidad venezolanas.
+//
+// Forma canónica: prefijo, guion y número sin puntos ni ceros a la izquierda
+// ("V-12345678"). Los menores sin cédula propia usan la de su representante
+// más un sufijo de secuencia de dos dígitos ("V-12345678-01").
+
+pub struct Cedula {
+    prefijo: char,
+    numero: String,
+    secuencia: Option<u32>,
+}
+
+impl Cedula {
+    pub fn parse(entrada: &str) -> Result<Self, &'static str> {
+        let limpia: String = entrada
+            .trim()
+            .to_uppercase()
+            .chars()
+            .filter(|c| !matches!(c, '.' | ' ' | ','))
+            .collect();
+
+        if limpia.is_empty() {
+            return Err("La cédula es obligatoria");
+        }
+
+        // Sin prefijo se asume venezolano
+        let (prefijo, resto) = match limpia.chars().next() {
+            Some(c) if c.is_ascii_digit() => ('V', limpia.as_str()),
+            Some(c @ ('V' | 'E' | 'J' | 'P')) => (c, limpia[1..].trim_start_matches('-')),
+            _ => return Err("El prefijo de la cédula debe ser V, E, J o P"),
+        };
+
+        if prefijo == 'P' {
+            // Pasaporte: alfanumérico, sin sufijo de menor
+            if !(5..=15).contains(&resto.len()) || !resto.chars().all(|c| c.is_ascii_alphanumeric()) {
+                return Err("El pasaporte debe tener entre 5 y 15 letras o dígitos");
+            }
+            return Ok(Cedula {
+                prefijo,
+                numero: resto.to_string(),
+                secuencia: None,
+            });
+        }
+
+        let (numero, secuencia) = match resto.split_once('-') {
+            None => (resto, None),
+            Some((numero, sufijo)) => {
+                let secuencia = match sufijo.parse::<u32>() {
+                    Ok(n) if sufijo.len() == 2 && n > 0 => n,
+                    _ => return Err("El sufijo de menor debe tener dos dígitos, entre 01 y 99"),
+                };
+                (numero, Some(secuencia))
+            }
+        };
+
+        if numero.is_empty() || !numero.chars().all(|c| c.is_ascii_digit()) {
+            return Err("La cédula solo puede contener dígitos después del prefijo");
+        }
+
+        let numero = numero.trim_start_matches('0');
+        let longitud_valida = match prefijo {
+            'J' => (8..=9).contains(&numero.len()),
+            _ => (1..=8).contains(&numero.len()),
+        };
+        if !longitud_valida {
+            return Err("El número de cédula no es válido");
+        }
+
+        Ok(Cedula {
+            prefijo,
+            numero: numero.to_string(),
+            secuencia,
+        })
+    }
+
+    // Cédula del titular, sin sufijo de menor
+    pub fn base(&self) -> String {
+        format!("{}-{}", self.prefijo, self.numero)
+    }
+
+    pub fn es_de_menor(&self) -> bool {
+        self.secuencia.is_some()
+    }
+
+    pub fn canonica(&self) -> String {
+        match self.secuencia {
+            Some(secuencia) => de_menor(&self.base(), secuencia),
+            None => self.base(),
+        }
+    }
+}
+
+// Cédula asignada al menor número `secuencia` del representante
+pub fn de_menor(representante: &str, secuencia: u32) -> String {
+    format!("{}-{:02}", representante, secuencia)
+}
+
+// Secuencia del menor si `ci` fue derivada de la cédula del representante
+pub fn secuencia_de_menor(ci: &str, representante: &str) -> Option<u32> {
+    let sufijo = ci.strip_prefix(representante)?.strip_prefix('-')?;
+    if sufijo.len() != 2 {
+        return None;
+    }
+    sufijo.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn canonica(entrada: &str) -> Result<String, &'static str> {
+        Cedula::parse(entrada).map(|cedula| cedula.canonica())
+    }
+
+    #[test]
+    fn prefijos_validos() {
+        assert_eq!(canonica("V-12.345.678"), Ok("V-12345678".to_string()));
+        assert_eq!(canonica("v 12345678"), Ok("V-12345678".to_string()));
+        assert_eq!(canonica("E-00123456"), Ok("E-123456".to_string()));
+        assert_eq!(canonica("J-123456789"), Ok("J-123456789".to_string()));
+        assert_eq!(canonica("P-AB12345"), Ok("P-AB12345".to_string()));
+    }
+
+    #[test]
+    fn sin_prefijo_se_asume_venezolano() {
+        assert_eq!(canonica("12345678"), Ok("V-12345678".to_string()));
+        assert_eq!(canonica("1"), Ok("V-1".to_string()));
+    }
+
+    #[test]
+    fn rif_juridico_lleva_ocho_o_nueve_digitos() {
+        assert!(Cedula::parse("J-12345678").is_ok());
+        assert!(Cedula::parse("J-123456789").is_ok());
+        assert!(Cedula::parse("J-1234567").is_err());
+        assert!(Cedula::parse("J-1234567890").is_err());
+        // Los ceros a la izquierda no cuentan
+        assert!(Cedula::parse("J-001234567").is_err());
+    }
+
+    #[test]
+    fn cedula_de_persona_lleva_hasta_ocho_digitos() {
+        assert!(Cedula::parse("V-123456789").is_err());
+        assert!(Cedula::parse("E-123456789").is_err());
+    }
+
+    #[test]
+    fn sufijo_de_menor() {
+        let cedula = Cedula::parse("V-12345678-01").unwrap();
+        assert!(cedula.es_de_menor());
+        assert_eq!(cedula.base(), "V-12345678");
+        assert_eq!(cedula.canonica(), "V-12345678-01");
+        assert!(!Cedula::parse("V-12345678").unwrap().es_de_menor());
+
+        assert!(Cedula::parse("V-12345678-1").is_err());
+        assert!(Cedula::parse("V-12345678-00").is_err());
+        assert!(Cedula::parse("V-12345678-100").is_err());
+        assert!(Cedula::parse("V-12345678-AB").is_err());
+        // Los pasaportes no admiten sufijo de menor
+        assert!(Cedula::parse("P-AB12345-01").is_err());
+    }
+
+    #[test]
+    fn entradas_rechazadas() {
+        assert!(Cedula::parse("").is_err());
+        assert!(Cedula::parse("   ").is_err());
+        assert!(Cedula::parse("X-12345678").is_err());
+        assert!(Cedula::parse("V-12A45678").is_err());
+        assert!(Cedula::parse("V-000").is_err());
+        assert!(Cedula::parse("V-").is_err());
+        assert!(Cedula::parse("P-AB1").is_err());
+        assert!(Cedula::parse("P-AB12345678901234").is_err());
+        assert!(Cedula::parse("V-12345678-REVISAR-5").is_err());
+    }
+
+    #[test]
+    fn secuencia_derivada_del_representante() {
+        assert_eq!(de_menor("V-12345678", 3), "V-12345678-03");
+        assert_eq!(secuencia_de_menor("V-12345678-03", "V-12345678"), Some(3));
+        assert_eq!(secuencia_de_menor("V-12345678", "V-12345678"), None);
+        assert_eq!(secuencia_de_menor("V-87654321-03", "V-12345678"), None);
+    }
+}
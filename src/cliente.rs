@@ -0,0 +1,48 @@
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRef, FromRequestParts},
+    http::request::Parts,
+};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::errores::ErrorApi;
+
+// Dirección IP del cliente. Solo se confía en X-Forwarded-For cuando el
+// servidor está detrás de un proxy (CONFIAR_PROXY=true).
+pub struct IpCliente(pub IpAddr);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for IpCliente
+where
+    S: Send + Sync,
+    Arc<Config>: FromRef<S>,
+{
+    type Rejection = ErrorApi;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let config = Arc::<Config>::from_ref(state);
+
+        if config.confiar_proxy {
+            let reenviada = parts
+                .headers
+                .get("x-forwarded-for")
+                .and_then(|valor| valor.to_str().ok())
+                .and_then(|valor| valor.split(',').next())
+                .and_then(|ip| ip.trim().parse().ok());
+            if let Some(ip) = reenviada {
+                return Ok(IpCliente(ip));
+            }
+        }
+
+        parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(direccion)| IpCliente(direccion.ip()))
+            .ok_or_else(|| {
+                eprintln!("No se pudo determinar la IP del cliente");
+                ErrorApi::Interno
+            })
+    }
+}
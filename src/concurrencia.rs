@@ -0,0 +1,88 @@
+// Concurrencia optimista. Cada fila editable lleva una columna `version` que
+// un trigger incrementa en cada UPDATE; se expone como ETag (`"3"`) y el
+// cliente la devuelve en `If-Match` para que su edición se rechace con 412
+// si alguien modificó el registro mientras tanto. PUT y PATCH sobre
+// pacientes, citas y horarios exigen la cabecera: sin ella, o con `*`, se
+// responde 428 en lugar de sobrescribir a ciegas.
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderValue},
+    response::{IntoResponse, Json, Response},
+};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::errores::ErrorApi;
+
+// Versión esperada según `If-Match`; `None` si la cabecera falta o es `*`
+pub struct IfMatch(Option<i32>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for IfMatch
+where
+    S: Send + Sync,
+{
+    type Rejection = ErrorApi;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(valor) = parts.headers.get(header::IF_MATCH) else {
+            return Ok(IfMatch(None));
+        };
+
+        let valor = valor.to_str().unwrap_or_default().trim();
+        if valor == "*" {
+            return Ok(IfMatch(None));
+        }
+
+        valor
+            .trim_start_matches("W/")
+            .trim_matches('"')
+            .parse()
+            .map(|version| IfMatch(Some(version)))
+            .map_err(|_| ErrorApi::SolicitudInvalida("La cabecera If-Match no contiene un ETag válido".to_string()))
+    }
+}
+
+impl IfMatch {
+    // Para las ediciones (PUT/PATCH): la versión es obligatoria
+    pub fn verificar(&self, version_actual: i32) -> Result<(), ErrorApi> {
+        if self.0.is_none() {
+            return Err(ErrorApi::PrecondicionRequerida(
+                "Falta la cabecera If-Match con el ETag del registro; cárgalo antes de editarlo".to_string(),
+            ));
+        }
+        self.verificar_si_presente(version_actual)
+    }
+
+    // Para las transiciones de estado de una cita, que ya rechaza la tabla de
+    // transiciones si otro usuario movió la cita antes: la versión es opcional
+    pub fn verificar_si_presente(&self, version_actual: i32) -> Result<(), ErrorApi> {
+        match self.0 {
+            Some(esperada) if esperada != version_actual => Err(ErrorApi::PrecondicionFallida {
+                mensaje: "El registro fue modificado por otro usuario; vuelve a cargarlo antes de guardar".to_string(),
+                detalles: Some(json!({ "etag_actual": etag(version_actual) })),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub fn etag(version: i32) -> String {
+    format!("\"{}\"", version)
+}
+
+// Respuesta JSON con la versión del recurso en la cabecera ETag
+pub struct ConVersion<T>(pub i32, pub T);
+
+impl<T: Serialize> IntoResponse for ConVersion<T> {
+    fn into_response(self) -> Response {
+        let ConVersion(version, cuerpo) = self;
+        let mut respuesta = Json(cuerpo).into_response();
+        if let Ok(valor) = HeaderValue::from_str(&etag(version)) {
+            respuesta.headers_mut().insert(header::ETAG, valor);
+        }
+        respuesta
+    }
+}
@@ -0,0 +1,248 @@
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use std::env;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::auth::{Claims, TipoToken};
+use crate::contrasenas::PoliticaContrasena;
+
+// Secreto usado solo fuera de producción cuando JWT_SECRET no está definido
+const SECRETO_DESARROLLO: &str = "tu_clave_secreta_aqui";
+
+// Configuración de la aplicación, cargada desde variables de entorno
+pub struct Config {
+    pub database_url: String,
+    pub produccion: bool,
+    pub confiar_proxy: bool,
+    pub totp_emisor: String,
+    pub jwt: JwtConfig,
+    pub login: LoginConfig,
+    pub contrasenas: PoliticaContrasena,
+    // Años que se conserva la historia clínica de un paciente archivado
+    // antes de poder purgarla
+    pub retencion_anios: i32,
+    // Duración de una cita, usada para detectar solapamientos
+    pub duracion_cita_minutos: i32,
+    pub recordatorios: RecordatoriosConfig,
+}
+
+pub struct JwtConfig {
+    algoritmo: Algorithm,
+    clave_firma: EncodingKey,
+    clave_verificacion: DecodingKey,
+    pub emisor: String,
+    pub audiencia: String,
+    pub duracion_acceso: u64,
+    pub duracion_refresco: u64,
+    pub duracion_recuperacion: u64,
+    pub duracion_2fa: u64,
+}
+
+// Recordatorios de citas que envía el trabajador en segundo plano
+pub struct RecordatoriosConfig {
+    // Antelaciones en minutos, de mayor a menor
+    pub antelaciones: Vec<i32>,
+    // Segundos entre revisiones de la cola
+    pub intervalo: u64,
+    pub max_intentos: i32,
+    // Espera tras el primer fallo; se duplica en cada reintento
+    pub reintento_base: u64,
+}
+
+// Protección contra fuerza bruta en /login
+pub struct LoginConfig {
+    pub max_intentos_cuenta: i32,
+    pub max_intentos_ip: i32,
+    pub bloqueo_base: u64,
+    pub bloqueo_maximo: u64,
+    pub ventana: u64,
+}
+
+impl Config {
+    pub fn desde_entorno() -> Result<Self, String> {
+        let database_url = env::var("DATABASE_URL")
+            .map_err(|_| "DATABASE_URL debe estar configurado".to_string())?;
+
+        let produccion = matches!(
+            env::var("APP_ENV").unwrap_or_default().to_lowercase().as_str(),
+            "produccion" | "production"
+        );
+
+        let duracion_cita_minutos = leer_entero("CITA_DURACION_MINUTOS", 30)?;
+        if !(5..=480).contains(&duracion_cita_minutos) {
+            return Err("CITA_DURACION_MINUTOS debe estar entre 5 y 480".to_string());
+        }
+
+        Ok(Config {
+            database_url,
+            produccion,
+            confiar_proxy: env::var("CONFIAR_PROXY").is_ok_and(|valor| valor == "true"),
+            totp_emisor: env::var("TOTP_EMISOR").unwrap_or_else(|_| "lab_clic".to_string()),
+            jwt: JwtConfig::desde_entorno(produccion)?,
+            login: LoginConfig {
+                max_intentos_cuenta: leer_entero("LOGIN_MAX_INTENTOS_CUENTA", 5)?,
+                max_intentos_ip: leer_entero("LOGIN_MAX_INTENTOS_IP", 20)?,
+                bloqueo_base: leer_segundos("LOGIN_BLOQUEO_BASE", 60)?,
+                bloqueo_maximo: leer_segundos("LOGIN_BLOQUEO_MAXIMO", 3600)?,
+                ventana: leer_segundos("LOGIN_VENTANA", 3600)?,
+            },
+            contrasenas: PoliticaContrasena::desde_entorno()?,
+            retencion_anios: leer_entero("RETENCION_HISTORIAS_ANIOS", 10)?,
+            duracion_cita_minutos,
+            recordatorios: RecordatoriosConfig {
+                antelaciones: leer_antelaciones("RECORDATORIOS_ANTELACIONES", "24h,2h")?,
+                intervalo: leer_segundos("RECORDATORIOS_INTERVALO", 60)?,
+                max_intentos: leer_entero("RECORDATORIOS_MAX_INTENTOS", 5)?,
+                reintento_base: leer_segundos("RECORDATORIOS_REINTENTO_BASE", 300)?,
+            },
+        })
+    }
+}
+
+impl JwtConfig {
+    fn desde_entorno(produccion: bool) -> Result<Self, String> {
+        let algoritmo = match env::var("JWT_ALGORITMO").unwrap_or_else(|_| "HS256".to_string()).as_str() {
+            "HS256" => Algorithm::HS256,
+            "RS256" => Algorithm::RS256,
+            "EdDSA" => Algorithm::EdDSA,
+            otro => return Err(format!("JWT_ALGORITMO no soportado: {}", otro)),
+        };
+
+        let (clave_firma, clave_verificacion) = match algoritmo {
+            Algorithm::HS256 => {
+                let secreto = match env::var("JWT_SECRET") {
+                    Ok(secreto) if !secreto.is_empty() => secreto,
+                    _ if produccion => {
+                        return Err("JWT_SECRET debe estar configurado en producción".to_string())
+                    }
+                    _ => {
+                        eprintln!("JWT_SECRET no configurado, usando secreto de desarrollo");
+                        SECRETO_DESARROLLO.to_string()
+                    }
+                };
+                (
+                    EncodingKey::from_secret(secreto.as_bytes()),
+                    DecodingKey::from_secret(secreto.as_bytes()),
+                )
+            }
+            _ => {
+                let privada = leer_pem("JWT_CLAVE_PRIVADA")?;
+                let publica = leer_pem("JWT_CLAVE_PUBLICA")?;
+                let claves = if algoritmo == Algorithm::RS256 {
+                    EncodingKey::from_rsa_pem(&privada)
+                        .and_then(|firma| Ok((firma, DecodingKey::from_rsa_pem(&publica)?)))
+                } else {
+                    EncodingKey::from_ed_pem(&privada)
+                        .and_then(|firma| Ok((firma, DecodingKey::from_ed_pem(&publica)?)))
+                };
+                claves.map_err(|e| format!("Clave PEM inválida: {}", e))?
+            }
+        };
+
+        Ok(JwtConfig {
+            algoritmo,
+            clave_firma,
+            clave_verificacion,
+            emisor: env::var("JWT_EMISOR").unwrap_or_else(|_| "lab_clic".to_string()),
+            audiencia: env::var("JWT_AUDIENCIA").unwrap_or_else(|_| "lab_clic".to_string()),
+            duracion_acceso: leer_segundos("JWT_DURACION_ACCESO", 900)?,
+            duracion_refresco: leer_segundos("JWT_DURACION_REFRESCO", 43200)?,
+            duracion_recuperacion: leer_segundos("JWT_DURACION_RECUPERACION", 900)?,
+            duracion_2fa: leer_segundos("JWT_DURACION_2FA", 300)?,
+        })
+    }
+
+    // Construye los claims de un token que expira en `duracion` segundos
+    pub fn claims(
+        &self,
+        tipo: TipoToken,
+        sub: String,
+        rol: String,
+        usuario_id: i32,
+        permisos: Vec<String>,
+        duracion: u64,
+    ) -> Claims {
+        let expiracion = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + duracion;
+
+        Claims {
+            sub,
+            exp: expiracion as usize,
+            rol,
+            usuario_id,
+            iss: self.emisor.clone(),
+            aud: self.audiencia.clone(),
+            tipo,
+            permisos,
+        }
+    }
+
+    pub fn firmar(&self, claims: &Claims) -> Result<String, jsonwebtoken::errors::Error> {
+        encode(&Header::new(self.algoritmo), claims, &self.clave_firma)
+    }
+
+    // Decodifica un token validando firma, expiración, emisor y audiencia
+    pub fn verificar(&self, token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
+        let mut validacion = Validation::new(self.algoritmo);
+        validacion.set_issuer(&[&self.emisor]);
+        validacion.set_audience(&[&self.audiencia]);
+
+        decode::<Claims>(token, &self.clave_verificacion, &validacion).map(|datos| datos.claims)
+    }
+}
+
+fn leer_pem(variable: &str) -> Result<Vec<u8>, String> {
+    let ruta = env::var(variable).map_err(|_| format!("{} debe estar configurado", variable))?;
+    fs::read(&ruta).map_err(|e| format!("No se pudo leer {} ({}): {}", variable, ruta, e))
+}
+
+fn leer_segundos(variable: &str, por_defecto: u64) -> Result<u64, String> {
+    match env::var(variable) {
+        Ok(valor) => valor
+            .parse()
+            .map_err(|_| format!("{} debe ser un número de segundos", variable)),
+        Err(_) => Ok(por_defecto),
+    }
+}
+
+fn leer_entero(variable: &str, por_defecto: i32) -> Result<i32, String> {
+    match env::var(variable) {
+        Ok(valor) => valor
+            .parse()
+            .map_err(|_| format!("{} debe ser un número entero", variable)),
+        Err(_) => Ok(por_defecto),
+    }
+}
+
+// Lista como "24h,2h,30m" o "1d" convertida a minutos, de mayor a menor
+fn leer_antelaciones(variable: &str, por_defecto: &str) -> Result<Vec<i32>, String> {
+    let valor = env::var(variable).unwrap_or_else(|_| por_defecto.to_string());
+
+    let mut antelaciones = valor
+        .split(',')
+        .map(str::trim)
+        .filter(|parte| !parte.is_empty())
+        .map(|parte| {
+            let (numero, factor) = match parte.char_indices().last() {
+                Some((i, 'd')) => (&parte[..i], 1440),
+                Some((i, 'h')) => (&parte[..i], 60),
+                Some((i, 'm')) => (&parte[..i], 1),
+                _ => (parte, 1),
+            };
+            numero
+                .trim()
+                .parse::<i32>()
+                .ok()
+                .filter(|n| *n > 0)
+                .and_then(|n| n.checked_mul(factor))
+                .ok_or_else(|| format!("{} tiene una antelación inválida: {}", variable, parte))
+        })
+        .collect::<Result<Vec<i32>, String>>()?;
+
+    antelaciones.sort_unstable_by(|a, b| b.cmp(a));
+    antelaciones.dedup();
+    Ok(antelaciones)
+}
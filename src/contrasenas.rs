@@ -0,0 +1,96 @@
+use std::env;
+
+// Lista de contraseñas comunes rechazadas, una por línea y en minúsculas
+const CONTRASENAS_COMUNES: &str = include_str!("contrasenas_comunes.txt");
+
+// bcrypt ignora todo lo que pase de 72 bytes
+const MAXIMO_BYTES_BCRYPT: usize = 72;
+
+// Política de contraseñas y costo de bcrypt, configurables por entorno
+pub struct PoliticaContrasena {
+    pub longitud_minima: usize,
+    pub requiere_mayuscula: bool,
+    pub requiere_minuscula: bool,
+    pub requiere_digito: bool,
+    pub requiere_simbolo: bool,
+    pub costo_bcrypt: u32,
+}
+
+impl PoliticaContrasena {
+    pub fn desde_entorno() -> Result<Self, String> {
+        let costo_bcrypt = leer("PASSWORD_COSTO_BCRYPT", bcrypt::DEFAULT_COST)?;
+        if !(4..=31).contains(&costo_bcrypt) {
+            return Err("PASSWORD_COSTO_BCRYPT debe estar entre 4 y 31".to_string());
+        }
+
+        Ok(PoliticaContrasena {
+            longitud_minima: leer("PASSWORD_LONGITUD_MINIMA", 10)?,
+            requiere_mayuscula: leer("PASSWORD_REQUIERE_MAYUSCULA", true)?,
+            requiere_minuscula: leer("PASSWORD_REQUIERE_MINUSCULA", true)?,
+            requiere_digito: leer("PASSWORD_REQUIERE_DIGITO", true)?,
+            requiere_simbolo: leer("PASSWORD_REQUIERE_SIMBOLO", false)?,
+            costo_bcrypt,
+        })
+    }
+
+    // Devuelve la lista de reglas incumplidas; vacía si la contraseña es válida.
+    // `datos_personales` son valores del usuario (email, nombre...) que no
+    // pueden usarse como contraseña.
+    pub fn validar(&self, contrasena: &str, datos_personales: &[&str]) -> Vec<String> {
+        let mut errores = Vec::new();
+
+        if contrasena.chars().count() < self.longitud_minima {
+            errores.push(format!(
+                "La contraseña debe tener al menos {} caracteres",
+                self.longitud_minima
+            ));
+        }
+        if contrasena.len() > MAXIMO_BYTES_BCRYPT {
+            errores.push(format!(
+                "La contraseña no puede superar {} bytes",
+                MAXIMO_BYTES_BCRYPT
+            ));
+        }
+        if self.requiere_mayuscula && !contrasena.chars().any(char::is_uppercase) {
+            errores.push("La contraseña debe incluir al menos una letra mayúscula".to_string());
+        }
+        if self.requiere_minuscula && !contrasena.chars().any(char::is_lowercase) {
+            errores.push("La contraseña debe incluir al menos una letra minúscula".to_string());
+        }
+        if self.requiere_digito && !contrasena.chars().any(|c| c.is_ascii_digit()) {
+            errores.push("La contraseña debe incluir al menos un dígito".to_string());
+        }
+        if self.requiere_simbolo && contrasena.chars().all(char::is_alphanumeric) {
+            errores.push("La contraseña debe incluir al menos un símbolo".to_string());
+        }
+
+        let normalizada = contrasena.trim().to_lowercase();
+        let coincide_con_datos = datos_personales.iter().any(|dato| {
+            let dato = dato.trim().to_lowercase();
+            let local = dato.split('@').next().unwrap_or_default();
+            !dato.is_empty() && (normalizada == dato || normalizada == local)
+        });
+        if coincide_con_datos {
+            errores.push("La contraseña no puede ser igual a tu email ni a tu nombre".to_string());
+        }
+
+        if CONTRASENAS_COMUNES.lines().any(|comun| comun == normalizada) {
+            errores.push("La contraseña es demasiado común".to_string());
+        }
+
+        errores
+    }
+
+    pub fn hash(&self, contrasena: &str) -> Result<String, bcrypt::BcryptError> {
+        bcrypt::hash(contrasena, self.costo_bcrypt)
+    }
+}
+
+fn leer<T: std::str::FromStr>(variable: &str, por_defecto: T) -> Result<T, String> {
+    match env::var(variable) {
+        Ok(valor) => valor
+            .parse()
+            .map_err(|_| format!("{} tiene un valor inválido: {}", variable, valor)),
+        Err(_) => Ok(por_defecto),
+    }
+}
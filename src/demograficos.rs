@@ -0,0 +1,164 @@
+// Datos complementarios del paciente: grupo sanguíneo, alergias, dirección,
+// contacto de emergencia y seguro. Todos son opcionales y se guardan en
+// columnas propias de `pacientes`, en este orden:
+//
+// grupo_sanguineo, alergias, direccion_estado, direccion_municipio,
+// direccion_parroquia, direccion_detalle, contacto_emergencia_nombre,
+// contacto_emergencia_telefono, contacto_emergencia_parentesco,
+// aseguradora, numero_poliza
+
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::{PgArguments, PgRow}, query::Query, Postgres, Row};
+
+use crate::errores::ErrorCampo;
+use crate::validacion::{Errores, Validar};
+
+const GRUPOS_SANGUINEOS: [&str; 8] = ["A+", "A-", "B+", "B-", "AB+", "AB-", "O+", "O-"];
+
+const MAX_ALERGIAS: usize = 50;
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct DatosComplementarios {
+    // Grupo ABO con factor Rh, p. ej. "O+"
+    grupo_sanguineo: Option<String>,
+    #[serde(default)]
+    alergias: Vec<String>,
+    direccion: Option<Direccion>,
+    contacto_emergencia: Option<ContactoEmergencia>,
+    seguro: Option<Seguro>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Direccion {
+    estado: String,
+    municipio: String,
+    parroquia: String,
+    // Calle, casa, punto de referencia
+    detalle: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ContactoEmergencia {
+    nombre: String,
+    telefono: String,
+    parentesco: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Seguro {
+    aseguradora: String,
+    numero_poliza: String,
+}
+
+impl DatosComplementarios {
+    pub fn desde_fila(row: &PgRow) -> Self {
+        let direccion = match (
+            row.get::<Option<String>, _>("direccion_estado"),
+            row.get::<Option<String>, _>("direccion_municipio"),
+            row.get::<Option<String>, _>("direccion_parroquia"),
+        ) {
+            (Some(estado), Some(municipio), Some(parroquia)) => Some(Direccion {
+                estado,
+                municipio,
+                parroquia,
+                detalle: row.get("direccion_detalle"),
+            }),
+            _ => None,
+        };
+
+        let contacto_emergencia = match (
+            row.get::<Option<String>, _>("contacto_emergencia_nombre"),
+            row.get::<Option<String>, _>("contacto_emergencia_telefono"),
+        ) {
+            (Some(nombre), Some(telefono)) => Some(ContactoEmergencia {
+                nombre,
+                telefono,
+                parentesco: row.get("contacto_emergencia_parentesco"),
+            }),
+            _ => None,
+        };
+
+        let seguro = match (
+            row.get::<Option<String>, _>("aseguradora"),
+            row.get::<Option<String>, _>("numero_poliza"),
+        ) {
+            (Some(aseguradora), Some(numero_poliza)) => Some(Seguro {
+                aseguradora,
+                numero_poliza,
+            }),
+            _ => None,
+        };
+
+        DatosComplementarios {
+            grupo_sanguineo: row.get("grupo_sanguineo"),
+            alergias: row.get("alergias"),
+            direccion,
+            contacto_emergencia,
+            seguro,
+        }
+    }
+
+    // Agrega los once valores en el orden de las columnas
+    pub fn vincular<'q>(&'q self, consulta: Query<'q, Postgres, PgArguments>) -> Query<'q, Postgres, PgArguments> {
+        let direccion = self.direccion.as_ref();
+        let contacto = self.contacto_emergencia.as_ref();
+        let seguro = self.seguro.as_ref();
+
+        let alergias: Vec<&str> = self.alergias.iter().map(|alergia| alergia.trim()).collect();
+
+        consulta
+            .bind(self.grupo_sanguineo.as_deref())
+            .bind(alergias)
+            .bind(direccion.map(|d| d.estado.trim()))
+            .bind(direccion.map(|d| d.municipio.trim()))
+            .bind(direccion.map(|d| d.parroquia.trim()))
+            .bind(direccion.and_then(|d| d.detalle.as_deref()))
+            .bind(contacto.map(|c| c.nombre.trim()))
+            .bind(contacto.map(|c| c.telefono.trim()))
+            .bind(contacto.and_then(|c| c.parentesco.as_deref()))
+            .bind(seguro.map(|s| s.aseguradora.trim()))
+            .bind(seguro.map(|s| s.numero_poliza.trim()))
+    }
+}
+
+impl Validar for DatosComplementarios {
+    fn validar(&self) -> Vec<ErrorCampo> {
+        let mut errores = Errores::default();
+
+        if let Some(grupo) = &self.grupo_sanguineo {
+            if !GRUPOS_SANGUINEOS.contains(&grupo.as_str()) {
+                errores.agregar(
+                    "grupo_sanguineo",
+                    format!("El grupo sanguíneo debe ser uno de: {}", GRUPOS_SANGUINEOS.join(", ")),
+                );
+            }
+        }
+
+        if self.alergias.len() > MAX_ALERGIAS {
+            errores.agregar("alergias", format!("No se pueden registrar más de {} alergias", MAX_ALERGIAS));
+        }
+        for (i, alergia) in self.alergias.iter().enumerate() {
+            errores.requerido(&format!("alergias[{}]", i), alergia, 100);
+        }
+
+        if let Some(direccion) = &self.direccion {
+            errores.requerido("direccion.estado", &direccion.estado, 100);
+            errores.requerido("direccion.municipio", &direccion.municipio, 100);
+            errores.requerido("direccion.parroquia", &direccion.parroquia, 100);
+            errores.opcional("direccion.detalle", direccion.detalle.as_deref(), 255);
+        }
+
+        if let Some(contacto) = &self.contacto_emergencia {
+            errores.requerido("contacto_emergencia.nombre", &contacto.nombre, 200);
+            errores.telefono("contacto_emergencia.telefono", &contacto.telefono);
+            errores.opcional("contacto_emergencia.parentesco", contacto.parentesco.as_deref(), 50);
+        }
+
+        if let Some(seguro) = &self.seguro {
+            errores.requerido("seguro.aseguradora", &seguro.aseguradora, 100);
+            errores.requerido("seguro.numero_poliza", &seguro.numero_poliza, 50);
+        }
+
+        errores.terminar()
+    }
+}
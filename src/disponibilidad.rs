@@ -0,0 +1,371 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Json,
+};
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Row};
+use std::sync::Arc;
+
+use crate::agenda::{Agenda, Hueco};
+use crate::config::Config;
+use crate::errores::{ErrorApi, ErrorCampo};
+use crate::validacion::{Errores, ValidatedJson, Validar};
+
+// Rango máximo que se expande en huecos de una sola consulta
+const MAX_DIAS_CONSULTA: i64 = 62;
+// Rango por defecto al buscar el primer hueco sin `hasta`
+const DIAS_BUSQUEDA_DEFECTO: i64 = 30;
+
+#[derive(Deserialize)]
+pub struct ConsultaDisponibilidad {
+    desde: Option<NaiveDate>,
+    hasta: Option<NaiveDate>,
+    // Minutos por hueco; por defecto la duración configurada de una cita
+    duracion: Option<i32>,
+}
+
+#[derive(Deserialize)]
+pub struct ConsultaPrimerHueco {
+    especialidad: String,
+    desde: Option<NaiveDate>,
+    hasta: Option<NaiveDate>,
+    duracion: Option<i32>,
+}
+
+#[derive(Serialize)]
+pub struct Disponibilidad {
+    usuario_id: i32,
+    desde: NaiveDate,
+    hasta: NaiveDate,
+    duracion_minutos: i32,
+    huecos: Vec<Hueco>,
+}
+
+#[derive(Serialize)]
+pub struct PrimerHueco {
+    usuario_id: i32,
+    nombre: String,
+    apellido: String,
+    especialidad: String,
+    inicio: NaiveDateTime,
+    fin: NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Feriado {
+    fecha: NaiveDate,
+    descripcion: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PeriodoBloqueado {
+    id: Option<i32>,
+    #[serde(default)]
+    usuario_id: i32,
+    desde: NaiveDateTime,
+    hasta: NaiveDateTime,
+    motivo: Option<String>,
+}
+
+impl Validar for Feriado {
+    fn validar(&self) -> Vec<ErrorCampo> {
+        let mut errores = Errores::default();
+        errores.requerido("descripcion", &self.descripcion, 200);
+        errores.terminar()
+    }
+}
+
+impl Validar for PeriodoBloqueado {
+    fn validar(&self) -> Vec<ErrorCampo> {
+        let mut errores = Errores::default();
+        if self.hasta <= self.desde {
+            errores.agregar("hasta", "El fin del periodo debe ser posterior a su inicio");
+        }
+        errores.opcional("motivo", self.motivo.as_deref(), 500);
+        errores.terminar()
+    }
+}
+
+// Rango y duración de una consulta, con sus valores por defecto
+fn rango(
+    desde: Option<NaiveDate>,
+    hasta: Option<NaiveDate>,
+    duracion: Option<i32>,
+    dias_defecto: i64,
+    config: &Config,
+) -> Result<(NaiveDate, NaiveDate, i32), ErrorApi> {
+    let mut errores = Errores::default();
+
+    let desde = desde.unwrap_or_else(|| Local::now().date_naive());
+    let hasta = hasta.unwrap_or(desde + Duration::days(dias_defecto - 1));
+    if hasta < desde {
+        errores.agregar("hasta", "La fecha final no puede ser anterior a la inicial");
+    } else if (hasta - desde).num_days() >= MAX_DIAS_CONSULTA {
+        errores.agregar("hasta", format!("El rango no puede superar {} días", MAX_DIAS_CONSULTA));
+    }
+
+    let duracion = duracion.unwrap_or(config.duracion_cita_minutos);
+    if !(5..=480).contains(&duracion) {
+        errores.agregar("duracion", "La duración debe estar entre 5 y 480 minutos");
+    }
+
+    let errores = errores.terminar();
+    if !errores.is_empty() {
+        return Err(ErrorApi::Validacion(errores));
+    }
+
+    Ok((desde, hasta, duracion))
+}
+
+// GET /medicos/:usuario_id/disponibilidad
+pub async fn get_disponibilidad(
+    Path(usuario_id): Path<i32>,
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Query(consulta): Query<ConsultaDisponibilidad>,
+) -> Result<Json<Disponibilidad>, ErrorApi> {
+    let (desde, hasta, duracion) = rango(
+        consulta.desde,
+        consulta.hasta,
+        consulta.duracion,
+        DIAS_BUSQUEDA_DEFECTO,
+        &config,
+    )?;
+
+    let existe = sqlx::query("SELECT 1 FROM usuarios WHERE id = $1 AND activo")
+        .bind(usuario_id)
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al obtener médico: {}", e);
+            ErrorApi::from(e)
+        })?;
+    if existe.is_none() {
+        return Err(ErrorApi::no_encontrado("Médico"));
+    }
+
+    let agenda = Agenda::cargar(&pool, usuario_id, desde, hasta, config.duracion_cita_minutos).await?;
+
+    Ok(Json(Disponibilidad {
+        usuario_id,
+        desde,
+        hasta,
+        duracion_minutos: duracion,
+        huecos: agenda.huecos(desde, hasta, duracion, Local::now().naive_local()),
+    }))
+}
+
+// GET /medicos/primer_hueco?especialidad=
+// Primer hueco libre entre todos los médicos activos de la especialidad
+pub async fn get_primer_hueco(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Query(consulta): Query<ConsultaPrimerHueco>,
+) -> Result<Json<PrimerHueco>, ErrorApi> {
+    let (desde, hasta, duracion) = rango(
+        consulta.desde,
+        consulta.hasta,
+        consulta.duracion,
+        DIAS_BUSQUEDA_DEFECTO,
+        &config,
+    )?;
+
+    let medicos = sqlx::query(
+        "SELECT id, nombre, apellido, especialidad FROM usuarios WHERE activo AND lower(sin_acentos(trim(especialidad))) = lower(sin_acentos(trim($1))) ORDER BY id"
+    )
+    .bind(&consulta.especialidad)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al obtener médicos por especialidad: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    if medicos.is_empty() {
+        return Err(ErrorApi::NoEncontrado(
+            "No hay médicos activos con esa especialidad".to_string(),
+        ));
+    }
+
+    let ahora = Local::now().naive_local();
+    let mut mejor: Option<PrimerHueco> = None;
+
+    for medico in &medicos {
+        let usuario_id: i32 = medico.get("id");
+        let agenda = Agenda::cargar(&pool, usuario_id, desde, hasta, config.duracion_cita_minutos).await?;
+
+        let Some(hueco) = agenda.huecos(desde, hasta, duracion, ahora).into_iter().next() else {
+            continue;
+        };
+        if mejor.as_ref().is_some_and(|actual| actual.inicio <= hueco.inicio) {
+            continue;
+        }
+
+        mejor = Some(PrimerHueco {
+            usuario_id,
+            nombre: medico.get("nombre"),
+            apellido: medico.get("apellido"),
+            especialidad: medico.get("especialidad"),
+            inicio: hueco.inicio,
+            fin: hueco.fin,
+        });
+    }
+
+    mejor.map(Json).ok_or_else(|| {
+        ErrorApi::NoEncontrado("No hay huecos libres para esa especialidad en el rango indicado".to_string())
+    })
+}
+
+#[derive(Deserialize)]
+pub struct FiltroFeriados {
+    desde: Option<NaiveDate>,
+    hasta: Option<NaiveDate>,
+}
+
+// GET /feriados
+pub async fn get_feriados(
+    State(pool): State<PgPool>,
+    Query(filtro): Query<FiltroFeriados>,
+) -> Result<Json<Vec<Feriado>>, ErrorApi> {
+    let rows = sqlx::query(
+        "SELECT fecha, descripcion FROM feriados WHERE ($1::DATE IS NULL OR fecha >= $1) AND ($2::DATE IS NULL OR fecha <= $2) ORDER BY fecha"
+    )
+    .bind(filtro.desde)
+    .bind(filtro.hasta)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al obtener feriados: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    let feriados = rows
+        .into_iter()
+        .map(|row| Feriado {
+            fecha: row.get("fecha"),
+            descripcion: row.get("descripcion"),
+        })
+        .collect();
+
+    Ok(Json(feriados))
+}
+
+// POST /feriados
+pub async fn create_feriado(
+    State(pool): State<PgPool>,
+    ValidatedJson(feriado): ValidatedJson<Feriado>,
+) -> Result<Json<Feriado>, ErrorApi> {
+    sqlx::query("INSERT INTO feriados (fecha, descripcion) VALUES ($1, $2)")
+        .bind(feriado.fecha)
+        .bind(feriado.descripcion.trim())
+        .execute(&pool)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al crear feriado: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    Ok(Json(feriado))
+}
+
+// DELETE /feriados/:fecha
+pub async fn delete_feriado(
+    Path(fecha): Path<NaiveDate>,
+    State(pool): State<PgPool>,
+) -> Result<StatusCode, ErrorApi> {
+    let resultado = sqlx::query("DELETE FROM feriados WHERE fecha = $1")
+        .bind(fecha)
+        .execute(&pool)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al eliminar feriado: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    if resultado.rows_affected() == 0 {
+        return Err(ErrorApi::no_encontrado("Feriado"));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// GET /medicos/:usuario_id/bloqueos
+pub async fn get_bloqueos(
+    Path(usuario_id): Path<i32>,
+    State(pool): State<PgPool>,
+) -> Result<Json<Vec<PeriodoBloqueado>>, ErrorApi> {
+    let rows = sqlx::query(
+        "SELECT id, usuario_id, desde, hasta, motivo FROM periodos_bloqueados WHERE usuario_id = $1 ORDER BY desde"
+    )
+    .bind(usuario_id)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al obtener periodos bloqueados: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    let bloqueos = rows
+        .into_iter()
+        .map(|row| PeriodoBloqueado {
+            id: row.get("id"),
+            usuario_id: row.get("usuario_id"),
+            desde: row.get("desde"),
+            hasta: row.get("hasta"),
+            motivo: row.get("motivo"),
+        })
+        .collect();
+
+    Ok(Json(bloqueos))
+}
+
+// POST /medicos/:usuario_id/bloqueos
+pub async fn create_bloqueo(
+    Path(usuario_id): Path<i32>,
+    State(pool): State<PgPool>,
+    ValidatedJson(bloqueo): ValidatedJson<PeriodoBloqueado>,
+) -> Result<Json<PeriodoBloqueado>, ErrorApi> {
+    let row = sqlx::query(
+        "INSERT INTO periodos_bloqueados (usuario_id, desde, hasta, motivo) VALUES ($1, $2, $3, $4) RETURNING id"
+    )
+    .bind(usuario_id)
+    .bind(bloqueo.desde)
+    .bind(bloqueo.hasta)
+    .bind(&bloqueo.motivo)
+    .fetch_one(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al crear periodo bloqueado: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    Ok(Json(PeriodoBloqueado {
+        id: row.get("id"),
+        usuario_id,
+        ..bloqueo
+    }))
+}
+
+// DELETE /medicos/:usuario_id/bloqueos/:id
+pub async fn delete_bloqueo(
+    Path((usuario_id, id)): Path<(i32, i32)>,
+    State(pool): State<PgPool>,
+) -> Result<StatusCode, ErrorApi> {
+    let resultado = sqlx::query("DELETE FROM periodos_bloqueados WHERE id = $1 AND usuario_id = $2")
+        .bind(id)
+        .bind(usuario_id)
+        .execute(&pool)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al eliminar periodo bloqueado: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    if resultado.rows_affected() == 0 {
+        return Err(ErrorApi::no_encontrado("Periodo bloqueado"));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
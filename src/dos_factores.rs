@@ -0,0 +1,548 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+};
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sqlx::{PgPool, Row};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::auth::{hash_token, AuthUser, TipoToken};
+use crate::bloqueos;
+use crate::config::Config;
+use crate::errores::ErrorApi;
+use crate::{confirmar_transaccion, iniciar_transaccion, LoginResponse};
+
+const PERIODO_TOTP: u64 = 30;
+const CANTIDAD_CODIGOS_RECUPERACION: usize = 10;
+
+#[derive(Serialize, Deserialize)]
+pub struct ConfiguracionTotpResponse {
+    secreto: String,
+    otpauth_uri: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CodigoRequest {
+    codigo: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CodigosRecuperacionResponse {
+    codigos_recuperacion: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Login2faRequest {
+    token_2fa: String,
+    codigo: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Requiere2faRequest {
+    requerido: bool,
+}
+
+// --- TOTP (RFC 6238, HMAC-SHA1, 6 dígitos, periodos de 30 s) ---
+
+fn base32(bytes: &[u8]) -> String {
+    const ALFABETO: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+    let mut salida = String::new();
+    for bloque in bytes.chunks(5) {
+        let mut buffer = [0u8; 5];
+        buffer[..bloque.len()].copy_from_slice(bloque);
+        let valor = buffer.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64);
+        let caracteres = (bloque.len() * 8).div_ceil(5);
+        for i in 0..caracteres {
+            let indice = (valor >> (35 - i * 5)) & 0x1f;
+            salida.push(ALFABETO[indice as usize] as char);
+        }
+    }
+    salida
+}
+
+fn base32_decodificar(texto: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut buffer: u64 = 0;
+    let mut bits = 0;
+    for c in texto.trim_end_matches('=').chars() {
+        let valor = match c.to_ascii_uppercase() {
+            c @ 'A'..='Z' => c as u64 - 'A' as u64,
+            c @ '2'..='7' => c as u64 - '2' as u64 + 26,
+            _ => return None,
+        };
+        buffer = (buffer << 5) | valor;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+fn codigo_totp(secreto: &[u8], paso: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secreto).expect("HMAC acepta claves de cualquier tamaño");
+    mac.update(&paso.to_be_bytes());
+    let resultado = mac.finalize().into_bytes();
+
+    let desplazamiento = (resultado[19] & 0x0f) as usize;
+    let binario = u32::from_be_bytes([
+        resultado[desplazamiento] & 0x7f,
+        resultado[desplazamiento + 1],
+        resultado[desplazamiento + 2],
+        resultado[desplazamiento + 3],
+    ]);
+    binario % 1_000_000
+}
+
+// Devuelve el paso en que el código es válido, tolerando un periodo de
+// desfase de reloj en cada dirección
+fn verificar_totp(secreto_base32: &str, codigo: &str) -> Option<i64> {
+    let ahora = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() / PERIODO_TOTP;
+    verificar_totp_en(secreto_base32, codigo, ahora)
+}
+
+fn verificar_totp_en(secreto_base32: &str, codigo: &str, ahora: u64) -> Option<i64> {
+    let secreto = base32_decodificar(secreto_base32)?;
+    let codigo: u32 = codigo.trim().parse().ok()?;
+
+    [ahora - 1, ahora, ahora + 1]
+        .into_iter()
+        .find(|paso| codigo_totp(&secreto, *paso) == codigo)
+        .map(|paso| paso as i64)
+}
+
+fn generar_codigos_recuperacion() -> Vec<String> {
+    const ALFABETO: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+    let mut rng = rand::thread_rng();
+    (0..CANTIDAD_CODIGOS_RECUPERACION)
+        .map(|_| {
+            let codigo: String = (0..8)
+                .map(|_| ALFABETO[rng.gen_range(0..ALFABETO.len())] as char)
+                .collect();
+            format!("{}-{}", &codigo[..4], &codigo[4..])
+        })
+        .collect()
+}
+
+fn normalizar_codigo_recuperacion(codigo: &str) -> String {
+    codigo
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+// Verifica un código TOTP o, si no lo es, un código de recuperación no usado.
+// Los códigos TOTP ya usados (mismo paso o anterior) se rechazan.
+async fn verificar_codigo(pool: &PgPool, usuario_id: i32, codigo: &str) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query("SELECT totp_secreto FROM usuarios WHERE id = $1")
+        .bind(usuario_id)
+        .fetch_one(pool)
+        .await?;
+
+    let secreto: Option<String> = row.get("totp_secreto");
+
+    if let Some(paso) = secreto.as_deref().and_then(|secreto| verificar_totp(secreto, codigo)) {
+        // Comprobar y consumir el paso en una sola sentencia: de dos
+        // solicitudes simultáneas con el mismo código solo una lo acepta
+        let resultado = sqlx::query(
+            "UPDATE usuarios SET totp_ultimo_paso = $1 WHERE id = $2 AND (totp_ultimo_paso IS NULL OR totp_ultimo_paso < $1)"
+        )
+        .bind(paso)
+        .bind(usuario_id)
+        .execute(pool)
+        .await?;
+        return Ok(resultado.rows_affected() == 1);
+    }
+
+    let resultado = sqlx::query(
+        "UPDATE codigos_recuperacion_2fa SET usado_en = NOW() WHERE usuario_id = $1 AND codigo_hash = $2 AND usado_en IS NULL"
+    )
+    .bind(usuario_id)
+    .bind(hash_token(&normalizar_codigo_recuperacion(codigo)))
+    .execute(pool)
+    .await?;
+
+    Ok(resultado.rows_affected() > 0)
+}
+
+fn codigo_incorrecto() -> ErrorApi {
+    ErrorApi::NoAutenticado("Código de verificación incorrecto".to_string())
+}
+
+fn token_2fa_invalido() -> ErrorApi {
+    ErrorApi::NoAutenticado("Token de verificación inválido o expirado".to_string())
+}
+
+// --- RUTAS ---
+
+// POST /usuarios/me/2fa/setup
+pub async fn setup_2fa(
+    usuario: AuthUser,
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+) -> Result<Json<ConfiguracionTotpResponse>, ErrorApi> {
+    let row = sqlx::query("SELECT email, totp_activo FROM usuarios WHERE id = $1")
+        .bind(usuario.usuario_id)
+        .fetch_one(&pool)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al obtener usuario: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    if row.get::<bool, _>("totp_activo") {
+        return Err(ErrorApi::EstadoInvalido {
+            mensaje: "La verificación en dos pasos ya está activa".to_string(),
+            detalles: None,
+        });
+    }
+
+    let email: String = row.get("email");
+    let mut bytes = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let secreto = base32(&bytes);
+
+    // El secreto queda pendiente hasta que se confirme con un código válido
+    sqlx::query("UPDATE usuarios SET totp_secreto = $1, totp_ultimo_paso = NULL WHERE id = $2")
+        .bind(&secreto)
+        .bind(usuario.usuario_id)
+        .execute(&pool)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al guardar secreto TOTP: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    let emisor = utf8_percent_encode(&config.totp_emisor, NON_ALPHANUMERIC).to_string();
+    let otpauth_uri = format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits=6&period={}",
+        emisor,
+        utf8_percent_encode(&email, NON_ALPHANUMERIC),
+        secreto,
+        emisor,
+        PERIODO_TOTP
+    );
+
+    Ok(Json(ConfiguracionTotpResponse {
+        secreto,
+        otpauth_uri,
+    }))
+}
+
+// POST /usuarios/me/2fa/confirm
+pub async fn confirmar_2fa(
+    usuario: AuthUser,
+    State(pool): State<PgPool>,
+    Json(request): Json<CodigoRequest>,
+) -> Result<Json<CodigosRecuperacionResponse>, ErrorApi> {
+    let row = sqlx::query("SELECT totp_secreto, totp_activo FROM usuarios WHERE id = $1")
+        .bind(usuario.usuario_id)
+        .fetch_one(&pool)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al obtener usuario: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    if row.get::<bool, _>("totp_activo") {
+        return Err(ErrorApi::EstadoInvalido {
+            mensaje: "La verificación en dos pasos ya está activa".to_string(),
+            detalles: None,
+        });
+    }
+
+    let secreto: Option<String> = row.get("totp_secreto");
+    let paso = secreto
+        .as_deref()
+        .and_then(|secreto| verificar_totp(secreto, &request.codigo))
+        .ok_or_else(codigo_incorrecto)?;
+
+    let codigos = generar_codigos_recuperacion();
+
+    let mut tx = iniciar_transaccion(&pool).await?;
+
+    sqlx::query("UPDATE usuarios SET totp_activo = TRUE, totp_ultimo_paso = $1 WHERE id = $2")
+        .bind(paso)
+        .bind(usuario.usuario_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al activar 2FA: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    sqlx::query("DELETE FROM codigos_recuperacion_2fa WHERE usuario_id = $1")
+        .bind(usuario.usuario_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al eliminar códigos de recuperación: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    for codigo in &codigos {
+        sqlx::query("INSERT INTO codigos_recuperacion_2fa (usuario_id, codigo_hash) VALUES ($1, $2)")
+            .bind(usuario.usuario_id)
+            .bind(hash_token(&normalizar_codigo_recuperacion(codigo)))
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| {
+                eprintln!("Error al guardar código de recuperación: {}", e);
+                ErrorApi::from(e)
+            })?;
+    }
+
+    confirmar_transaccion(tx).await?;
+
+    Ok(Json(CodigosRecuperacionResponse {
+        codigos_recuperacion: codigos,
+    }))
+}
+
+// POST /usuarios/me/2fa/disable
+pub async fn desactivar_2fa(
+    usuario: AuthUser,
+    State(pool): State<PgPool>,
+    Json(request): Json<CodigoRequest>,
+) -> Result<StatusCode, ErrorApi> {
+    let row = sqlx::query(
+        "SELECT u.totp_activo, r.requiere_2fa FROM usuarios u JOIN roles r ON u.rol_id = r.id WHERE u.id = $1"
+    )
+    .bind(usuario.usuario_id)
+    .fetch_one(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al obtener usuario: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    if !row.get::<bool, _>("totp_activo") {
+        return Err(ErrorApi::EstadoInvalido {
+            mensaje: "La verificación en dos pasos no está activa".to_string(),
+            detalles: None,
+        });
+    }
+
+    // El rol del usuario exige 2FA: no puede desactivarlo
+    if row.get::<bool, _>("requiere_2fa") {
+        return Err(ErrorApi::Prohibido(
+            "El rol del usuario exige verificación en dos pasos".to_string(),
+        ));
+    }
+
+    let valido = verificar_codigo(&pool, usuario.usuario_id, &request.codigo)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al verificar código 2FA: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    if !valido {
+        return Err(codigo_incorrecto());
+    }
+
+    let mut tx = iniciar_transaccion(&pool).await?;
+
+    sqlx::query("UPDATE usuarios SET totp_activo = FALSE, totp_secreto = NULL, totp_ultimo_paso = NULL WHERE id = $1")
+        .bind(usuario.usuario_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al desactivar 2FA: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    sqlx::query("DELETE FROM codigos_recuperacion_2fa WHERE usuario_id = $1")
+        .bind(usuario.usuario_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al eliminar códigos de recuperación: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    confirmar_transaccion(tx).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// POST /login/2fa
+pub async fn login_2fa(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Json(request): Json<Login2faRequest>,
+) -> Result<Json<LoginResponse>, ErrorApi> {
+    let claims = config.jwt.verificar(&request.token_2fa).map_err(|e| {
+        eprintln!("Token 2FA inválido o expirado: {}", e);
+        token_2fa_invalido()
+    })?;
+
+    if claims.tipo != TipoToken::Pendiente2fa {
+        return Err(token_2fa_invalido());
+    }
+
+    let clave_cuenta = bloqueos::clave_cuenta(&claims.sub);
+    let bloqueado = bloqueos::esta_bloqueado(&pool, std::slice::from_ref(&clave_cuenta))
+        .await
+        .map_err(|e| {
+            eprintln!("Error al consultar bloqueos: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    if bloqueado {
+        return Err(ErrorApi::DemasiadosIntentos(
+            "Demasiados intentos fallidos, intenta más tarde".to_string(),
+        ));
+    }
+
+    let valido = verificar_codigo(&pool, claims.usuario_id, &request.codigo)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al verificar código 2FA: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    if !valido {
+        eprintln!("Código 2FA incorrecto para usuario {}", claims.usuario_id);
+        bloqueos::registrar_fallo(&pool, &clave_cuenta, config.login.max_intentos_cuenta, &config.login)
+            .await
+            .map_err(|e| {
+                eprintln!("Error al registrar intento fallido: {}", e);
+                ErrorApi::from(e)
+            })?;
+        return Err(codigo_incorrecto());
+    }
+
+    bloqueos::limpiar(&pool, &clave_cuenta).await.map_err(|e| {
+        eprintln!("Error al limpiar intentos fallidos: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    let row = sqlx::query(
+        "SELECT u.email, u.nombre, u.apellido, u.activo, r.nombre AS rol_nombre FROM usuarios u JOIN roles r ON u.rol_id = r.id WHERE u.id = $1"
+    )
+    .bind(claims.usuario_id)
+    .fetch_one(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al obtener usuario: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    // El usuario pudo desactivarse entre la contraseña y el código
+    if !row.get::<bool, _>("activo") {
+        eprintln!("Intento de login 2FA de usuario desactivado: {}", claims.usuario_id);
+        return Err(ErrorApi::Prohibido("El usuario está desactivado".to_string()));
+    }
+
+    let respuesta = crate::emitir_tokens(
+        &pool,
+        &config,
+        claims.usuario_id,
+        row.get("email"),
+        row.get("nombre"),
+        row.get("apellido"),
+        row.get("rol_nombre"),
+    )
+    .await?;
+
+    Ok(Json(respuesta))
+}
+
+// PUT /roles/:id/2fa
+pub async fn requerir_2fa_rol(
+    Path(id): Path<i32>,
+    State(pool): State<PgPool>,
+    Json(request): Json<Requiere2faRequest>,
+) -> Result<StatusCode, ErrorApi> {
+    let resultado = sqlx::query("UPDATE roles SET requiere_2fa = $1 WHERE id = $2")
+        .bind(request.requerido)
+        .bind(id)
+        .execute(&pool)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al actualizar rol: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    if resultado.rows_affected() == 0 {
+        return Err(ErrorApi::no_encontrado("Rol"));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Secreto de los vectores de prueba SHA-1 del RFC 6238 (apéndice B)
+    const SECRETO_RFC: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn vectores_rfc_6238() {
+        // (T en segundos, código de 8 dígitos del RFC); aquí se usan los 6 últimos
+        let vectores = [
+            (59, 94287082),
+            (1111111109, 7081804),
+            (1111111111, 14050471),
+            (1234567890, 89005924),
+            (2000000000, 69279037),
+            (20000000000, 65353130),
+        ];
+
+        for (tiempo, esperado) in vectores {
+            assert_eq!(codigo_totp(SECRETO_RFC, tiempo / PERIODO_TOTP), esperado % 1_000_000, "T = {}", tiempo);
+        }
+    }
+
+    #[test]
+    fn base32_del_secreto_rfc() {
+        assert_eq!(base32(SECRETO_RFC), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        assert_eq!(base32_decodificar("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").as_deref(), Some(SECRETO_RFC));
+    }
+
+    #[test]
+    fn base32_ida_y_vuelta() {
+        // Todas las longitudes de bloque final (1 a 5 bytes) y secretos aleatorios
+        for longitud in 0..=25 {
+            let bytes: Vec<u8> = (0..longitud).map(|i| (i * 37 + 11) as u8).collect();
+            assert_eq!(base32_decodificar(&base32(&bytes)), Some(bytes));
+        }
+        for _ in 0..100 {
+            let mut bytes = [0u8; 20];
+            rand::thread_rng().fill_bytes(&mut bytes);
+            assert_eq!(base32_decodificar(&base32(&bytes)).as_deref(), Some(&bytes[..]));
+        }
+    }
+
+    #[test]
+    fn base32_tolera_minusculas_y_relleno() {
+        assert_eq!(base32_decodificar("gezdgnbv").as_deref(), Some(&b"12345"[..]));
+        assert_eq!(base32_decodificar("GE======").as_deref(), Some(&b"1"[..]));
+        assert_eq!(base32_decodificar("GEZ1"), None);
+    }
+
+    #[test]
+    fn verificar_totp_acepta_un_periodo_de_desfase() {
+        let secreto = base32(SECRETO_RFC);
+        let ahora = 1111111111 / PERIODO_TOTP;
+        let codigo = |paso| format!("{:06}", codigo_totp(SECRETO_RFC, paso));
+
+        for paso in [ahora - 1, ahora, ahora + 1] {
+            assert_eq!(verificar_totp_en(&secreto, &codigo(paso), ahora), Some(paso as i64));
+        }
+        assert_eq!(verificar_totp_en(&secreto, &codigo(ahora - 2), ahora), None);
+        assert_eq!(verificar_totp_en(&secreto, &codigo(ahora + 2), ahora), None);
+        assert_eq!(verificar_totp_en(&secreto, "no-es-un-codigo", ahora), None);
+    }
+}
@@ -0,0 +1,195 @@
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+pub const CABECERA_ID_SOLICITUD: HeaderName = HeaderName::from_static("x-request-id");
+
+tokio::task_local! {
+    // Identificador de la solicitud en curso, visible para los errores
+    static ID_SOLICITUD: String;
+}
+
+// Error de un campo concreto del cuerpo de la solicitud
+#[derive(Serialize)]
+pub struct ErrorCampo {
+    pub campo: String,
+    pub mensaje: String,
+}
+
+impl ErrorCampo {
+    pub fn new(campo: &str, mensaje: impl Into<String>) -> Self {
+        ErrorCampo {
+            campo: campo.to_string(),
+            mensaje: mensaje.into(),
+        }
+    }
+}
+
+// Errores de la API. Todos se responden con el mismo cuerpo JSON:
+// {"code": ..., "mensaje": ..., "detalles": ..., "request_id": ...}
+pub enum ErrorApi {
+    SolicitudInvalida(String),
+    NoAutenticado(String),
+    Prohibido(String),
+    NoEncontrado(String),
+    Conflicto { mensaje: String, detalles: Option<Value> },
+    ReferenciaInvalida { mensaje: String, detalles: Option<Value> },
+    // La operación no es válida en el estado actual del recurso
+    EstadoInvalido { mensaje: String, detalles: Option<Value> },
+    // La cita choca con otra cita o cae fuera del horario del médico
+    ConflictoAgenda { mensaje: String, detalles: Option<Value> },
+    // If-Match no coincide con la versión actual del recurso
+    PrecondicionFallida { mensaje: String, detalles: Option<Value> },
+    // La edición llegó sin If-Match
+    PrecondicionRequerida(String),
+    Validacion(Vec<ErrorCampo>),
+    DemasiadosIntentos(String),
+    NoDisponible,
+    Interno,
+}
+
+impl ErrorApi {
+    pub fn estado(&self) -> StatusCode {
+        match self {
+            ErrorApi::SolicitudInvalida(_) => StatusCode::BAD_REQUEST,
+            ErrorApi::NoAutenticado(_) => StatusCode::UNAUTHORIZED,
+            ErrorApi::Prohibido(_) => StatusCode::FORBIDDEN,
+            ErrorApi::NoEncontrado(_) => StatusCode::NOT_FOUND,
+            ErrorApi::Conflicto { .. }
+            | ErrorApi::ReferenciaInvalida { .. }
+            | ErrorApi::EstadoInvalido { .. }
+            | ErrorApi::ConflictoAgenda { .. } => StatusCode::CONFLICT,
+            ErrorApi::PrecondicionFallida { .. } => StatusCode::PRECONDITION_FAILED,
+            ErrorApi::PrecondicionRequerida(_) => StatusCode::PRECONDITION_REQUIRED,
+            ErrorApi::Validacion(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorApi::DemasiadosIntentos(_) => StatusCode::TOO_MANY_REQUESTS,
+            ErrorApi::NoDisponible => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorApi::Interno => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn codigo(&self) -> &'static str {
+        match self {
+            ErrorApi::SolicitudInvalida(_) => "solicitud_invalida",
+            ErrorApi::NoAutenticado(_) => "no_autenticado",
+            ErrorApi::Prohibido(_) => "prohibido",
+            ErrorApi::NoEncontrado(_) => "no_encontrado",
+            ErrorApi::Conflicto { .. } => "registro_duplicado",
+            ErrorApi::ReferenciaInvalida { .. } => "referencia_invalida",
+            ErrorApi::EstadoInvalido { .. } => "estado_invalido",
+            ErrorApi::ConflictoAgenda { .. } => "conflicto_agenda",
+            ErrorApi::PrecondicionFallida { .. } => "version_obsoleta",
+            ErrorApi::PrecondicionRequerida(_) => "precondicion_requerida",
+            ErrorApi::Validacion(_) => "validacion",
+            ErrorApi::DemasiadosIntentos(_) => "demasiados_intentos",
+            ErrorApi::NoDisponible => "servicio_no_disponible",
+            ErrorApi::Interno => "error_interno",
+        }
+    }
+
+    pub fn no_encontrado(recurso: &str) -> Self {
+        ErrorApi::NoEncontrado(format!("{} no encontrado", recurso))
+    }
+}
+
+// Mensaje legible para las restricciones UNIQUE conocidas
+fn mensaje_duplicado(restriccion: Option<&str>) -> String {
+    match restriccion {
+        Some("pacientes_ci_unico") => "Ya existe un paciente con esa cédula".to_string(),
+        Some("usuarios_email_key") => "Ya existe un usuario con ese email".to_string(),
+        Some("roles_nombre_key") => "Ya existe un rol con ese nombre".to_string(),
+        Some("feriados_pkey") => "Ya hay un feriado registrado en esa fecha".to_string(),
+        _ => "El registro ya existe".to_string(),
+    }
+}
+
+impl From<sqlx::Error> for ErrorApi {
+    fn from(error: sqlx::Error) -> Self {
+        match &error {
+            sqlx::Error::RowNotFound => ErrorApi::NoEncontrado("Registro no encontrado".to_string()),
+            sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed | sqlx::Error::Io(_) => ErrorApi::NoDisponible,
+            sqlx::Error::Database(db_err) => {
+                let restriccion = db_err.constraint();
+                let detalles = restriccion.map(|r| json!({ "restriccion": r }));
+                match db_err.code().as_deref() {
+                    // 23505: violación de UNIQUE
+                    Some("23505") => ErrorApi::Conflicto {
+                        mensaje: mensaje_duplicado(restriccion),
+                        detalles,
+                    },
+                    // 23503: la fila referenciada no existe o aún está referenciada
+                    Some("23503") => ErrorApi::ReferenciaInvalida {
+                        mensaje: "El registro referenciado no existe o todavía está en uso".to_string(),
+                        detalles,
+                    },
+                    _ => ErrorApi::Interno,
+                }
+            }
+            _ => ErrorApi::Interno,
+        }
+    }
+}
+
+impl IntoResponse for ErrorApi {
+    fn into_response(self) -> Response {
+        let estado = self.estado();
+        let codigo = self.codigo();
+
+        let (mensaje, detalles) = match self {
+            ErrorApi::SolicitudInvalida(mensaje)
+            | ErrorApi::NoAutenticado(mensaje)
+            | ErrorApi::Prohibido(mensaje)
+            | ErrorApi::NoEncontrado(mensaje)
+            | ErrorApi::PrecondicionRequerida(mensaje)
+            | ErrorApi::DemasiadosIntentos(mensaje) => (mensaje, None),
+            ErrorApi::Conflicto { mensaje, detalles }
+            | ErrorApi::ReferenciaInvalida { mensaje, detalles }
+            | ErrorApi::EstadoInvalido { mensaje, detalles }
+            | ErrorApi::ConflictoAgenda { mensaje, detalles }
+            | ErrorApi::PrecondicionFallida { mensaje, detalles } => (mensaje, detalles),
+            ErrorApi::Validacion(errores) => (
+                "Los datos enviados no son válidos".to_string(),
+                serde_json::to_value(errores).ok(),
+            ),
+            ErrorApi::NoDisponible => ("El servicio no está disponible en este momento".to_string(), None),
+            ErrorApi::Interno => ("Error interno del servidor".to_string(), None),
+        };
+
+        let request_id = ID_SOLICITUD.try_with(|id| id.clone()).ok();
+
+        let cuerpo = json!({
+            "code": codigo,
+            "mensaje": mensaje,
+            "detalles": detalles,
+            "request_id": request_id,
+        });
+
+        (estado, Json(cuerpo)).into_response()
+    }
+}
+
+// Middleware: asigna un identificador a cada solicitud (o respeta el que
+// envía el cliente en X-Request-Id) y lo devuelve en la misma cabecera.
+pub async fn asignar_id_solicitud(req: Request, next: Next) -> Response {
+    let id = req
+        .headers()
+        .get(&CABECERA_ID_SOLICITUD)
+        .and_then(|valor| valor.to_str().ok())
+        .filter(|valor| !valor.is_empty() && valor.len() <= 64)
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let mut respuesta = ID_SOLICITUD.scope(id.clone(), next.run(req)).await;
+
+    if let Ok(valor) = HeaderValue::from_str(&id) {
+        respuesta.headers_mut().insert(CABECERA_ID_SOLICITUD, valor);
+    }
+
+    respuesta
+}
@@ -0,0 +1,375 @@
+// Ciclo de vida de una cita. El estado solo cambia por los endpoints de
+// transición, que comprueban que el paso está permitido y registran quién lo
+// dio y cuándo en `citas_estados`:
+//
+// programada -> confirmada -> en_sala -> atendida
+// programada | confirmada -> cancelada | no_asistio
+// programada -> en_sala
+
+use axum::{
+    extract::{Path, State},
+    response::Json,
+};
+use chrono::{Local, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{PgConnection, PgPool, Row};
+use std::fmt;
+
+use crate::auditoria::{self, Actor, Evento};
+use crate::concurrencia::{ConVersion, IfMatch};
+use crate::errores::{ErrorApi, ErrorCampo};
+use crate::validacion::{Errores, ValidatedJson, Validar};
+use crate::{auditar, cita_actual, cita_desde_fila, confirmar_transaccion, iniciar_transaccion, Cita};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum EstadoCita {
+    #[default]
+    Programada,
+    Confirmada,
+    EnSala,
+    Atendida,
+    Cancelada,
+    #[serde(alias = "no_asistió")]
+    NoAsistio,
+}
+
+impl EstadoCita {
+    // Valor guardado en `citas.estado`
+    pub fn como_str(self) -> &'static str {
+        match self {
+            EstadoCita::Programada => "programada",
+            EstadoCita::Confirmada => "confirmada",
+            EstadoCita::EnSala => "en_sala",
+            EstadoCita::Atendida => "atendida",
+            EstadoCita::Cancelada => "cancelada",
+            EstadoCita::NoAsistio => "no_asistio",
+        }
+    }
+
+    // La restricción citas_estado_valido garantiza que la columna tiene uno
+    // de los valores de `como_str`
+    pub fn desde_columna(valor: &str) -> Self {
+        match valor {
+            "confirmada" => EstadoCita::Confirmada,
+            "en_sala" => EstadoCita::EnSala,
+            "atendida" => EstadoCita::Atendida,
+            "cancelada" => EstadoCita::Cancelada,
+            "no_asistio" => EstadoCita::NoAsistio,
+            _ => EstadoCita::Programada,
+        }
+    }
+
+    // Una cita cerrada ya no se edita ni cambia de estado
+    pub fn cerrada(self) -> bool {
+        matches!(self, EstadoCita::Atendida | EstadoCita::Cancelada | EstadoCita::NoAsistio)
+    }
+
+    pub fn siguientes(self) -> &'static [EstadoCita] {
+        match self {
+            EstadoCita::Programada => &[
+                EstadoCita::Confirmada,
+                EstadoCita::EnSala,
+                EstadoCita::Cancelada,
+                EstadoCita::NoAsistio,
+            ],
+            EstadoCita::Confirmada => &[EstadoCita::EnSala, EstadoCita::Cancelada, EstadoCita::NoAsistio],
+            EstadoCita::EnSala => &[EstadoCita::Atendida],
+            EstadoCita::Atendida | EstadoCita::Cancelada | EstadoCita::NoAsistio => &[],
+        }
+    }
+}
+
+impl fmt::Display for EstadoCita {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.como_str())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CancelarCitaRequest {
+    pub motivo: String,
+}
+
+impl Validar for CancelarCitaRequest {
+    fn validar(&self) -> Vec<ErrorCampo> {
+        let mut errores = Errores::default();
+        errores.requerido("motivo", &self.motivo, 500);
+        errores.terminar()
+    }
+}
+
+#[derive(Serialize)]
+pub struct CambioEstado {
+    id: i32,
+    cita_id: i32,
+    estado_anterior: EstadoCita,
+    estado_nuevo: EstadoCita,
+    motivo: Option<String>,
+    usuario_id: Option<i32>,
+    fecha: NaiveDateTime,
+}
+
+// POST /citas/:id/confirmar
+pub async fn confirmar_cita(
+    Path(id): Path<i32>,
+    State(pool): State<PgPool>,
+    actor: Actor,
+    if_match: IfMatch,
+) -> Result<ConVersion<Cita>, ErrorApi> {
+    cambiar_estado(&pool, &actor, &if_match, id, EstadoCita::Confirmada, None).await
+}
+
+// POST /citas/:id/cancelar
+pub async fn cancelar_cita(
+    Path(id): Path<i32>,
+    State(pool): State<PgPool>,
+    actor: Actor,
+    if_match: IfMatch,
+    ValidatedJson(request): ValidatedJson<CancelarCitaRequest>,
+) -> Result<ConVersion<Cita>, ErrorApi> {
+    cambiar_estado(&pool, &actor, &if_match, id, EstadoCita::Cancelada, Some(request.motivo.trim())).await
+}
+
+// POST /citas/:id/checkin
+pub async fn checkin_cita(
+    Path(id): Path<i32>,
+    State(pool): State<PgPool>,
+    actor: Actor,
+    if_match: IfMatch,
+) -> Result<ConVersion<Cita>, ErrorApi> {
+    cambiar_estado(&pool, &actor, &if_match, id, EstadoCita::EnSala, None).await
+}
+
+// POST /citas/:id/completar
+pub async fn completar_cita(
+    Path(id): Path<i32>,
+    State(pool): State<PgPool>,
+    actor: Actor,
+    if_match: IfMatch,
+) -> Result<ConVersion<Cita>, ErrorApi> {
+    cambiar_estado(&pool, &actor, &if_match, id, EstadoCita::Atendida, None).await
+}
+
+// POST /citas/:id/no_asistio
+pub async fn no_asistio_cita(
+    Path(id): Path<i32>,
+    State(pool): State<PgPool>,
+    actor: Actor,
+    if_match: IfMatch,
+) -> Result<ConVersion<Cita>, ErrorApi> {
+    cambiar_estado(&pool, &actor, &if_match, id, EstadoCita::NoAsistio, None).await
+}
+
+async fn cambiar_estado(
+    pool: &PgPool,
+    actor: &Actor,
+    if_match: &IfMatch,
+    id: i32,
+    destino: EstadoCita,
+    motivo: Option<&str>,
+) -> Result<ConVersion<Cita>, ErrorApi> {
+    let mut tx = iniciar_transaccion(pool).await?;
+    let (antes, version) = cita_actual(&mut *tx, id).await?;
+    if_match.verificar_si_presente(version)?;
+    let (actualizada, version) = transicion(&mut tx, actor, id, antes, destino, motivo).await?;
+    confirmar_transaccion(tx).await?;
+
+    Ok(ConVersion(version, actualizada))
+}
+
+// Lleva la cita `antes`, ya bloqueada, al estado `destino` si la transición
+// está permitida, y deja constancia en `citas_estados` y en la auditoría.
+// Devuelve la cita guardada con su nueva versión.
+pub async fn transicion(
+    conn: &mut PgConnection,
+    actor: &Actor,
+    id: i32,
+    antes: Cita,
+    destino: EstadoCita,
+    motivo: Option<&str>,
+) -> Result<(Cita, i32), ErrorApi> {
+    if !antes.estado.siguientes().contains(&destino) {
+        return Err(ErrorApi::EstadoInvalido {
+            mensaje: format!("Una cita {} no puede pasar a {}", antes.estado, destino),
+            detalles: Some(json!({
+                "estado_actual": antes.estado,
+                "estado_solicitado": destino,
+                "permitidos": antes.estado.siguientes(),
+            })),
+        });
+    }
+
+    // No se marca la inasistencia de una cita que aún no ha llegado
+    if destino == EstadoCita::NoAsistio && antes.fecha_hora > Local::now().naive_local() {
+        return Err(ErrorApi::EstadoInvalido {
+            mensaje: "La cita todavía no ha ocurrido".to_string(),
+            detalles: Some(json!({ "fecha_hora": antes.fecha_hora })),
+        });
+    }
+
+    let row = sqlx::query(
+        "UPDATE citas SET estado = $1, motivo_cancelacion = COALESCE($2, motivo_cancelacion) WHERE id = $3 RETURNING id, paciente_id, usuario_id, fecha_hora, estado, motivo, serie_id, version"
+    )
+    .bind(destino.como_str())
+    .bind(motivo)
+    .bind(id)
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al cambiar estado de cita: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    sqlx::query(
+        "INSERT INTO citas_estados (cita_id, estado_anterior, estado_nuevo, motivo, usuario_id) VALUES ($1, $2, $3, $4, $5)"
+    )
+    .bind(id)
+    .bind(antes.estado.como_str())
+    .bind(destino.como_str())
+    .bind(motivo)
+    .bind(actor.usuario_id)
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al registrar cambio de estado de cita: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    let actualizada = cita_desde_fila(&row);
+
+    auditar(
+        &mut *conn,
+        actor,
+        Evento::cambio(
+            destino.como_str(),
+            "citas",
+            id,
+            Some(actualizada.paciente_id),
+            auditoria::valor(&antes),
+            auditoria::valor(&actualizada),
+        ),
+    )
+    .await?;
+
+    Ok((actualizada, row.get("version")))
+}
+
+// GET /citas/:id/estados
+pub async fn get_estados_cita(
+    Path(id): Path<i32>,
+    State(pool): State<PgPool>,
+    actor: Actor,
+) -> Result<Json<Vec<CambioEstado>>, ErrorApi> {
+    let rows = sqlx::query(
+        "SELECT id, cita_id, estado_anterior, estado_nuevo, motivo, usuario_id, fecha FROM citas_estados WHERE cita_id = $1 ORDER BY fecha, id"
+    )
+    .bind(id)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al obtener cambios de estado de cita: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    let cambios: Vec<CambioEstado> = rows
+        .into_iter()
+        .map(|row| CambioEstado {
+            id: row.get("id"),
+            cita_id: row.get("cita_id"),
+            estado_anterior: EstadoCita::desde_columna(row.get("estado_anterior")),
+            estado_nuevo: EstadoCita::desde_columna(row.get("estado_nuevo")),
+            motivo: row.get("motivo"),
+            usuario_id: row.get("usuario_id"),
+            fecha: row.get("fecha"),
+        })
+        .collect();
+
+    auditar(&pool, &actor, Evento::lectura("citas_estados", Some(id), None)).await?;
+
+    Ok(Json(cambios))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TODOS: [EstadoCita; 6] = [
+        EstadoCita::Programada,
+        EstadoCita::Confirmada,
+        EstadoCita::EnSala,
+        EstadoCita::Atendida,
+        EstadoCita::Cancelada,
+        EstadoCita::NoAsistio,
+    ];
+
+    #[test]
+    fn transiciones_permitidas_y_prohibidas() {
+        use EstadoCita::*;
+        let permitidas = [
+            (Programada, Confirmada),
+            (Programada, EnSala),
+            (Programada, Cancelada),
+            (Programada, NoAsistio),
+            (Confirmada, EnSala),
+            (Confirmada, Cancelada),
+            (Confirmada, NoAsistio),
+            (EnSala, Atendida),
+        ];
+
+        for desde in TODOS {
+            for hacia in TODOS {
+                assert_eq!(
+                    desde.siguientes().contains(&hacia),
+                    permitidas.contains(&(desde, hacia)),
+                    "{} -> {}",
+                    desde,
+                    hacia
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn solo_las_cerradas_no_tienen_salida() {
+        for estado in TODOS {
+            assert_eq!(estado.cerrada(), estado.siguientes().is_empty(), "{}", estado);
+        }
+        assert!(EstadoCita::Atendida.cerrada());
+        assert!(EstadoCita::Cancelada.cerrada());
+        assert!(EstadoCita::NoAsistio.cerrada());
+        assert!(!EstadoCita::EnSala.cerrada());
+    }
+
+    #[test]
+    fn columna_ida_y_vuelta() {
+        for estado in TODOS {
+            assert_eq!(EstadoCita::desde_columna(estado.como_str()), estado);
+        }
+    }
+
+    #[test]
+    fn serde_usa_los_valores_de_la_columna() {
+        for estado in TODOS {
+            let json = serde_json::to_value(estado).unwrap();
+            assert_eq!(json, estado.como_str());
+            assert_eq!(serde_json::from_value::<EstadoCita>(json).unwrap(), estado);
+        }
+    }
+
+    #[test]
+    fn alias_no_asistio_con_tilde() {
+        let estado: EstadoCita = serde_json::from_str("\"no_asistió\"").unwrap();
+        assert_eq!(estado, EstadoCita::NoAsistio);
+        // Se guarda y se devuelve sin tilde
+        assert_eq!(serde_json::to_string(&estado).unwrap(), "\"no_asistio\"");
+        assert_eq!(EstadoCita::desde_columna(estado.como_str()), EstadoCita::NoAsistio);
+    }
+
+    #[test]
+    fn estados_desconocidos_se_rechazan() {
+        assert!(serde_json::from_str::<EstadoCita>("\"pendiente\"").is_err());
+        assert!(serde_json::from_str::<EstadoCita>("\"Confirmada\"").is_err());
+    }
+}
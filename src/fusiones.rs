@@ -0,0 +1,321 @@
+use axum::{
+    extract::{Path, State},
+    response::Json,
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::{PgPool, Row};
+use std::collections::HashMap;
+
+use crate::auditoria::{self, Actor, Evento};
+use crate::errores::{ErrorApi, ErrorCampo};
+use crate::validacion::{Errores, ValidatedJson, Validar};
+use crate::{
+    auditar, auditar_lecturas, confirmar_transaccion, iniciar_transaccion, paciente_actual, paciente_con_edad_desde_fila, PacienteConEdad,
+};
+
+#[derive(Serialize)]
+pub struct GrupoDuplicados {
+    // "ci" o "nombre_fecha_nacimiento"
+    motivo: String,
+    pacientes: Vec<PacienteConEdad>,
+}
+
+#[derive(Deserialize)]
+pub struct FusionarRequest {
+    paciente_duplicado_id: i32,
+}
+
+impl Validar for FusionarRequest {
+    fn validar(&self) -> Vec<ErrorCampo> {
+        let mut errores = Errores::default();
+        errores.id("paciente_duplicado_id", self.paciente_duplicado_id);
+        errores.terminar()
+    }
+}
+
+#[derive(Serialize)]
+pub struct Fusion {
+    id: i32,
+    paciente_conservado_id: i32,
+    paciente_fusionado_id: i32,
+    datos_fusionado: Value,
+    citas_movidas: i32,
+    diagnosticos_movidos: i32,
+    usuario_id: Option<i32>,
+    fecha: NaiveDateTime,
+}
+
+// GET /pacientes/duplicados
+pub async fn get_duplicados(
+    State(pool): State<PgPool>,
+    actor: Actor,
+) -> Result<Json<Vec<GrupoDuplicados>>, ErrorApi> {
+    // Las cédulas repetidas anteriores al índice único quedaron apartadas en
+    // `pacientes_ci_revision` con una cédula provisional (`-REVISAR-<id>`);
+    // mientras la conserven se ofrecen junto al paciente que se quedó la
+    // cédula original
+    let grupos = sqlx::query(
+        "SELECT 'ci' AS motivo, ARRAY_AGG(id ORDER BY id) AS ids FROM pacientes WHERE archivado_en IS NULL GROUP BY ci_normalizada(ci) HAVING COUNT(*) > 1 UNION ALL SELECT 'ci', r.paciente_conservado_id || ARRAY_AGG(r.paciente_id ORDER BY r.paciente_id) FROM pacientes_ci_revision r JOIN pacientes p ON p.id = r.paciente_id JOIN pacientes c ON c.id = r.paciente_conservado_id WHERE p.archivado_en IS NULL AND c.archivado_en IS NULL AND p.ci LIKE '%-REVISAR-%' GROUP BY r.paciente_conservado_id UNION ALL SELECT 'nombre_fecha_nacimiento', ARRAY_AGG(id ORDER BY id) FROM pacientes WHERE archivado_en IS NULL GROUP BY lower(sin_acentos(trim(nombre))), lower(sin_acentos(trim(apellido))), fecha_nacimiento HAVING COUNT(*) > 1"
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al buscar pacientes duplicados: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    let ids: Vec<i32> = grupos
+        .iter()
+        .flat_map(|row| row.get::<Vec<i32>, _>("ids"))
+        .collect();
+
+    let rows = sqlx::query(
+        "SELECT id, nombre, apellido, ci, ci_representante, telefono, email, fecha_nacimiento, sexo, grupo_sanguineo, alergias, direccion_estado, direccion_municipio, direccion_parroquia, direccion_detalle, contacto_emergencia_nombre, contacto_emergencia_telefono, contacto_emergencia_parentesco, aseguradora, numero_poliza, archivado_en, EXTRACT(YEAR FROM AGE(fecha_nacimiento))::INTEGER AS edad FROM pacientes WHERE id = ANY($1)"
+    )
+    .bind(&ids)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al obtener pacientes duplicados: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    let por_id: HashMap<i32, sqlx::postgres::PgRow> =
+        rows.into_iter().map(|row| (row.get("id"), row)).collect();
+
+    let duplicados: Vec<GrupoDuplicados> = grupos
+        .into_iter()
+        .map(|grupo| GrupoDuplicados {
+            motivo: grupo.get("motivo"),
+            pacientes: grupo
+                .get::<Vec<i32>, _>("ids")
+                .into_iter()
+                .filter_map(|id| por_id.get(&id))
+                .map(paciente_con_edad_desde_fila)
+                .collect(),
+        })
+        .collect();
+
+    let lecturas: Vec<(i32, i32)> = por_id.keys().map(|&id| (id, id)).collect();
+    auditar_lecturas(&pool, &actor, "pacientes", &lecturas).await?;
+
+    Ok(Json(duplicados))
+}
+
+// POST /pacientes/:id/fusionar
+// El paciente de la ruta se conserva; el duplicado le cede sus citas y
+// diagnósticos (con sus exámenes) y luego se archiva con `fusionado_en`
+// apuntando al conservado, sujeto a la misma retención que cualquier archivado.
+pub async fn fusionar_pacientes(
+    Path(id): Path<i32>,
+    State(pool): State<PgPool>,
+    actor: Actor,
+    ValidatedJson(request): ValidatedJson<FusionarRequest>,
+) -> Result<Json<Fusion>, ErrorApi> {
+    let duplicado_id = request.paciente_duplicado_id;
+    if duplicado_id == id {
+        return Err(ErrorApi::Validacion(vec![ErrorCampo::new(
+            "paciente_duplicado_id",
+            "Un paciente no puede fusionarse consigo mismo",
+        )]));
+    }
+
+    let mut tx = iniciar_transaccion(&pool).await?;
+
+    // Bloquear siempre en el mismo orden para evitar interbloqueos
+    let (primero, segundo) = (id.min(duplicado_id), id.max(duplicado_id));
+    let (a, _) = paciente_actual(&mut *tx, primero).await?;
+    let (b, _) = paciente_actual(&mut *tx, segundo).await?;
+    let duplicado = if primero == duplicado_id { a } else { b };
+
+    let citas_movidas = sqlx::query("UPDATE citas SET paciente_id = $1 WHERE paciente_id = $2")
+        .bind(id)
+        .bind(duplicado_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al mover citas: {}", e);
+            ErrorApi::from(e)
+        })?
+        .rows_affected();
+
+    sqlx::query("UPDATE series_citas SET paciente_id = $1 WHERE paciente_id = $2")
+        .bind(id)
+        .bind(duplicado_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al mover series de citas: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    let diagnosticos_movidos = mover_expediente(&mut tx, id, duplicado_id).await?;
+
+    sqlx::query("UPDATE pacientes SET archivado_en = NOW(), archivado_por = $2, fusionado_en = $3 WHERE id = $1")
+        .bind(duplicado_id)
+        .bind(actor.usuario_id)
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al archivar paciente fusionado: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    let datos_fusionado = auditoria::valor(&duplicado).unwrap_or(Value::Null);
+
+    let row = sqlx::query(
+        "INSERT INTO fusiones_pacientes (paciente_conservado_id, paciente_fusionado_id, datos_fusionado, citas_movidas, diagnosticos_movidos, usuario_id) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id, fecha"
+    )
+    .bind(id)
+    .bind(duplicado_id)
+    .bind(&datos_fusionado)
+    .bind(citas_movidas as i32)
+    .bind(diagnosticos_movidos as i32)
+    .bind(actor.usuario_id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al registrar fusión: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    auditar(
+        &mut *tx,
+        &actor,
+        Evento::cambio(
+            "fusionar",
+            "pacientes",
+            duplicado_id,
+            Some(id),
+            Some(datos_fusionado.clone()),
+            Some(json!({ "fusionado_en": id })),
+        ),
+    )
+    .await?;
+    confirmar_transaccion(tx).await?;
+
+    Ok(Json(Fusion {
+        id: row.get("id"),
+        paciente_conservado_id: id,
+        paciente_fusionado_id: duplicado_id,
+        datos_fusionado,
+        citas_movidas: citas_movidas as i32,
+        diagnosticos_movidos: diagnosticos_movidos as i32,
+        usuario_id: Some(actor.usuario_id),
+        fecha: row.get("fecha"),
+    }))
+}
+
+// Pasa los diagnósticos del expediente del duplicado al del paciente
+// conservado. Si el conservado no tiene expediente, hereda el del duplicado.
+// Devuelve cuántos diagnósticos cambiaron de paciente.
+async fn mover_expediente(
+    conn: &mut sqlx::PgConnection,
+    conservado_id: i32,
+    duplicado_id: i32,
+) -> Result<u64, ErrorApi> {
+    let rows = sqlx::query("SELECT id, paciente_id FROM expedientes WHERE paciente_id = ANY($1) FOR UPDATE")
+        .bind([conservado_id, duplicado_id])
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al obtener expedientes: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    let expediente_de = |paciente_id: i32| {
+        rows.iter()
+            .find(|row| row.get::<i32, _>("paciente_id") == paciente_id)
+            .map(|row| row.get::<i32, _>("id"))
+    };
+
+    let Some(expediente_duplicado) = expediente_de(duplicado_id) else {
+        return Ok(0);
+    };
+
+    let diagnosticos: i64 = sqlx::query("SELECT COUNT(*) AS total FROM expedientes_diagnosticos WHERE expediente_id = $1")
+        .bind(expediente_duplicado)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al contar diagnósticos: {}", e);
+            ErrorApi::from(e)
+        })?
+        .get("total");
+
+    match expediente_de(conservado_id) {
+        None => {
+            sqlx::query("UPDATE expedientes SET paciente_id = $1 WHERE id = $2")
+                .bind(conservado_id)
+                .bind(expediente_duplicado)
+                .execute(&mut *conn)
+                .await
+                .map_err(|e| {
+                    eprintln!("Error al reasignar expediente: {}", e);
+                    ErrorApi::from(e)
+                })?;
+        }
+        Some(expediente_conservado) => {
+            // Los exámenes cuelgan de cada diagnóstico y se mueven con él
+            sqlx::query("UPDATE expedientes_diagnosticos SET expediente_id = $1 WHERE expediente_id = $2")
+                .bind(expediente_conservado)
+                .bind(expediente_duplicado)
+                .execute(&mut *conn)
+                .await
+                .map_err(|e| {
+                    eprintln!("Error al mover diagnósticos: {}", e);
+                    ErrorApi::from(e)
+                })?;
+
+            sqlx::query("DELETE FROM expedientes WHERE id = $1")
+                .bind(expediente_duplicado)
+                .execute(&mut *conn)
+                .await
+                .map_err(|e| {
+                    eprintln!("Error al eliminar expediente duplicado: {}", e);
+                    ErrorApi::from(e)
+                })?;
+        }
+    }
+
+    Ok(diagnosticos as u64)
+}
+
+// GET /pacientes/:id/fusiones
+pub async fn get_fusiones(
+    Path(id): Path<i32>,
+    State(pool): State<PgPool>,
+    actor: Actor,
+) -> Result<Json<Vec<Fusion>>, ErrorApi> {
+    let rows = sqlx::query(
+        "SELECT id, paciente_conservado_id, paciente_fusionado_id, datos_fusionado, citas_movidas, diagnosticos_movidos, usuario_id, fecha FROM fusiones_pacientes WHERE paciente_conservado_id = $1 ORDER BY fecha DESC"
+    )
+    .bind(id)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al obtener fusiones: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    let fusiones: Vec<Fusion> = rows
+        .into_iter()
+        .map(|row| Fusion {
+            id: row.get("id"),
+            paciente_conservado_id: row.get("paciente_conservado_id"),
+            paciente_fusionado_id: row.get("paciente_fusionado_id"),
+            datos_fusionado: row.get("datos_fusionado"),
+            citas_movidas: row.get("citas_movidas"),
+            diagnosticos_movidos: row.get("diagnosticos_movidos"),
+            usuario_id: row.get("usuario_id"),
+            fecha: row.get("fecha"),
+        })
+        .collect();
+
+    auditar(&pool, &actor, Evento::lectura("fusiones_pacientes", None, Some(id))).await?;
+
+    Ok(Json(fusiones))
+}
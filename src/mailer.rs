@@ -0,0 +1,245 @@
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::Utc;
+use std::env;
+use std::sync::Arc;
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::{self, pki_types::ServerName, ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+pub struct Correo {
+    pub para: String,
+    pub asunto: String,
+    pub cuerpo: String,
+}
+
+// Envío de correos. La implementación se elige con MAIL_TRANSPORTE.
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn enviar(&self, correo: &Correo) -> Result<(), String>;
+}
+
+pub fn desde_entorno(produccion: bool) -> Result<Arc<dyn Mailer>, String> {
+    let transporte = env::var("MAIL_TRANSPORTE").unwrap_or_else(|_| {
+        if produccion { "smtp" } else { "archivo" }.to_string()
+    });
+
+    match transporte.as_str() {
+        "smtp" => Ok(Arc::new(SmtpMailer::desde_entorno()?)),
+        "archivo" => Ok(Arc::new(ArchivoMailer {
+            ruta: env::var("MAIL_ARCHIVO").ok(),
+        })),
+        otro => Err(format!("MAIL_TRANSPORTE no soportado: {}", otro)),
+    }
+}
+
+// Para desarrollo: escribe los correos en un archivo o, si no hay ruta, en la consola
+pub struct ArchivoMailer {
+    ruta: Option<String>,
+}
+
+#[async_trait]
+impl Mailer for ArchivoMailer {
+    async fn enviar(&self, correo: &Correo) -> Result<(), String> {
+        let texto = format!(
+            "--- {} ---\nPara: {}\nAsunto: {}\n\n{}\n\n",
+            Utc::now().to_rfc3339(),
+            correo.para,
+            correo.asunto,
+            correo.cuerpo
+        );
+
+        match &self.ruta {
+            Some(ruta) => {
+                let mut archivo = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(ruta)
+                    .await
+                    .map_err(|e| format!("No se pudo abrir {}: {}", ruta, e))?;
+                archivo
+                    .write_all(texto.as_bytes())
+                    .await
+                    .map_err(|e| format!("No se pudo escribir en {}: {}", ruta, e))
+            }
+            None => {
+                println!("{}", texto);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(PartialEq)]
+enum Seguridad {
+    Ninguna,
+    StartTls,
+    Tls,
+}
+
+pub struct SmtpMailer {
+    host: String,
+    puerto: u16,
+    seguridad: Seguridad,
+    usuario: Option<String>,
+    contrasena: Option<String>,
+    remitente: String,
+}
+
+impl SmtpMailer {
+    fn desde_entorno() -> Result<Self, String> {
+        let host = env::var("SMTP_HOST").map_err(|_| "SMTP_HOST debe estar configurado".to_string())?;
+        let remitente =
+            env::var("SMTP_REMITENTE").map_err(|_| "SMTP_REMITENTE debe estar configurado".to_string())?;
+
+        let seguridad = match env::var("SMTP_SEGURIDAD").unwrap_or_else(|_| "starttls".to_string()).as_str() {
+            "ninguna" => Seguridad::Ninguna,
+            "starttls" => Seguridad::StartTls,
+            "tls" => Seguridad::Tls,
+            otro => return Err(format!("SMTP_SEGURIDAD no soportado: {}", otro)),
+        };
+
+        let puerto = match env::var("SMTP_PUERTO") {
+            Ok(puerto) => puerto
+                .parse()
+                .map_err(|_| "SMTP_PUERTO debe ser un número".to_string())?,
+            Err(_) if seguridad == Seguridad::Tls => 465,
+            Err(_) => 587,
+        };
+
+        Ok(SmtpMailer {
+            host,
+            puerto,
+            seguridad,
+            usuario: env::var("SMTP_USUARIO").ok(),
+            contrasena: env::var("SMTP_CONTRASENA").ok(),
+            remitente,
+        })
+    }
+
+    async fn tls<S>(&self, stream: S) -> Result<tokio_rustls::client::TlsStream<S>, String>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let raices = RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        let config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| e.to_string())?
+            .with_root_certificates(raices)
+            .with_no_client_auth();
+
+        let nombre = ServerName::try_from(self.host.clone()).map_err(|e| e.to_string())?;
+        TlsConnector::from(Arc::new(config))
+            .connect(nombre, stream)
+            .await
+            .map_err(|e| format!("Error TLS con {}: {}", self.host, e))
+    }
+
+    // Conversación SMTP a partir del EHLO, sobre un canal ya establecido
+    async fn conversar<S>(&self, canal: &mut BufReader<S>, correo: &Correo) -> Result<(), String>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        comando(canal, "EHLO lab_clic", 250).await?;
+
+        if let (Some(usuario), Some(contrasena)) = (&self.usuario, &self.contrasena) {
+            let credenciales = STANDARD.encode(format!("\0{}\0{}", usuario, contrasena));
+            comando(canal, &format!("AUTH PLAIN {}", credenciales), 235).await?;
+        }
+
+        comando(canal, &format!("MAIL FROM:<{}>", self.remitente), 250).await?;
+        comando(canal, &format!("RCPT TO:<{}>", correo.para), 250).await?;
+        comando(canal, "DATA", 354).await?;
+        comando(canal, &format!("{}\r\n.", self.mensaje(correo)), 250).await?;
+        comando(canal, "QUIT", 221).await
+    }
+
+    fn mensaje(&self, correo: &Correo) -> String {
+        // Cuerpo en base64 para no depender de 8BITMIME con texto en español
+        let cuerpo = STANDARD.encode(correo.cuerpo.as_bytes());
+        let lineas: Vec<&str> = cuerpo
+            .as_bytes()
+            .chunks(76)
+            .map(|linea| std::str::from_utf8(linea).unwrap())
+            .collect();
+
+        format!(
+            "From: <{}>\r\nTo: <{}>\r\nSubject: =?UTF-8?B?{}?=\r\nDate: {}\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: base64\r\n\r\n{}",
+            self.remitente,
+            correo.para,
+            STANDARD.encode(correo.asunto.as_bytes()),
+            Utc::now().to_rfc2822(),
+            lineas.join("\r\n")
+        )
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn enviar(&self, correo: &Correo) -> Result<(), String> {
+        let tcp = TcpStream::connect((self.host.as_str(), self.puerto))
+            .await
+            .map_err(|e| format!("No se pudo conectar a {}:{}: {}", self.host, self.puerto, e))?;
+
+        match self.seguridad {
+            Seguridad::Ninguna => {
+                let mut canal = BufReader::new(tcp);
+                leer_respuesta(&mut canal, 220).await?;
+                self.conversar(&mut canal, correo).await
+            }
+            Seguridad::Tls => {
+                let mut canal = BufReader::new(self.tls(tcp).await?);
+                leer_respuesta(&mut canal, 220).await?;
+                self.conversar(&mut canal, correo).await
+            }
+            Seguridad::StartTls => {
+                let mut canal = BufReader::new(tcp);
+                leer_respuesta(&mut canal, 220).await?;
+                comando(&mut canal, "EHLO lab_clic", 250).await?;
+                comando(&mut canal, "STARTTLS", 220).await?;
+                let mut canal = BufReader::new(self.tls(canal.into_inner()).await?);
+                self.conversar(&mut canal, correo).await
+            }
+        }
+    }
+}
+
+async fn comando<S>(canal: &mut BufReader<S>, linea: &str, esperado: u16) -> Result<(), String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    canal
+        .get_mut()
+        .write_all(format!("{}\r\n", linea).as_bytes())
+        .await
+        .map_err(|e| format!("Error al escribir al servidor SMTP: {}", e))?;
+    leer_respuesta(canal, esperado).await
+}
+
+// Lee una respuesta SMTP (posiblemente multilínea) y verifica su código
+async fn leer_respuesta<S>(canal: &mut BufReader<S>, esperado: u16) -> Result<(), String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    loop {
+        let mut linea = String::new();
+        let leidos = canal
+            .read_line(&mut linea)
+            .await
+            .map_err(|e| format!("Error al leer del servidor SMTP: {}", e))?;
+        if leidos == 0 {
+            return Err("El servidor SMTP cerró la conexión".to_string());
+        }
+
+        let codigo: u16 = linea.get(..3).and_then(|c| c.parse().ok()).unwrap_or(0);
+        if codigo != esperado {
+            return Err(format!("Respuesta SMTP inesperada: {}", linea.trim_end()));
+        }
+        // "250-..." indica que la respuesta continúa en la siguiente línea
+        if linea.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
@@ -1,26 +1,81 @@
+mod agenda;
+mod auditoria;
+mod auth;
+mod bloqueos;
+mod cedula;
+mod cliente;
+mod concurrencia;
+mod config;
+mod contrasenas;
+mod demograficos;
+mod disponibilidad;
+mod dos_factores;
+mod errores;
+mod estados_cita;
+mod fusiones;
+mod mailer;
+mod notificaciones;
+mod paginacion;
+mod permisos;
+mod recordatorios;
+mod series_citas;
+mod sesiones;
+mod validacion;
+
 use axum::{
-    extract::{Path, State},
+    extract::{FromRef, OriginalUri, Path, Query, State},
     http::StatusCode,
-    response::Json,
-    routing::{delete, get, post, put},
+    middleware,
+    response::{Json, Response},
+    routing::{delete, get, patch, post, put},
     Router,
 };
 use tower_http::cors::CorsLayer;
 use sqlx::{PgPool, Row};
 use serde::{Deserialize, Serialize};
-use std::env;
-use tokio;
-use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
-use bcrypt::{hash, verify, DEFAULT_COST};
-use jsonwebtoken::{encode, decode, Header, Validation, EncodingKey, DecodingKey};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+use chrono::{Local, NaiveDate, NaiveDateTime, NaiveTime};
+use bcrypt::verify;
+use cedula::Cedula;
+use uuid::Uuid;
+
+use auditoria::{Actor, Evento};
+use auth::{AuthUser, TipoToken};
+use cliente::IpCliente;
+use concurrencia::{ConVersion, IfMatch};
+use config::Config;
+use demograficos::DatosComplementarios;
+use errores::{ErrorApi, ErrorCampo};
+use estados_cita::EstadoCita;
+use mailer::{Correo, Mailer};
+use paginacion::{Pagina, Paginacion};
+use validacion::{Errores, Parche, ValidatedJson, Validar};
+
+// Estado compartido por todas las rutas
+#[derive(Clone)]
+struct AppState {
+    pool: PgPool,
+    config: Arc<Config>,
+    mailer: Arc<dyn Mailer>,
+}
 
-#[derive(Serialize, Deserialize)]
-struct Claims {
-    sub: String,
-    exp: usize,
-    rol: String,
-    usuario_id: i32,
+impl FromRef<AppState> for PgPool {
+    fn from_ref(state: &AppState) -> Self {
+        state.pool.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Config> {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<dyn Mailer> {
+    fn from_ref(state: &AppState) -> Self {
+        state.mailer.clone()
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -29,9 +84,23 @@ struct LoginRequest {
     contrasena: String,
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum RespuestaLogin {
+    Completa(LoginResponse),
+    SegundoFactor(SegundoFactorResponse),
+}
+
+#[derive(Serialize, Deserialize)]
+struct SegundoFactorResponse {
+    token_2fa: String,
+    enrolamiento_requerido: bool,
+}
+
 #[derive(Serialize, Deserialize)]
 struct LoginResponse {
     token: String,
+    refresh_token: String,
     rol: String,
     usuario_id: i32,
     nombre: String,
@@ -43,11 +112,17 @@ struct Paciente {
     id: Option<i32>,
     nombre: String,
     apellido: String,
+    // Vacía para menores sin cédula: se deriva de `ci_representante`
+    #[serde(default)]
     ci: String,
+    #[serde(default)]
+    ci_representante: Option<String>,
     telefono: String,
     email: String,
     fecha_nacimiento: NaiveDate,
     sexo: String,
+    #[serde(flatten)]
+    complementarios: DatosComplementarios,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -56,11 +131,16 @@ struct PacienteConEdad {
     nombre: String,
     apellido: String,
     ci: String,
+    ci_representante: Option<String>,
     telefono: String,
     email: String,
     fecha_nacimiento: NaiveDate,
     sexo: String,
     edad: i32,
+    #[serde(flatten)]
+    complementarios: DatosComplementarios,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    archivado_en: Option<NaiveDateTime>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -86,14 +166,22 @@ struct NuevoExpedienteDiagnostico {
 }
 
 #[derive(Serialize, Deserialize)]
-struct Rol {
-    id: i32,
+struct Usuario {
+    id: Option<i32>,
     nombre: String,
+    apellido: String,
+    telefono: String,
+    email: String,
+    fecha_nacimiento: NaiveDate,
+    sexo: String,
+    rol_id: i32,
+    especialidad: Option<String>,
 }
 
+// Datos de entrada para crear un usuario; la contraseña llega en texto plano
+// y se hashea en el servidor
 #[derive(Serialize, Deserialize)]
-struct Usuario {
-    id: Option<i32>,
+struct NuevoUsuario {
     nombre: String,
     apellido: String,
     telefono: String,
@@ -101,7 +189,9 @@ struct Usuario {
     fecha_nacimiento: NaiveDate,
     sexo: String,
     rol_id: i32,
-    contrasena_hash: String,
+    contrasena: String,
+    // Solo para médicos, p. ej. "Medicina interna"
+    especialidad: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -115,6 +205,27 @@ struct UsuarioConRol {
     sexo: String,
     rol_id: i32,
     rol_nombre: String,
+    activo: bool,
+    especialidad: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ActualizarUsuario {
+    nombre: String,
+    apellido: String,
+    telefono: String,
+    email: String,
+    fecha_nacimiento: NaiveDate,
+    sexo: String,
+    rol_id: i32,
+    activo: bool,
+    especialidad: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CambiarMiContrasenaRequest {
+    contrasena_actual: String,
+    nueva_contrasena: String,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -143,10 +254,40 @@ struct Cita {
     paciente_id: i32,
     usuario_id: i32,
     fecha_hora: NaiveDateTime,
-    estado: String,
+    // Solo cambia por los endpoints de transición; ver `estados_cita`
+    #[serde(default)]
+    estado: EstadoCita,
+    motivo: Option<String>,
+    // Serie recurrente a la que pertenece; se asigna al crear la serie
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    serie_id: Option<i32>,
+}
+
+// Cuerpo de POST y PUT /citas. `estado` es opcional: si se omite se toma
+// el actual (programada al crear) y si se envía debe coincidir con él
+#[derive(Deserialize)]
+struct CitaEntrada {
+    paciente_id: i32,
+    usuario_id: i32,
+    fecha_hora: NaiveDateTime,
+    estado: Option<EstadoCita>,
     motivo: Option<String>,
 }
 
+impl CitaEntrada {
+    fn en_estado(self, actual: EstadoCita) -> Cita {
+        Cita {
+            id: None,
+            paciente_id: self.paciente_id,
+            usuario_id: self.usuario_id,
+            fecha_hora: self.fecha_hora,
+            estado: self.estado.unwrap_or(actual),
+            motivo: self.motivo,
+            serie_id: None,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 struct CitaConDetalles {
     id: i32,
@@ -157,8 +298,10 @@ struct CitaConDetalles {
     nombre_medico: String,
     apellido_medico: String,
     fecha_hora: NaiveDateTime,
-    estado: String,
+    estado: EstadoCita,
     motivo: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    serie_id: Option<i32>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -231,179 +374,816 @@ struct NuevoExamenDiagnostico {
     resultado: Option<String>,
 }
 
+// --- VALIDACIONES ---
+
+const DIAS_SEMANA: [&str; 9] = [
+    "lunes", "martes", "miércoles", "miercoles", "jueves", "viernes", "sábado", "sabado", "domingo",
+];
+
+impl Validar for Paciente {
+    fn validar(&self) -> Vec<ErrorCampo> {
+        let mut errores = Errores::default();
+        errores.requerido("nombre", &self.nombre, 100);
+        errores.requerido("apellido", &self.apellido, 100);
+        self.validar_ci(&mut errores);
+        if !self.telefono.trim().is_empty() {
+            errores.telefono("telefono", &self.telefono);
+        }
+        if !self.email.trim().is_empty() {
+            errores.email("email", &self.email);
+        }
+        errores.fecha_nacimiento("fecha_nacimiento", self.fecha_nacimiento);
+        errores.sexo("sexo", &self.sexo);
+
+        let mut errores = errores.terminar();
+        errores.extend(self.complementarios.validar());
+        errores
+    }
+}
+
+impl Paciente {
+    fn validar_ci(&self, errores: &mut Errores) {
+        let representante = self
+            .ci_representante
+            .as_deref()
+            .map(str::trim)
+            .filter(|ci| !ci.is_empty());
+
+        let base_representante = match representante.map(Cedula::parse) {
+            Some(Ok(cedula)) if !cedula.es_de_menor() => Some(cedula.base()),
+            Some(Ok(_)) => {
+                errores.agregar("ci_representante", "La cédula del representante no puede llevar sufijo de menor");
+                None
+            }
+            Some(Err(mensaje)) => {
+                errores.agregar("ci_representante", mensaje);
+                None
+            }
+            None => None,
+        };
+
+        if self.ci.trim().is_empty() {
+            let edad = Local::now().date_naive().years_since(self.fecha_nacimiento);
+            if representante.is_none() {
+                errores.agregar("ci", "Indica la cédula del paciente o la de su representante");
+            } else if edad.is_some_and(|edad| edad >= 18) {
+                errores.agregar(
+                    "ci_representante",
+                    "Solo los menores de edad pueden registrarse con la cédula de un representante",
+                );
+            }
+            return;
+        }
+
+        match Cedula::parse(&self.ci) {
+            Err(mensaje) => errores.agregar("ci", mensaje),
+            Ok(cedula) if cedula.es_de_menor() && base_representante != Some(cedula.base()) => errores.agregar(
+                "ci",
+                "Una cédula con sufijo de menor requiere la cédula del representante correspondiente",
+            ),
+            Ok(_) => {}
+        }
+    }
+}
+
+impl Validar for NuevoExpedienteDiagnostico {
+    fn validar(&self) -> Vec<ErrorCampo> {
+        let mut errores = Errores::default();
+        errores.requerido("diagnostico", &self.diagnostico, 2000);
+        errores.opcional("tratamiento", self.tratamiento.as_deref(), 2000);
+        errores.terminar()
+    }
+}
+
+// Reglas compartidas por el alta y la edición de usuarios
+#[allow(clippy::too_many_arguments)]
+fn validar_datos_usuario(
+    errores: &mut Errores,
+    nombre: &str,
+    apellido: &str,
+    telefono: &str,
+    email: &str,
+    fecha_nacimiento: NaiveDate,
+    sexo: &str,
+    rol_id: i32,
+) {
+    errores.requerido("nombre", nombre, 100);
+    errores.requerido("apellido", apellido, 100);
+    if !telefono.trim().is_empty() {
+        errores.telefono("telefono", telefono);
+    }
+    errores.email("email", email);
+    errores.fecha_nacimiento("fecha_nacimiento", fecha_nacimiento);
+    errores.sexo("sexo", sexo);
+    errores.id("rol_id", rol_id);
+}
+
+impl Validar for NuevoUsuario {
+    fn validar(&self) -> Vec<ErrorCampo> {
+        let mut errores = Errores::default();
+        validar_datos_usuario(
+            &mut errores,
+            &self.nombre,
+            &self.apellido,
+            &self.telefono,
+            &self.email,
+            self.fecha_nacimiento,
+            &self.sexo,
+            self.rol_id,
+        );
+        errores.opcional("especialidad", self.especialidad.as_deref(), 100);
+        if self.contrasena.is_empty() {
+            errores.agregar("contrasena", "Este campo es obligatorio");
+        }
+        errores.terminar()
+    }
+}
+
+impl Validar for ActualizarUsuario {
+    fn validar(&self) -> Vec<ErrorCampo> {
+        let mut errores = Errores::default();
+        validar_datos_usuario(
+            &mut errores,
+            &self.nombre,
+            &self.apellido,
+            &self.telefono,
+            &self.email,
+            self.fecha_nacimiento,
+            &self.sexo,
+            self.rol_id,
+        );
+        errores.opcional("especialidad", self.especialidad.as_deref(), 100);
+        errores.terminar()
+    }
+}
+
+impl Validar for Horario {
+    fn validar(&self) -> Vec<ErrorCampo> {
+        let mut errores = Errores::default();
+        errores.id("usuario_id", self.usuario_id);
+        if !DIAS_SEMANA.contains(&self.dia_semana.trim().to_lowercase().as_str()) {
+            errores.agregar("dia_semana", "El día debe ser un día de la semana (lunes a domingo)");
+        }
+        if self.hora_fin <= self.hora_inicio {
+            errores.agregar("hora_fin", "La hora de fin debe ser posterior a la hora de inicio");
+        }
+        errores.terminar()
+    }
+}
+
+impl Validar for Cita {
+    fn validar(&self) -> Vec<ErrorCampo> {
+        let mut errores = Errores::default();
+        errores.id("paciente_id", self.paciente_id);
+        errores.id("usuario_id", self.usuario_id);
+        errores.opcional("motivo", self.motivo.as_deref(), 500);
+        errores.terminar()
+    }
+}
+
+impl Validar for CitaEntrada {
+    fn validar(&self) -> Vec<ErrorCampo> {
+        let mut errores = Errores::default();
+        errores.id("paciente_id", self.paciente_id);
+        errores.id("usuario_id", self.usuario_id);
+        errores.opcional("motivo", self.motivo.as_deref(), 500);
+        errores.terminar()
+    }
+}
+
+impl Validar for NuevoExamenDiagnostico {
+    fn validar(&self) -> Vec<ErrorCampo> {
+        let mut errores = Errores::default();
+        errores.id("examen_id", self.examen_id);
+        errores.opcional("resultado", self.resultado.as_deref(), 2000);
+        errores.terminar()
+    }
+}
+
 // --- FUNCIONES DE RUTAS ---
 
+#[derive(Deserialize)]
+struct FiltroPacientes {
+    nombre: Option<String>,
+    ci: Option<String>,
+    sexo: Option<String>,
+    // true lista solo los archivados; por defecto solo los activos
+    archivados: Option<bool>,
+}
+
 // GET /pacientes
-async fn get_pacientes(State(pool): State<PgPool>) -> Result<Json<Vec<PacienteConEdad>>, StatusCode> {
+async fn get_pacientes(
+    State(pool): State<PgPool>,
+    actor: Actor,
+    OriginalUri(uri): OriginalUri,
+    Query(paginacion): Query<Paginacion>,
+    Query(filtro): Query<FiltroPacientes>,
+) -> Result<Response, ErrorApi> {
+    let (rows, total) = paginacion::listar(
+        &pool,
+        "pacientes",
+        "id, nombre, apellido, ci, ci_representante, telefono, email, fecha_nacimiento, sexo, grupo_sanguineo, alergias, direccion_estado, direccion_municipio, direccion_parroquia, direccion_detalle, contacto_emergencia_nombre, contacto_emergencia_telefono, contacto_emergencia_parentesco, aseguradora, numero_poliza, archivado_en, EXTRACT(YEAR FROM AGE(fecha_nacimiento))::INTEGER AS edad",
+        "FROM pacientes WHERE TRUE",
+        |consulta| {
+            if filtro.archivados == Some(true) {
+                consulta.push(" AND archivado_en IS NOT NULL");
+            } else {
+                consulta.push(" AND archivado_en IS NULL");
+            }
+            if let Some(nombre) = &filtro.nombre {
+                consulta
+                    .push(" AND (nombre || ' ' || apellido) ILIKE ")
+                    .push_bind(paginacion::contiene(nombre));
+            }
+            if let Some(ci) = &filtro.ci {
+                let ci = Cedula::parse(ci).map(|c| c.canonica()).unwrap_or_else(|_| ci.trim().to_string());
+                consulta.push(" AND ci = ").push_bind(ci);
+            }
+            if let Some(sexo) = &filtro.sexo {
+                consulta.push(" AND sexo = ").push_bind(sexo.clone());
+            }
+        },
+        &paginacion,
+        &[
+            ("id", "id"),
+            ("nombre", "nombre"),
+            ("apellido", "apellido"),
+            ("ci", "ci"),
+            ("fecha_nacimiento", "fecha_nacimiento"),
+        ],
+        "id",
+    )
+    .await?;
+
+    let pacientes: Vec<PacienteConEdad> = rows
+        .into_iter()
+        .map(|row| paciente_con_edad_desde_fila(&row))
+        .collect();
+
+    let lecturas: Vec<(i32, i32)> = pacientes.iter().map(|p| (p.id, p.id)).collect();
+    auditar_lecturas(&pool, &actor, "pacientes", &lecturas).await?;
+
+    Ok(Pagina::new(pacientes, total, &paginacion).responder(&uri))
+}
+
+#[derive(Deserialize)]
+struct BusquedaPacientes {
+    q: String,
+    limite: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct PacienteEncontrado {
+    #[serde(flatten)]
+    paciente: PacienteConEdad,
+    similitud: f32,
+}
+
+// GET /pacientes/buscar?q=
+async fn buscar_pacientes(
+    State(pool): State<PgPool>,
+    actor: Actor,
+    Query(busqueda): Query<BusquedaPacientes>,
+) -> Result<Json<Vec<PacienteEncontrado>>, ErrorApi> {
+    // Puntos y guiones de cédulas y teléfonos no cuentan, salvo en emails
+    let q = if busqueda.q.contains('@') {
+        busqueda.q.trim().to_string()
+    } else {
+        busqueda.q.trim().replace(['.', '-'], "")
+    };
+
+    if q.chars().count() < 2 {
+        return Err(ErrorApi::Validacion(vec![ErrorCampo::new(
+            "q",
+            "La búsqueda debe tener al menos 2 caracteres",
+        )]));
+    }
+
     let rows = sqlx::query(
-        "SELECT id, nombre, apellido, ci, telefono, email, fecha_nacimiento, sexo, EXTRACT(YEAR FROM AGE(fecha_nacimiento))::INTEGER AS edad FROM pacientes"
+        "SELECT id, nombre, apellido, ci, ci_representante, telefono, email, fecha_nacimiento, sexo, grupo_sanguineo, alergias, direccion_estado, direccion_municipio, direccion_parroquia, direccion_detalle, contacto_emergencia_nombre, contacto_emergencia_telefono, contacto_emergencia_parentesco, aseguradora, numero_poliza, archivado_en, EXTRACT(YEAR FROM AGE(fecha_nacimiento))::INTEGER AS edad, word_similarity(b.termino, texto_busqueda_paciente(nombre, apellido, ci, telefono, email)) AS similitud, texto_busqueda_paciente(nombre, apellido, ci, telefono, email) LIKE b.patron AS contiene FROM pacientes, (SELECT lower(sin_acentos($1)) AS termino, lower(sin_acentos($2)) AS patron) b WHERE archivado_en IS NULL AND (b.termino <% texto_busqueda_paciente(nombre, apellido, ci, telefono, email) OR texto_busqueda_paciente(nombre, apellido, ci, telefono, email) LIKE b.patron) ORDER BY contiene DESC, similitud DESC, apellido, id LIMIT $3"
     )
+    .bind(&q)
+    .bind(paginacion::contiene(&q))
+    .bind(busqueda.limite.unwrap_or(20).clamp(1, 50))
     .fetch_all(&pool)
     .await
     .map_err(|e| {
-        eprintln!("Error en la consulta: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        eprintln!("Error al buscar pacientes: {}", e);
+        ErrorApi::from(e)
     })?;
 
-    let pacientes: Vec<PacienteConEdad> = rows
+    let pacientes: Vec<PacienteEncontrado> = rows
         .into_iter()
-        .map(|row| PacienteConEdad {
-            id: row.get("id"),
-            nombre: row.get("nombre"),
-            apellido: row.get("apellido"),
-            ci: row.get("ci"),
-            telefono: row.get("telefono"),
-            email: row.get("email"),
-            fecha_nacimiento: row.get("fecha_nacimiento"),
-            sexo: row.get("sexo"),
-            edad: row.get("edad"),
+        .map(|row| PacienteEncontrado {
+            paciente: paciente_con_edad_desde_fila(&row),
+            similitud: if row.get::<bool, _>("contiene") { 1.0 } else { row.get("similitud") },
         })
         .collect();
 
+    let lecturas: Vec<(i32, i32)> = pacientes.iter().map(|p| (p.paciente.id, p.paciente.id)).collect();
+    auditar_lecturas(&pool, &actor, "pacientes", &lecturas).await?;
+
     Ok(Json(pacientes))
 }
 
 // POST /pacientes
 async fn create_paciente(
     State(pool): State<PgPool>,
-    Json(paciente): Json<Paciente>,
-) -> Result<Json<Paciente>, StatusCode> {
-    let result = sqlx::query(
-        "INSERT INTO pacientes (nombre, apellido, ci, telefono, email, fecha_nacimiento, sexo) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id"
+    actor: Actor,
+    ValidatedJson(paciente): ValidatedJson<Paciente>,
+) -> Result<ConVersion<Paciente>, ErrorApi> {
+    let mut tx = iniciar_transaccion(&pool).await?;
+    let (ci, ci_representante) = resolver_ci(&mut tx, &paciente, None).await?;
+
+    let consulta = sqlx::query(
+        "INSERT INTO pacientes (nombre, apellido, ci, ci_representante, telefono, email, fecha_nacimiento, sexo, grupo_sanguineo, alergias, direccion_estado, direccion_municipio, direccion_parroquia, direccion_detalle, contacto_emergencia_nombre, contacto_emergencia_telefono, contacto_emergencia_parentesco, aseguradora, numero_poliza) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19) RETURNING id, nombre, apellido, ci, ci_representante, telefono, email, fecha_nacimiento, sexo, grupo_sanguineo, alergias, direccion_estado, direccion_municipio, direccion_parroquia, direccion_detalle, contacto_emergencia_nombre, contacto_emergencia_telefono, contacto_emergencia_parentesco, aseguradora, numero_poliza, version"
     )
     .bind(&paciente.nombre)
     .bind(&paciente.apellido)
-    .bind(&paciente.ci)
+    .bind(&ci)
+    .bind(&ci_representante)
     .bind(&paciente.telefono)
     .bind(&paciente.email)
     .bind(paciente.fecha_nacimiento)
-    .bind(&paciente.sexo)
-    .fetch_one(&pool)
-    .await
-    .map_err(|e| {
-        eprintln!("Error al crear paciente: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .bind(&paciente.sexo);
 
-    let id = result.get("id");
+    let row = paciente
+        .complementarios
+        .vincular(consulta)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al crear paciente: {}", e);
+            ErrorApi::from(e)
+        })?;
 
-    Ok(Json(Paciente {
-        id: Some(id),
-        nombre: paciente.nombre,
-        apellido: paciente.apellido,
-        ci: paciente.ci,
-        telefono: paciente.telefono,
-        email: paciente.email,
-        fecha_nacimiento: paciente.fecha_nacimiento,
-        sexo: paciente.sexo,
-    }))
+    let creado = paciente_desde_fila(&row);
+    let id = row.get("id");
+
+    auditar(
+        &mut *tx,
+        &actor,
+        Evento::cambio("crear", "pacientes", id, Some(id), None, auditoria::valor(&creado)),
+    )
+    .await?;
+    confirmar_transaccion(tx).await?;
+
+    Ok(ConVersion(row.get("version"), creado))
 }
 
 // GET /pacientes/:id
 async fn get_paciente_by_id(
     Path(id): Path<i32>,
     State(pool): State<PgPool>,
-) -> Result<Json<PacienteConEdad>, StatusCode> {
+    actor: Actor,
+) -> Result<ConVersion<PacienteConEdad>, ErrorApi> {
     let row = sqlx::query(
-        "SELECT id, nombre, apellido, ci, telefono, email, fecha_nacimiento, sexo, EXTRACT(YEAR FROM AGE(fecha_nacimiento))::INTEGER AS edad FROM pacientes WHERE id = $1"
+        "SELECT id, nombre, apellido, ci, ci_representante, telefono, email, fecha_nacimiento, sexo, grupo_sanguineo, alergias, direccion_estado, direccion_municipio, direccion_parroquia, direccion_detalle, contacto_emergencia_nombre, contacto_emergencia_telefono, contacto_emergencia_parentesco, aseguradora, numero_poliza, archivado_en, version, EXTRACT(YEAR FROM AGE(fecha_nacimiento))::INTEGER AS edad FROM pacientes WHERE id = $1 AND archivado_en IS NULL"
     )
     .bind(id)
-    .fetch_one(&pool)
+    .fetch_optional(&pool)
     .await
     .map_err(|e| {
         eprintln!("Error al obtener paciente: {}", e);
-        if e.as_database_error().map_or(false, |db_err| {
-            db_err.code().map_or(false, |code| code == "23505")
-        }) {
-            StatusCode::CONFLICT
-        } else {
-            StatusCode::NOT_FOUND
-        }
-    })?;
+        ErrorApi::from(e)
+    })?
+    .ok_or_else(|| ErrorApi::no_encontrado("Paciente"))?;
+
+    let paciente = paciente_con_edad_desde_fila(&row);
+
+    auditar(&pool, &actor, Evento::lectura("pacientes", Some(id), Some(id))).await?;
+
+    Ok(ConVersion(row.get("version"), paciente))
+}
 
-    let paciente = PacienteConEdad {
+fn paciente_con_edad_desde_fila(row: &sqlx::postgres::PgRow) -> PacienteConEdad {
+    PacienteConEdad {
         id: row.get("id"),
         nombre: row.get("nombre"),
         apellido: row.get("apellido"),
         ci: row.get("ci"),
+        ci_representante: row.get("ci_representante"),
         telefono: row.get("telefono"),
         email: row.get("email"),
         fecha_nacimiento: row.get("fecha_nacimiento"),
         sexo: row.get("sexo"),
         edad: row.get("edad"),
+        complementarios: DatosComplementarios::desde_fila(row),
+        archivado_en: row.get("archivado_en"),
+    }
+}
+
+fn paciente_desde_fila(row: &sqlx::postgres::PgRow) -> Paciente {
+    Paciente {
+        id: row.get("id"),
+        nombre: row.get("nombre"),
+        apellido: row.get("apellido"),
+        ci: row.get("ci"),
+        ci_representante: row.get("ci_representante"),
+        telefono: row.get("telefono"),
+        email: row.get("email"),
+        fecha_nacimiento: row.get("fecha_nacimiento"),
+        sexo: row.get("sexo"),
+        complementarios: DatosComplementarios::desde_fila(row),
+    }
+}
+
+// Cédula canónica con la que se guarda el paciente, junto con la del
+// representante. Un menor sin cédula propia conserva la que ya tenía con el
+// mismo representante o recibe la siguiente secuencia libre.
+async fn resolver_ci(
+    conn: &mut sqlx::PgConnection,
+    paciente: &Paciente,
+    actual: Option<&Paciente>,
+) -> Result<(String, Option<String>), ErrorApi> {
+    let representante = match paciente.ci_representante.as_deref().map(str::trim) {
+        Some(ci) if !ci.is_empty() => Some(
+            Cedula::parse(ci)
+                .map_err(|mensaje| ErrorApi::Validacion(vec![ErrorCampo::new("ci_representante", mensaje)]))?
+                .base(),
+        ),
+        _ => None,
     };
 
-    Ok(Json(paciente))
+    if !paciente.ci.trim().is_empty() {
+        let cedula = Cedula::parse(&paciente.ci)
+            .map_err(|mensaje| ErrorApi::Validacion(vec![ErrorCampo::new("ci", mensaje)]))?;
+        return Ok((cedula.canonica(), representante));
+    }
+
+    let Some(representante) = representante else {
+        return Err(ErrorApi::Validacion(vec![ErrorCampo::new(
+            "ci",
+            "Indica la cédula del paciente o la de su representante",
+        )]));
+    };
+
+    if let Some(actual) = actual {
+        if cedula::secuencia_de_menor(&actual.ci, &representante).is_some() {
+            return Ok((actual.ci.clone(), Some(representante)));
+        }
+    }
+
+    // Serializa las altas de menores de un mismo representante
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+        .bind(&representante)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al bloquear la secuencia de menores: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    let rows = sqlx::query("SELECT ci FROM pacientes WHERE ci LIKE $1 || '-__'")
+        .bind(&representante)
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al obtener menores del representante: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    let siguiente = rows
+        .iter()
+        .filter_map(|row| cedula::secuencia_de_menor(row.get("ci"), &representante))
+        .max()
+        .unwrap_or(0)
+        + 1;
+
+    if siguiente > 99 {
+        return Err(ErrorApi::Conflicto {
+            mensaje: "El representante ya tiene el máximo de menores registrados".to_string(),
+            detalles: None,
+        });
+    }
+
+    Ok((cedula::de_menor(&representante, siguiente), Some(representante)))
+}
+
+// Estado actual del paciente y su versión, bloqueado hasta el fin de la
+// transacción, para registrar el "antes" de un cambio
+async fn paciente_actual<'e, E: sqlx::PgExecutor<'e>>(executor: E, id: i32) -> Result<(Paciente, i32), ErrorApi> {
+    let row = sqlx::query(
+        "SELECT id, nombre, apellido, ci, ci_representante, telefono, email, fecha_nacimiento, sexo, grupo_sanguineo, alergias, direccion_estado, direccion_municipio, direccion_parroquia, direccion_detalle, contacto_emergencia_nombre, contacto_emergencia_telefono, contacto_emergencia_parentesco, aseguradora, numero_poliza, version FROM pacientes WHERE id = $1 AND archivado_en IS NULL FOR UPDATE"
+    )
+    .bind(id)
+    .fetch_optional(executor)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al obtener paciente: {}", e);
+        ErrorApi::from(e)
+    })?
+    .ok_or_else(|| ErrorApi::no_encontrado("Paciente"))?;
+
+    Ok((paciente_desde_fila(&row), row.get("version")))
 }
 
 // PUT /pacientes/:id
 async fn update_paciente(
     Path(id): Path<i32>,
     State(pool): State<PgPool>,
-    Json(paciente): Json<Paciente>,
-) -> Result<Json<Paciente>, StatusCode> {
-    sqlx::query(
-        "UPDATE pacientes SET nombre = $1, apellido = $2, ci = $3, telefono = $4, email = $5, fecha_nacimiento = $6, sexo = $7 WHERE id = $8"
+    actor: Actor,
+    if_match: IfMatch,
+    ValidatedJson(paciente): ValidatedJson<Paciente>,
+) -> Result<ConVersion<Paciente>, ErrorApi> {
+    let mut tx = iniciar_transaccion(&pool).await?;
+    let (antes, version) = paciente_actual(&mut *tx, id).await?;
+    if_match.verificar(version)?;
+    let (actualizado, version) = actualizar_paciente(&mut tx, &actor, id, antes, paciente).await?;
+    confirmar_transaccion(tx).await?;
+
+    Ok(ConVersion(version, actualizado))
+}
+
+// PATCH /pacientes/:id
+// Modifica solo los campos enviados; el resultado se valida completo
+async fn patch_paciente(
+    Path(id): Path<i32>,
+    State(pool): State<PgPool>,
+    actor: Actor,
+    if_match: IfMatch,
+    ValidatedJson(parche): ValidatedJson<Parche>,
+) -> Result<ConVersion<Paciente>, ErrorApi> {
+    let mut tx = iniciar_transaccion(&pool).await?;
+    let (antes, version) = paciente_actual(&mut *tx, id).await?;
+    if_match.verificar(version)?;
+    let paciente = parche.aplicar(&antes)?;
+    let (actualizado, version) = actualizar_paciente(&mut tx, &actor, id, antes, paciente).await?;
+    confirmar_transaccion(tx).await?;
+
+    Ok(ConVersion(version, actualizado))
+}
+
+// Guarda el nuevo estado del paciente y registra el cambio en la auditoría.
+// Devuelve el paciente guardado con su nueva versión.
+async fn actualizar_paciente(
+    conn: &mut sqlx::PgConnection,
+    actor: &Actor,
+    id: i32,
+    antes: Paciente,
+    paciente: Paciente,
+) -> Result<(Paciente, i32), ErrorApi> {
+    let (ci, ci_representante) = resolver_ci(&mut *conn, &paciente, Some(&antes)).await?;
+
+    let consulta = sqlx::query(
+        "UPDATE pacientes SET nombre = $2, apellido = $3, ci = $4, ci_representante = $5, telefono = $6, email = $7, fecha_nacimiento = $8, sexo = $9, grupo_sanguineo = $10, alergias = $11, direccion_estado = $12, direccion_municipio = $13, direccion_parroquia = $14, direccion_detalle = $15, contacto_emergencia_nombre = $16, contacto_emergencia_telefono = $17, contacto_emergencia_parentesco = $18, aseguradora = $19, numero_poliza = $20 WHERE id = $1 RETURNING id, nombre, apellido, ci, ci_representante, telefono, email, fecha_nacimiento, sexo, grupo_sanguineo, alergias, direccion_estado, direccion_municipio, direccion_parroquia, direccion_detalle, contacto_emergencia_nombre, contacto_emergencia_telefono, contacto_emergencia_parentesco, aseguradora, numero_poliza, version"
     )
+    .bind(id)
     .bind(&paciente.nombre)
     .bind(&paciente.apellido)
-    .bind(&paciente.ci)
+    .bind(&ci)
+    .bind(&ci_representante)
     .bind(&paciente.telefono)
     .bind(&paciente.email)
     .bind(paciente.fecha_nacimiento)
-    .bind(&paciente.sexo)
+    .bind(&paciente.sexo);
+
+    let row = paciente
+        .complementarios
+        .vincular(consulta)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al actualizar paciente: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    let actualizado = paciente_desde_fila(&row);
+
+    auditar(
+        &mut *conn,
+        actor,
+        Evento::cambio(
+            "actualizar",
+            "pacientes",
+            id,
+            Some(id),
+            auditoria::valor(&antes),
+            auditoria::valor(&actualizado),
+        ),
+    )
+    .await?;
+
+    Ok((actualizado, row.get("version")))
+}
+
+// DELETE /pacientes/:id
+// Archiva al paciente: deja de aparecer en listados y búsquedas, pero su
+// historia clínica se conserva y puede restaurarse.
+async fn delete_paciente(
+    Path(id): Path<i32>,
+    State(pool): State<PgPool>,
+    actor: Actor,
+) -> Result<StatusCode, ErrorApi> {
+    let mut tx = iniciar_transaccion(&pool).await?;
+    let (antes, _) = paciente_actual(&mut *tx, id).await?;
+
+    // Sus citas pendientes se cancelan para liberar la agenda del médico y
+    // que no salgan más recordatorios
+    let citas = sqlx::query(
+        "SELECT id FROM citas WHERE paciente_id = $1 AND estado IN ('programada', 'confirmada') AND fecha_hora > $2 ORDER BY fecha_hora"
+    )
     .bind(id)
-    .execute(&pool)
+    .bind(Local::now().naive_local())
+    .fetch_all(&mut *tx)
     .await
     .map_err(|e| {
-        eprintln!("Error al actualizar paciente: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        eprintln!("Error al obtener citas del paciente: {}", e);
+        ErrorApi::from(e)
     })?;
 
-    Ok(Json(Paciente {
-        id: Some(id),
-        nombre: paciente.nombre,
-        apellido: paciente.apellido,
-        ci: paciente.ci,
-        telefono: paciente.telefono,
-        email: paciente.email,
-        fecha_nacimiento: paciente.fecha_nacimiento,
-        sexo: paciente.sexo,
-    }))
+    for row in citas {
+        let cita_id: i32 = row.get("id");
+        let (cita, _) = cita_actual(&mut *tx, cita_id).await?;
+        estados_cita::transicion(&mut tx, &actor, cita_id, cita, EstadoCita::Cancelada, Some("Paciente archivado")).await?;
+    }
+
+    sqlx::query("UPDATE pacientes SET archivado_en = NOW(), archivado_por = $1 WHERE id = $2")
+        .bind(actor.usuario_id)
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al archivar paciente: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    auditar(
+        &mut *tx,
+        &actor,
+        Evento::cambio("archivar", "pacientes", id, Some(id), auditoria::valor(&antes), None),
+    )
+    .await?;
+    confirmar_transaccion(tx).await?;
+
+    Ok(StatusCode::NO_CONTENT)
 }
 
-// DELETE /pacientes/:id
-async fn delete_paciente(
+// POST /pacientes/:id/restaurar
+// Un paciente archivado por una fusión también se restaura, y deja de
+// apuntar al conservado; sus citas y diagnósticos siguen en el conservado.
+async fn restaurar_paciente(
     Path(id): Path<i32>,
     State(pool): State<PgPool>,
-) -> Result<StatusCode, StatusCode> {
-    sqlx::query("DELETE FROM pacientes WHERE id = $1")
+    actor: Actor,
+) -> Result<ConVersion<Paciente>, ErrorApi> {
+    let mut tx = iniciar_transaccion(&pool).await?;
+
+    let archivado = sqlx::query("SELECT archivado_en IS NOT NULL AS archivado FROM pacientes WHERE id = $1 FOR UPDATE")
         .bind(id)
-        .execute(&pool)
+        .fetch_optional(&mut *tx)
         .await
         .map_err(|e| {
-            eprintln!("Error al eliminar paciente: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+            eprintln!("Error al obtener paciente: {}", e);
+            ErrorApi::from(e)
+        })?
+        .ok_or_else(|| ErrorApi::no_encontrado("Paciente"))?
+        .get::<bool, _>("archivado");
+
+    if !archivado {
+        return Err(ErrorApi::EstadoInvalido {
+            mensaje: "El paciente no está archivado".to_string(),
+            detalles: None,
+        });
+    }
+
+    let row = sqlx::query(
+        "UPDATE pacientes SET archivado_en = NULL, archivado_por = NULL, fusionado_en = NULL WHERE id = $1 RETURNING id, nombre, apellido, ci, ci_representante, telefono, email, fecha_nacimiento, sexo, grupo_sanguineo, alergias, direccion_estado, direccion_municipio, direccion_parroquia, direccion_detalle, contacto_emergencia_nombre, contacto_emergencia_telefono, contacto_emergencia_parentesco, aseguradora, numero_poliza, version"
+    )
+    .bind(id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al restaurar paciente: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    let restaurado = paciente_desde_fila(&row);
+
+    auditar(
+        &mut *tx,
+        &actor,
+        Evento::cambio("restaurar", "pacientes", id, Some(id), None, auditoria::valor(&restaurado)),
+    )
+    .await?;
+    confirmar_transaccion(tx).await?;
+
+    Ok(ConVersion(row.get("version"), restaurado))
+}
+
+// DELETE /pacientes/:id/purgar
+// Borrado definitivo de un paciente archivado y toda su historia clínica.
+// Solo procede cuando el período de retención, contado desde la última
+// actividad clínica o el archivado, ya expiró.
+async fn purgar_paciente(
+    Path(id): Path<i32>,
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    actor: Actor,
+) -> Result<StatusCode, ErrorApi> {
+    let mut tx = iniciar_transaccion(&pool).await?;
+
+    let row = sqlx::query(
+        "SELECT p.id, p.nombre, p.apellido, p.ci, p.ci_representante, p.telefono, p.email, p.fecha_nacimiento, p.sexo, p.grupo_sanguineo, p.alergias, p.direccion_estado, p.direccion_municipio, p.direccion_parroquia, p.direccion_detalle, p.contacto_emergencia_nombre, p.contacto_emergencia_telefono, p.contacto_emergencia_parentesco, p.aseguradora, p.numero_poliza, p.archivado_en, GREATEST(p.archivado_en, (SELECT MAX(ed.fecha_registro) FROM expedientes_diagnosticos ed JOIN expedientes e ON ed.expediente_id = e.id WHERE e.paciente_id = p.id), (SELECT MAX(c.fecha_hora) FROM citas c WHERE c.paciente_id = p.id)) + make_interval(years => $2) AS retencion_hasta FROM pacientes p WHERE p.id = $1 FOR UPDATE"
+    )
+    .bind(id)
+    .bind(config.retencion_anios)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al obtener paciente: {}", e);
+        ErrorApi::from(e)
+    })?
+    .ok_or_else(|| ErrorApi::no_encontrado("Paciente"))?;
+
+    if row.get::<Option<NaiveDateTime>, _>("archivado_en").is_none() {
+        return Err(ErrorApi::EstadoInvalido {
+            mensaje: "Solo se pueden purgar pacientes archivados".to_string(),
+            detalles: None,
+        });
+    }
+
+    let retencion_hasta: NaiveDateTime = row.get("retencion_hasta");
+    if retencion_hasta > Local::now().naive_local() {
+        return Err(ErrorApi::EstadoInvalido {
+            mensaje: "La historia clínica del paciente aún está en período de retención".to_string(),
+            detalles: Some(serde_json::json!({ "retencion_hasta": retencion_hasta })),
+        });
+    }
+
+    let antes = paciente_desde_fila(&row);
+
+    for (sql, contexto) in [
+        ("DELETE FROM expedientes_diagnosticos_examenes WHERE expediente_diagnostico_id IN (SELECT ed.id FROM expedientes_diagnosticos ed JOIN expedientes e ON ed.expediente_id = e.id WHERE e.paciente_id = $1)", "exámenes"),
+        ("DELETE FROM expedientes_diagnosticos WHERE expediente_id IN (SELECT id FROM expedientes WHERE paciente_id = $1)", "diagnósticos"),
+        ("DELETE FROM expedientes WHERE paciente_id = $1", "expediente"),
+        ("DELETE FROM citas WHERE paciente_id = $1", "citas"),
+        ("DELETE FROM fusiones_pacientes WHERE paciente_conservado_id = $1", "fusiones"),
+        ("DELETE FROM pacientes WHERE id = $1", "paciente"),
+    ] {
+        sqlx::query(sql)
+            .bind(id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| {
+                eprintln!("Error al purgar {} del paciente {}: {}", contexto, id, e);
+                ErrorApi::from(e)
+            })?;
+    }
+
+    auditar(
+        &mut *tx,
+        &actor,
+        Evento::cambio("purgar", "pacientes", id, Some(id), auditoria::valor(&antes), None),
+    )
+    .await?;
+    confirmar_transaccion(tx).await?;
 
     Ok(StatusCode::NO_CONTENT)
 }
 
+// Rechaza operaciones clínicas nuevas sobre pacientes archivados. El
+// paciente queda bloqueado (FOR SHARE) para que no se archive mientras tanto.
+async fn exigir_paciente_activo(conn: &mut sqlx::PgConnection, id: i32) -> Result<(), ErrorApi> {
+    let row = sqlx::query("SELECT archivado_en IS NOT NULL AS archivado FROM pacientes WHERE id = $1 FOR SHARE")
+        .bind(id)
+        .fetch_optional(conn)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al obtener paciente {}: {}", id, e);
+            ErrorApi::from(e)
+        })?;
+
+    match row {
+        None => Err(ErrorApi::ReferenciaInvalida {
+            mensaje: "El paciente no existe".to_string(),
+            detalles: Some(serde_json::json!({ "campo": "paciente_id" })),
+        }),
+        Some(row) if row.get::<bool, _>("archivado") => Err(ErrorApi::EstadoInvalido {
+            mensaje: "El paciente está archivado; restáurelo antes de continuar".to_string(),
+            detalles: None,
+        }),
+        Some(_) => Ok(()),
+    }
+}
+
 // GET /expedientes/{paciente_id}
 async fn get_expediente_by_paciente(
     Path(paciente_id): Path<i32>,
     State(pool): State<PgPool>,
-) -> Result<Json<Expediente>, StatusCode> {
+    actor: Actor,
+) -> Result<Json<Expediente>, ErrorApi> {
     let row = sqlx::query(
         "SELECT id, paciente_id, fecha_creacion FROM expedientes WHERE paciente_id = $1"
     )
     .bind(paciente_id)
-    .fetch_one(&pool)
+    .fetch_optional(&pool)
     .await
     .map_err(|e| {
         eprintln!("Error al obtener expediente: {}", e);
-        StatusCode::NOT_FOUND
-    })?;
+        ErrorApi::from(e)
+    })?
+    .ok_or_else(|| ErrorApi::no_encontrado("Expediente"))?;
 
     let expediente = Expediente {
         id: row.get("id"),
@@ -411,6 +1191,13 @@ async fn get_expediente_by_paciente(
         fecha_creacion: row.get("fecha_creacion"),
     };
 
+    auditar(
+        &pool,
+        &actor,
+        Evento::lectura("expedientes", Some(expediente.id), Some(paciente_id)),
+    )
+    .await?;
+
     Ok(Json(expediente))
 }
 
@@ -418,15 +1205,17 @@ async fn get_expediente_by_paciente(
 async fn get_diagnosticos_by_expediente(
     Path(paciente_id): Path<i32>,
     State(pool): State<PgPool>,
-) -> Result<Json<Vec<ExpedienteDiagnostico>>, StatusCode> {
+    actor: Actor,
+) -> Result<Json<Vec<ExpedienteDiagnostico>>, ErrorApi> {
     let row = sqlx::query("SELECT id FROM expedientes WHERE paciente_id = $1")
         .bind(paciente_id)
-        .fetch_one(&pool)
+        .fetch_optional(&pool)
         .await
         .map_err(|e| {
-            eprintln!("Expediente no encontrado para paciente {}: {}", paciente_id, e);
-            StatusCode::NOT_FOUND
-        })?;
+            eprintln!("Error al obtener expediente del paciente {}: {}", paciente_id, e);
+            ErrorApi::from(e)
+        })?
+        .ok_or_else(|| ErrorApi::no_encontrado("Expediente"))?;
 
     let expediente_id: i32 = row.get("id");
 
@@ -438,7 +1227,7 @@ async fn get_diagnosticos_by_expediente(
     .await
     .map_err(|e| {
         eprintln!("Error al obtener diagnósticos: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        ErrorApi::from(e)
     })?;
 
     let diagnosticos: Vec<ExpedienteDiagnostico> = rows
@@ -452,6 +1241,13 @@ async fn get_diagnosticos_by_expediente(
         })
         .collect();
 
+    auditar(
+        &pool,
+        &actor,
+        Evento::lectura("expedientes_diagnosticos", None, Some(paciente_id)),
+    )
+    .await?;
+
     Ok(Json(diagnosticos))
 }
 
@@ -459,30 +1255,35 @@ async fn get_diagnosticos_by_expediente(
 async fn create_diagnostico(
     Path(paciente_id): Path<i32>,
     State(pool): State<PgPool>,
-    Json(diagnostico_data): Json<NuevoExpedienteDiagnostico>,
-) -> Result<Json<ExpedienteDiagnostico>, StatusCode> {
+    actor: Actor,
+    ValidatedJson(diagnostico_data): ValidatedJson<NuevoExpedienteDiagnostico>,
+) -> Result<Json<ExpedienteDiagnostico>, ErrorApi> {
     let row = sqlx::query("SELECT id FROM expedientes WHERE paciente_id = $1")
         .bind(paciente_id)
-        .fetch_one(&pool)
+        .fetch_optional(&pool)
         .await
         .map_err(|e| {
-            eprintln!("Expediente no encontrado para paciente {}: {}", paciente_id, e);
-            StatusCode::NOT_FOUND
-        })?;
+            eprintln!("Error al obtener expediente del paciente {}: {}", paciente_id, e);
+            ErrorApi::from(e)
+        })?
+        .ok_or_else(|| ErrorApi::no_encontrado("Expediente"))?;
 
     let expediente_id: i32 = row.get("id");
 
+    let mut tx = iniciar_transaccion(&pool).await?;
+    exigir_paciente_activo(&mut tx, paciente_id).await?;
+
     let result = sqlx::query(
         "INSERT INTO expedientes_diagnosticos (expediente_id, diagnostico, tratamiento) VALUES ($1, $2, $3) RETURNING id, fecha_registro"
     )
     .bind(expediente_id)
     .bind(&diagnostico_data.diagnostico)
     .bind(&diagnostico_data.tratamiento)
-    .fetch_one(&pool)
+    .fetch_one(&mut *tx)
     .await
     .map_err(|e| {
         eprintln!("Error al crear diagnóstico: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        ErrorApi::from(e)
     })?;
 
     let id = result.get("id");
@@ -496,20 +1297,78 @@ async fn create_diagnostico(
         fecha_registro,
     };
 
+    auditar(
+        &mut *tx,
+        &actor,
+        Evento::cambio(
+            "crear",
+            "expedientes_diagnosticos",
+            id,
+            Some(paciente_id),
+            None,
+            auditoria::valor(&nuevo_diagnostico),
+        ),
+    )
+    .await?;
+    confirmar_transaccion(tx).await?;
+
     Ok(Json(nuevo_diagnostico))
 }
 
+#[derive(Deserialize)]
+struct FiltroUsuarios {
+    nombre: Option<String>,
+    rol_id: Option<i32>,
+    activo: Option<bool>,
+    especialidad: Option<String>,
+}
+
 // GET /usuarios
-async fn get_usuarios(State(pool): State<PgPool>) -> Result<Json<Vec<UsuarioConRol>>, StatusCode> {
-    let rows = sqlx::query(
-        "SELECT u.id, u.nombre, u.apellido, u.telefono, u.email, u.fecha_nacimiento, u.sexo, u.rol_id, r.nombre AS rol_nombre FROM usuarios u JOIN roles r ON u.rol_id = r.id"
+async fn get_usuarios(
+    State(pool): State<PgPool>,
+    OriginalUri(uri): OriginalUri,
+    Query(paginacion): Query<Paginacion>,
+    Query(filtro): Query<FiltroUsuarios>,
+) -> Result<Response, ErrorApi> {
+    let (rows, total) = paginacion::listar(
+        &pool,
+        "usuarios",
+        "u.id, u.nombre, u.apellido, u.telefono, u.email, u.fecha_nacimiento, u.sexo, u.rol_id, r.nombre AS rol_nombre, u.activo, u.especialidad",
+        "FROM usuarios u JOIN roles r ON u.rol_id = r.id WHERE TRUE",
+        |consulta| {
+            if let Some(nombre) = &filtro.nombre {
+                let patron = paginacion::contiene(nombre);
+                consulta
+                    .push(" AND ((u.nombre || ' ' || u.apellido) ILIKE ")
+                    .push_bind(patron.clone())
+                    .push(" OR u.email ILIKE ")
+                    .push_bind(patron)
+                    .push(")");
+            }
+            if let Some(rol_id) = filtro.rol_id {
+                consulta.push(" AND u.rol_id = ").push_bind(rol_id);
+            }
+            if let Some(activo) = filtro.activo {
+                consulta.push(" AND u.activo = ").push_bind(activo);
+            }
+            if let Some(especialidad) = &filtro.especialidad {
+                consulta
+                    .push(" AND lower(sin_acentos(u.especialidad)) = lower(sin_acentos(")
+                    .push_bind(especialidad.trim().to_string())
+                    .push("))");
+            }
+        },
+        &paginacion,
+        &[
+            ("id", "u.id"),
+            ("nombre", "u.nombre"),
+            ("apellido", "u.apellido"),
+            ("email", "u.email"),
+            ("rol", "r.nombre"),
+        ],
+        "u.id",
     )
-    .fetch_all(&pool)
-    .await
-    .map_err(|e| {
-        eprintln!("Error en la consulta de usuarios: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .await?;
 
     let usuarios: Vec<UsuarioConRol> = rows
         .into_iter()
@@ -523,19 +1382,219 @@ async fn get_usuarios(State(pool): State<PgPool>) -> Result<Json<Vec<UsuarioConR
             sexo: row.get("sexo"),
             rol_id: row.get("rol_id"),
             rol_nombre: row.get("rol_nombre"),
+            activo: row.get("activo"),
+            especialidad: row.get("especialidad"),
         })
         .collect();
 
-    Ok(Json(usuarios))
+    Ok(Pagina::new(usuarios, total, &paginacion).responder(&uri))
+}
+
+async fn obtener_usuario(pool: &PgPool, id: i32) -> Result<UsuarioConRol, ErrorApi> {
+    let row = sqlx::query(
+        "SELECT u.id, u.nombre, u.apellido, u.telefono, u.email, u.fecha_nacimiento, u.sexo, u.rol_id, r.nombre AS rol_nombre, u.activo, u.especialidad FROM usuarios u JOIN roles r ON u.rol_id = r.id WHERE u.id = $1"
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al obtener usuario: {}", e);
+        ErrorApi::from(e)
+    })?
+    .ok_or_else(|| ErrorApi::no_encontrado("Usuario"))?;
+
+    Ok(UsuarioConRol {
+        id: row.get("id"),
+        nombre: row.get("nombre"),
+        apellido: row.get("apellido"),
+        telefono: row.get("telefono"),
+        email: row.get("email"),
+        fecha_nacimiento: row.get("fecha_nacimiento"),
+        sexo: row.get("sexo"),
+        rol_id: row.get("rol_id"),
+        rol_nombre: row.get("rol_nombre"),
+        activo: row.get("activo"),
+        especialidad: row.get("especialidad"),
+    })
+}
+
+// GET /usuarios/:id
+async fn get_usuario_by_id(
+    Path(id): Path<i32>,
+    State(pool): State<PgPool>,
+) -> Result<Json<UsuarioConRol>, ErrorApi> {
+    Ok(Json(obtener_usuario(&pool, id).await?))
+}
+
+// GET /usuarios/me
+async fn get_usuario_actual(
+    usuario: AuthUser,
+    State(pool): State<PgPool>,
+) -> Result<Json<UsuarioConRol>, ErrorApi> {
+    Ok(Json(obtener_usuario(&pool, usuario.usuario_id).await?))
+}
+
+// PUT /usuarios/:id
+async fn update_usuario(
+    Path(id): Path<i32>,
+    State(pool): State<PgPool>,
+    ValidatedJson(usuario): ValidatedJson<ActualizarUsuario>,
+) -> Result<Json<UsuarioConRol>, ErrorApi> {
+    let mut tx = pool.begin().await.map_err(|e| {
+        eprintln!("Error al iniciar transacción: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    let resultado = sqlx::query(
+        "UPDATE usuarios SET nombre = $1, apellido = $2, telefono = $3, email = $4, fecha_nacimiento = $5, sexo = $6, rol_id = $7, activo = $8, especialidad = $9 WHERE id = $10"
+    )
+    .bind(&usuario.nombre)
+    .bind(&usuario.apellido)
+    .bind(&usuario.telefono)
+    .bind(&usuario.email)
+    .bind(usuario.fecha_nacimiento)
+    .bind(&usuario.sexo)
+    .bind(usuario.rol_id)
+    .bind(usuario.activo)
+    .bind(usuario.especialidad.as_deref().map(str::trim))
+    .bind(id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al actualizar usuario: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    if resultado.rows_affected() == 0 {
+        return Err(ErrorApi::no_encontrado("Usuario"));
+    }
+
+    // Un usuario desactivado pierde de inmediato sus sesiones de refresco
+    if !usuario.activo {
+        sesiones::revocar_sesiones_usuario(&mut *tx, id)
+            .await
+            .map_err(|e| {
+                eprintln!("Error al revocar sesiones: {}", e);
+                ErrorApi::from(e)
+            })?;
+    }
+
+    tx.commit().await.map_err(|e| {
+        eprintln!("Error al confirmar transacción: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    Ok(Json(obtener_usuario(&pool, id).await?))
+}
+
+// PUT /usuarios/me/contrasena
+async fn cambiar_mi_contrasena(
+    usuario: AuthUser,
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Json(request): Json<CambiarMiContrasenaRequest>,
+) -> Result<Json<CambiarContrasenaResponse>, ErrorApi> {
+    let row = sqlx::query("SELECT email, nombre, apellido, contrasena_hash FROM usuarios WHERE id = $1")
+        .bind(usuario.usuario_id)
+        .fetch_one(&pool)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al obtener usuario: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    let contrasena_hash: String = row.get("contrasena_hash");
+    let actual_valida = verify(&request.contrasena_actual, &contrasena_hash).map_err(|e| {
+        eprintln!("Error al verificar contraseña: {}", e);
+        ErrorApi::Interno
+    })?;
+
+    if !actual_valida {
+        eprintln!("Contraseña actual incorrecta para usuario {}", usuario.usuario_id);
+        return Err(ErrorApi::NoAutenticado("La contraseña actual es incorrecta".to_string()));
+    }
+
+    let email: String = row.get("email");
+    let nombre: String = row.get("nombre");
+    let apellido: String = row.get("apellido");
+
+    let errores = config
+        .contrasenas
+        .validar(&request.nueva_contrasena, &[&email, &nombre, &apellido]);
+    if !errores.is_empty() {
+        return Err(ErrorApi::Validacion(
+            errores.into_iter().map(|mensaje| ErrorCampo::new("nueva_contrasena", mensaje)).collect(),
+        ));
+    }
+
+    let nueva_contrasena_hash = config.contrasenas.hash(&request.nueva_contrasena).map_err(|e| {
+        eprintln!("Error al encriptar contraseña: {}", e);
+        ErrorApi::Interno
+    })?;
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        eprintln!("Error al iniciar transacción: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    sqlx::query("UPDATE usuarios SET contrasena_hash = $1 WHERE id = $2")
+        .bind(&nueva_contrasena_hash)
+        .bind(usuario.usuario_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al actualizar contraseña: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    invalidar_tokens_recuperacion(&mut *tx, usuario.usuario_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al invalidar tokens de recuperación: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    // Las sesiones abiertas en otros equipos deben volver a autenticarse
+    sesiones::revocar_sesiones_usuario(&mut *tx, usuario.usuario_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al revocar sesiones: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    tx.commit().await.map_err(|e| {
+        eprintln!("Error al confirmar transacción: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    Ok(Json(CambiarContrasenaResponse {
+        mensaje: "Contraseña actualizada exitosamente".to_string(),
+    }))
 }
 
 // POST /usuarios
 async fn create_usuario(
     State(pool): State<PgPool>,
-    Json(usuario): Json<Usuario>,
-) -> Result<Json<Usuario>, StatusCode> {
+    State(config): State<Arc<Config>>,
+    ValidatedJson(usuario): ValidatedJson<NuevoUsuario>,
+) -> Result<Json<Usuario>, ErrorApi> {
+    let errores = config.contrasenas.validar(
+        &usuario.contrasena,
+        &[&usuario.email, &usuario.nombre, &usuario.apellido],
+    );
+    if !errores.is_empty() {
+        return Err(ErrorApi::Validacion(
+            errores.into_iter().map(|mensaje| ErrorCampo::new("contrasena", mensaje)).collect(),
+        ));
+    }
+
+    let contrasena_hash = config.contrasenas.hash(&usuario.contrasena).map_err(|e| {
+        eprintln!("Error al encriptar contraseña: {}", e);
+        ErrorApi::Interno
+    })?;
+
     let result = sqlx::query(
-        "INSERT INTO usuarios (nombre, apellido, telefono, email, fecha_nacimiento, sexo, rol_id, contrasena_hash) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING id"
+        "INSERT INTO usuarios (nombre, apellido, telefono, email, fecha_nacimiento, sexo, rol_id, contrasena_hash, especialidad) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING id"
     )
     .bind(&usuario.nombre)
     .bind(&usuario.apellido)
@@ -544,12 +1603,13 @@ async fn create_usuario(
     .bind(usuario.fecha_nacimiento)
     .bind(&usuario.sexo)
     .bind(usuario.rol_id)
-    .bind(&usuario.contrasena_hash)
+    .bind(&contrasena_hash)
+    .bind(usuario.especialidad.as_deref().map(str::trim))
     .fetch_one(&pool)
     .await
     .map_err(|e| {
         eprintln!("Error al crear usuario: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        ErrorApi::from(e)
     })?;
 
     let id = result.get("id");
@@ -563,21 +1623,48 @@ async fn create_usuario(
         fecha_nacimiento: usuario.fecha_nacimiento,
         sexo: usuario.sexo,
         rol_id: usuario.rol_id,
-        contrasena_hash: usuario.contrasena_hash,
+        especialidad: usuario.especialidad,
     }))
 }
 
+#[derive(Deserialize)]
+struct FiltroHorarios {
+    usuario_id: Option<i32>,
+    dia_semana: Option<String>,
+}
+
 // GET /horarios
-async fn get_horarios(State(pool): State<PgPool>) -> Result<Json<Vec<HorarioConUsuario>>, StatusCode> {
-    let rows = sqlx::query(
-        "SELECT h.id, h.usuario_id, u.nombre AS nombre_usuario, u.apellido AS apellido_usuario, h.dia_semana, h.hora_inicio, h.hora_fin FROM horarios h JOIN usuarios u ON h.usuario_id = u.id"
+async fn get_horarios(
+    State(pool): State<PgPool>,
+    OriginalUri(uri): OriginalUri,
+    Query(paginacion): Query<Paginacion>,
+    Query(filtro): Query<FiltroHorarios>,
+) -> Result<Response, ErrorApi> {
+    let (rows, total) = paginacion::listar(
+        &pool,
+        "horarios",
+        "h.id, h.usuario_id, u.nombre AS nombre_usuario, u.apellido AS apellido_usuario, h.dia_semana, h.hora_inicio, h.hora_fin",
+        "FROM horarios h JOIN usuarios u ON h.usuario_id = u.id WHERE TRUE",
+        |consulta| {
+            if let Some(usuario_id) = filtro.usuario_id {
+                consulta.push(" AND h.usuario_id = ").push_bind(usuario_id);
+            }
+            if let Some(dia_semana) = &filtro.dia_semana {
+                consulta
+                    .push(" AND LOWER(h.dia_semana) = ")
+                    .push_bind(dia_semana.trim().to_lowercase());
+            }
+        },
+        &paginacion,
+        &[
+            ("id", "h.id"),
+            ("dia_semana", "h.dia_semana"),
+            ("hora_inicio", "h.hora_inicio"),
+            ("usuario", "u.apellido"),
+        ],
+        "h.id",
     )
-    .fetch_all(&pool)
-    .await
-    .map_err(|e| {
-        eprintln!("Error en la consulta de horarios: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .await?;
 
     let horarios: Vec<HorarioConUsuario> = rows
         .into_iter()
@@ -592,54 +1679,58 @@ async fn get_horarios(State(pool): State<PgPool>) -> Result<Json<Vec<HorarioConU
         })
         .collect();
 
-    Ok(Json(horarios))
+    Ok(Pagina::new(horarios, total, &paginacion).responder(&uri))
 }
 
 // POST /horarios
 async fn create_horario(
     State(pool): State<PgPool>,
-    Json(horario): Json<Horario>,
-) -> Result<Json<Horario>, StatusCode> {
+    ValidatedJson(horario): ValidatedJson<Horario>,
+) -> Result<ConVersion<Horario>, ErrorApi> {
     let result = sqlx::query(
-        "INSERT INTO horarios (usuario_id, dia_semana, hora_inicio, hora_fin) VALUES ($1, $2, $3, $4) RETURNING id"
+        "INSERT INTO horarios (usuario_id, dia_semana, hora_inicio, hora_fin) VALUES ($1, $2, $3, $4) RETURNING id, version"
     )
     .bind(horario.usuario_id)
     .bind(&horario.dia_semana)
-    .bind(&horario.hora_inicio)
-    .bind(&horario.hora_fin)
+    .bind(horario.hora_inicio)
+    .bind(horario.hora_fin)
     .fetch_one(&pool)
     .await
     .map_err(|e| {
         eprintln!("Error al crear horario: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        ErrorApi::from(e)
     })?;
 
     let id = result.get("id");
 
-    Ok(Json(Horario {
-        id: Some(id),
-        usuario_id: horario.usuario_id,
-        dia_semana: horario.dia_semana,
-        hora_inicio: horario.hora_inicio,
-        hora_fin: horario.hora_fin,
-    }))
+    Ok(ConVersion(
+        result.get("version"),
+        Horario {
+            id: Some(id),
+            usuario_id: horario.usuario_id,
+            dia_semana: horario.dia_semana,
+            hora_inicio: horario.hora_inicio,
+            hora_fin: horario.hora_fin,
+        },
+    ))
 }
 
 // GET /horarios/{id}
 async fn get_horario_by_id(
     Path(id): Path<i32>,
     State(pool): State<PgPool>,
-) -> Result<Json<HorarioConUsuario>, StatusCode> {
+) -> Result<ConVersion<HorarioConUsuario>, ErrorApi> {
     let row = sqlx::query(
-        "SELECT h.id, h.usuario_id, u.nombre AS nombre_usuario, u.apellido AS apellido_usuario, h.dia_semana, h.hora_inicio, h.hora_fin FROM horarios h JOIN usuarios u ON h.usuario_id = u.id WHERE h.id = $1"
+        "SELECT h.id, h.usuario_id, u.nombre AS nombre_usuario, u.apellido AS apellido_usuario, h.dia_semana, h.hora_inicio, h.hora_fin, h.version FROM horarios h JOIN usuarios u ON h.usuario_id = u.id WHERE h.id = $1"
     )
     .bind(id)
-    .fetch_one(&pool)
+    .fetch_optional(&pool)
     .await
     .map_err(|e| {
         eprintln!("Error al obtener horario: {}", e);
-        StatusCode::NOT_FOUND
-    })?;
+        ErrorApi::from(e)
+    })?
+    .ok_or_else(|| ErrorApi::no_encontrado("Horario"))?;
 
     let horario = HorarioConUsuario {
         id: row.get("id"),
@@ -651,67 +1742,168 @@ async fn get_horario_by_id(
         hora_fin: row.get("hora_fin"),
     };
 
-    Ok(Json(horario))
+    Ok(ConVersion(row.get("version"), horario))
+}
+
+fn horario_desde_fila(row: &sqlx::postgres::PgRow) -> Horario {
+    Horario {
+        id: row.get("id"),
+        usuario_id: row.get("usuario_id"),
+        dia_semana: row.get("dia_semana"),
+        hora_inicio: row.get("hora_inicio"),
+        hora_fin: row.get("hora_fin"),
+    }
+}
+
+// Estado actual del horario y su versión, bloqueado hasta el fin de la
+// transacción
+async fn horario_actual<'e, E: sqlx::PgExecutor<'e>>(executor: E, id: i32) -> Result<(Horario, i32), ErrorApi> {
+    let row = sqlx::query(
+        "SELECT id, usuario_id, dia_semana, hora_inicio, hora_fin, version FROM horarios WHERE id = $1 FOR UPDATE"
+    )
+    .bind(id)
+    .fetch_optional(executor)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al obtener horario: {}", e);
+        ErrorApi::from(e)
+    })?
+    .ok_or_else(|| ErrorApi::no_encontrado("Horario"))?;
+
+    Ok((horario_desde_fila(&row), row.get("version")))
 }
 
-// PUT /horarios/{id}
+// PUT /horarios/:id
 async fn update_horario(
     Path(id): Path<i32>,
     State(pool): State<PgPool>,
-    Json(horario): Json<Horario>,
-) -> Result<Json<Horario>, StatusCode> {
-    sqlx::query(
-        "UPDATE horarios SET usuario_id = $1, dia_semana = $2, hora_inicio = $3, hora_fin = $4 WHERE id = $5"
+    if_match: IfMatch,
+    ValidatedJson(horario): ValidatedJson<Horario>,
+) -> Result<ConVersion<Horario>, ErrorApi> {
+    let mut tx = iniciar_transaccion(&pool).await?;
+    let (_, version) = horario_actual(&mut *tx, id).await?;
+    if_match.verificar(version)?;
+    let actualizado = actualizar_horario(&mut tx, id, horario).await?;
+    confirmar_transaccion(tx).await?;
+
+    Ok(actualizado)
+}
+
+// PATCH /horarios/:id
+async fn patch_horario(
+    Path(id): Path<i32>,
+    State(pool): State<PgPool>,
+    if_match: IfMatch,
+    ValidatedJson(parche): ValidatedJson<Parche>,
+) -> Result<ConVersion<Horario>, ErrorApi> {
+    let mut tx = iniciar_transaccion(&pool).await?;
+    let (antes, version) = horario_actual(&mut *tx, id).await?;
+    if_match.verificar(version)?;
+    let horario = parche.aplicar(&antes)?;
+    let actualizado = actualizar_horario(&mut tx, id, horario).await?;
+    confirmar_transaccion(tx).await?;
+
+    Ok(actualizado)
+}
+
+async fn actualizar_horario(
+    conn: &mut sqlx::PgConnection,
+    id: i32,
+    horario: Horario,
+) -> Result<ConVersion<Horario>, ErrorApi> {
+    let row = sqlx::query(
+        "UPDATE horarios SET usuario_id = $1, dia_semana = $2, hora_inicio = $3, hora_fin = $4 WHERE id = $5 RETURNING id, usuario_id, dia_semana, hora_inicio, hora_fin, version"
     )
     .bind(horario.usuario_id)
     .bind(&horario.dia_semana)
-    .bind(&horario.hora_inicio)
-    .bind(&horario.hora_fin)
+    .bind(horario.hora_inicio)
+    .bind(horario.hora_fin)
     .bind(id)
-    .execute(&pool)
+    .fetch_one(conn)
     .await
     .map_err(|e| {
         eprintln!("Error al actualizar horario: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        ErrorApi::from(e)
     })?;
 
-    Ok(Json(Horario {
-        id: Some(id),
-        usuario_id: horario.usuario_id,
-        dia_semana: horario.dia_semana,
-        hora_inicio: horario.hora_inicio,
-        hora_fin: horario.hora_fin,
-    }))
+    Ok(ConVersion(row.get("version"), horario_desde_fila(&row)))
 }
 
 // DELETE /horarios/{id}
 async fn delete_horario(
     Path(id): Path<i32>,
     State(pool): State<PgPool>,
-) -> Result<StatusCode, StatusCode> {
-    sqlx::query("DELETE FROM horarios WHERE id = $1")
+) -> Result<StatusCode, ErrorApi> {
+    let resultado = sqlx::query("DELETE FROM horarios WHERE id = $1")
         .bind(id)
         .execute(&pool)
         .await
         .map_err(|e| {
             eprintln!("Error al eliminar horario: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            ErrorApi::from(e)
         })?;
 
+    if resultado.rows_affected() == 0 {
+        return Err(ErrorApi::no_encontrado("Horario"));
+    }
+
     Ok(StatusCode::NO_CONTENT)
 }
 
+#[derive(Deserialize)]
+struct FiltroCitas {
+    estado: Option<EstadoCita>,
+    usuario_id: Option<i32>,
+    paciente_id: Option<i32>,
+    serie_id: Option<i32>,
+    desde: Option<NaiveDateTime>,
+    hasta: Option<NaiveDateTime>,
+}
+
 // GET /citas
-async fn get_citas(State(pool): State<PgPool>) -> Result<Json<Vec<CitaConDetalles>>, StatusCode> {
-    let rows = sqlx::query(
-        "SELECT c.id, c.paciente_id, p.nombre AS nombre_paciente, p.apellido AS apellido_paciente, c.usuario_id, u.nombre AS nombre_medico, u.apellido AS apellido_medico, c.fecha_hora, c.estado, c.motivo FROM citas c JOIN pacientes p ON c.paciente_id = p.id JOIN usuarios u ON c.usuario_id = u.id"
+async fn get_citas(
+    State(pool): State<PgPool>,
+    actor: Actor,
+    OriginalUri(uri): OriginalUri,
+    Query(paginacion): Query<Paginacion>,
+    Query(filtro): Query<FiltroCitas>,
+) -> Result<Response, ErrorApi> {
+    let (rows, total) = paginacion::listar(
+        &pool,
+        "citas",
+        "c.id, c.paciente_id, p.nombre AS nombre_paciente, p.apellido AS apellido_paciente, c.usuario_id, u.nombre AS nombre_medico, u.apellido AS apellido_medico, c.fecha_hora, c.estado, c.motivo, c.serie_id",
+        "FROM citas c JOIN pacientes p ON c.paciente_id = p.id JOIN usuarios u ON c.usuario_id = u.id WHERE TRUE",
+        |consulta| {
+            if let Some(estado) = &filtro.estado {
+                consulta.push(" AND c.estado = ").push_bind(estado.como_str());
+            }
+            if let Some(usuario_id) = filtro.usuario_id {
+                consulta.push(" AND c.usuario_id = ").push_bind(usuario_id);
+            }
+            if let Some(paciente_id) = filtro.paciente_id {
+                consulta.push(" AND c.paciente_id = ").push_bind(paciente_id);
+            }
+            if let Some(serie_id) = filtro.serie_id {
+                consulta.push(" AND c.serie_id = ").push_bind(serie_id);
+            }
+            if let Some(desde) = filtro.desde {
+                consulta.push(" AND c.fecha_hora >= ").push_bind(desde);
+            }
+            if let Some(hasta) = filtro.hasta {
+                consulta.push(" AND c.fecha_hora <= ").push_bind(hasta);
+            }
+        },
+        &paginacion,
+        &[
+            ("fecha_hora", "c.fecha_hora"),
+            ("id", "c.id"),
+            ("estado", "c.estado"),
+            ("paciente", "p.apellido"),
+            ("medico", "u.apellido"),
+        ],
+        "c.id",
     )
-    .fetch_all(&pool)
-    .await
-    .map_err(|e| {
-        eprintln!("Error en la consulta de citas: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    .await?;
 
     let citas: Vec<CitaConDetalles> = rows
         .into_iter()
@@ -724,61 +1916,92 @@ async fn get_citas(State(pool): State<PgPool>) -> Result<Json<Vec<CitaConDetalle
             nombre_medico: row.get("nombre_medico"),
             apellido_medico: row.get("apellido_medico"),
             fecha_hora: row.get("fecha_hora"),
-            estado: row.get("estado"),
+            estado: EstadoCita::desde_columna(row.get("estado")),
             motivo: row.get("motivo"),
+            serie_id: row.get("serie_id"),
         })
         .collect();
 
-    Ok(Json(citas))
+    let lecturas: Vec<(i32, i32)> = citas.iter().map(|c| (c.id, c.paciente_id)).collect();
+    auditar_lecturas(&pool, &actor, "citas", &lecturas).await?;
+
+    Ok(Pagina::new(citas, total, &paginacion).responder(&uri))
 }
 
 // POST /citas
 async fn create_cita(
     State(pool): State<PgPool>,
-    Json(cita): Json<Cita>,
-) -> Result<Json<Cita>, StatusCode> {
+    State(config): State<Arc<Config>>,
+    actor: Actor,
+    ValidatedJson(entrada): ValidatedJson<CitaEntrada>,
+) -> Result<ConVersion<Cita>, ErrorApi> {
+    let cita = entrada.en_estado(EstadoCita::Programada);
+    if cita.estado != EstadoCita::Programada {
+        return Err(ErrorApi::Validacion(vec![ErrorCampo::new(
+            "estado",
+            "Las citas se crean como programadas; el estado cambia con los endpoints de la cita",
+        )]));
+    }
+
+    let mut tx = iniciar_transaccion(&pool).await?;
+    exigir_paciente_activo(&mut tx, cita.paciente_id).await?;
+    agenda::verificar_cita(&mut tx, cita.usuario_id, cita.fecha_hora, config.duracion_cita_minutos, None).await?;
+
     let result = sqlx::query(
-        "INSERT INTO citas (paciente_id, usuario_id, fecha_hora, estado, motivo) VALUES ($1, $2, $3, $4, $5) RETURNING id"
+        "INSERT INTO citas (paciente_id, usuario_id, fecha_hora, estado, motivo) VALUES ($1, $2, $3, $4, $5) RETURNING id, version"
     )
     .bind(cita.paciente_id)
     .bind(cita.usuario_id)
     .bind(cita.fecha_hora)
-    .bind(&cita.estado)
+    .bind(cita.estado.como_str())
     .bind(&cita.motivo)
-    .fetch_one(&pool)
+    .fetch_one(&mut *tx)
     .await
     .map_err(|e| {
         eprintln!("Error al crear cita: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        ErrorApi::from(e)
     })?;
 
     let id = result.get("id");
 
-    Ok(Json(Cita {
+    let creada = Cita {
         id: Some(id),
         paciente_id: cita.paciente_id,
         usuario_id: cita.usuario_id,
         fecha_hora: cita.fecha_hora,
         estado: cita.estado,
         motivo: cita.motivo,
-    }))
+        serie_id: None,
+    };
+
+    auditar(
+        &mut *tx,
+        &actor,
+        Evento::cambio("crear", "citas", id, Some(creada.paciente_id), None, auditoria::valor(&creada)),
+    )
+    .await?;
+    confirmar_transaccion(tx).await?;
+
+    Ok(ConVersion(result.get("version"), creada))
 }
 
 // GET /citas/{id}
 async fn get_cita_by_id(
     Path(id): Path<i32>,
     State(pool): State<PgPool>,
-) -> Result<Json<CitaConDetalles>, StatusCode> {
+    actor: Actor,
+) -> Result<ConVersion<CitaConDetalles>, ErrorApi> {
     let row = sqlx::query(
-        "SELECT c.id, c.paciente_id, p.nombre AS nombre_paciente, p.apellido AS apellido_paciente, c.usuario_id, u.nombre AS nombre_medico, u.apellido AS apellido_medico, c.fecha_hora, c.estado, c.motivo FROM citas c JOIN pacientes p ON c.paciente_id = p.id JOIN usuarios u ON c.usuario_id = u.id WHERE c.id = $1"
+        "SELECT c.id, c.paciente_id, p.nombre AS nombre_paciente, p.apellido AS apellido_paciente, c.usuario_id, u.nombre AS nombre_medico, u.apellido AS apellido_medico, c.fecha_hora, c.estado, c.motivo, c.serie_id, c.version FROM citas c JOIN pacientes p ON c.paciente_id = p.id JOIN usuarios u ON c.usuario_id = u.id WHERE c.id = $1"
     )
     .bind(id)
-    .fetch_one(&pool)
+    .fetch_optional(&pool)
     .await
     .map_err(|e| {
         eprintln!("Error al obtener cita: {}", e);
-        StatusCode::NOT_FOUND
-    })?;
+        ErrorApi::from(e)
+    })?
+    .ok_or_else(|| ErrorApi::NoEncontrado("Cita no encontrada".to_string()))?;
 
     let cita = CitaConDetalles {
         id: row.get("id"),
@@ -789,235 +2012,570 @@ async fn get_cita_by_id(
         nombre_medico: row.get("nombre_medico"),
         apellido_medico: row.get("apellido_medico"),
         fecha_hora: row.get("fecha_hora"),
-        estado: row.get("estado"),
+        estado: EstadoCita::desde_columna(row.get("estado")),
         motivo: row.get("motivo"),
+        serie_id: row.get("serie_id"),
     };
 
-    Ok(Json(cita))
+    auditar(&pool, &actor, Evento::lectura("citas", Some(id), Some(cita.paciente_id))).await?;
+
+    Ok(ConVersion(row.get("version"), cita))
+}
+
+fn cita_desde_fila(row: &sqlx::postgres::PgRow) -> Cita {
+    Cita {
+        id: row.get("id"),
+        paciente_id: row.get("paciente_id"),
+        usuario_id: row.get("usuario_id"),
+        fecha_hora: row.get("fecha_hora"),
+        estado: EstadoCita::desde_columna(row.get("estado")),
+        motivo: row.get("motivo"),
+        serie_id: row.get("serie_id"),
+    }
+}
+
+// Estado actual de la cita y su versión, bloqueada hasta el fin de la
+// transacción, para registrar el "antes" de un cambio
+async fn cita_actual<'e, E: sqlx::PgExecutor<'e>>(executor: E, id: i32) -> Result<(Cita, i32), ErrorApi> {
+    let row = sqlx::query(
+        "SELECT id, paciente_id, usuario_id, fecha_hora, estado, motivo, serie_id, version FROM citas WHERE id = $1 FOR UPDATE"
+    )
+    .bind(id)
+    .fetch_optional(executor)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al obtener cita: {}", e);
+        ErrorApi::from(e)
+    })?
+    .ok_or_else(|| ErrorApi::NoEncontrado("Cita no encontrada".to_string()))?;
+
+    Ok((cita_desde_fila(&row), row.get("version")))
 }
 
-// PUT /citas/{id}
+// PUT /citas/:id
 async fn update_cita(
     Path(id): Path<i32>,
     State(pool): State<PgPool>,
-    Json(cita): Json<Cita>,
-) -> Result<Json<Cita>, StatusCode> {
-    sqlx::query(
-        "UPDATE citas SET paciente_id = $1, usuario_id = $2, fecha_hora = $3, estado = $4, motivo = $5 WHERE id = $6"
+    State(config): State<Arc<Config>>,
+    actor: Actor,
+    if_match: IfMatch,
+    ValidatedJson(entrada): ValidatedJson<CitaEntrada>,
+) -> Result<ConVersion<Cita>, ErrorApi> {
+    let mut tx = iniciar_transaccion(&pool).await?;
+    let (antes, version) = cita_actual(&mut *tx, id).await?;
+    if_match.verificar(version)?;
+    let cita = entrada.en_estado(antes.estado);
+    let (actualizada, version) = actualizar_cita(&mut tx, &actor, id, antes, cita, config.duracion_cita_minutos).await?;
+    confirmar_transaccion(tx).await?;
+
+    Ok(ConVersion(version, actualizada))
+}
+
+// PATCH /citas/:id
+async fn patch_cita(
+    Path(id): Path<i32>,
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    actor: Actor,
+    if_match: IfMatch,
+    ValidatedJson(parche): ValidatedJson<Parche>,
+) -> Result<ConVersion<Cita>, ErrorApi> {
+    let mut tx = iniciar_transaccion(&pool).await?;
+    let (antes, version) = cita_actual(&mut *tx, id).await?;
+    if_match.verificar(version)?;
+    let cita = parche.aplicar(&antes)?;
+    let (actualizada, version) = actualizar_cita(&mut tx, &actor, id, antes, cita, config.duracion_cita_minutos).await?;
+    confirmar_transaccion(tx).await?;
+
+    Ok(ConVersion(version, actualizada))
+}
+
+// Guarda el nuevo estado de la cita y registra el cambio en la auditoría.
+// La agenda solo se vuelve a validar si la cita cambia de hora o de médico.
+// El estado no se edita aquí sino con los endpoints de transición.
+// Devuelve la cita guardada con su nueva versión.
+async fn actualizar_cita(
+    conn: &mut sqlx::PgConnection,
+    actor: &Actor,
+    id: i32,
+    antes: Cita,
+    cita: Cita,
+    duracion_minutos: i32,
+) -> Result<(Cita, i32), ErrorApi> {
+    if antes.estado.cerrada() {
+        return Err(ErrorApi::EstadoInvalido {
+            mensaje: format!("Una cita {} ya no se puede modificar", antes.estado),
+            detalles: Some(serde_json::json!({ "estado_actual": antes.estado })),
+        });
+    }
+    if cita.estado != antes.estado {
+        return Err(ErrorApi::EstadoInvalido {
+            mensaje: "El estado de la cita solo cambia con los endpoints confirmar, cancelar, checkin, completar y no_asistio".to_string(),
+            detalles: Some(serde_json::json!({ "estado_actual": antes.estado })),
+        });
+    }
+
+    exigir_paciente_activo(&mut *conn, cita.paciente_id).await?;
+
+    if cita.fecha_hora != antes.fecha_hora || cita.usuario_id != antes.usuario_id {
+        agenda::verificar_cita(&mut *conn, cita.usuario_id, cita.fecha_hora, duracion_minutos, Some(id)).await?;
+    }
+
+    let row = sqlx::query(
+        "UPDATE citas SET paciente_id = $1, usuario_id = $2, fecha_hora = $3, estado = $4, motivo = $5 WHERE id = $6 RETURNING id, paciente_id, usuario_id, fecha_hora, estado, motivo, serie_id, version"
     )
     .bind(cita.paciente_id)
     .bind(cita.usuario_id)
     .bind(cita.fecha_hora)
-    .bind(&cita.estado)
+    .bind(cita.estado.como_str())
     .bind(&cita.motivo)
     .bind(id)
-    .execute(&pool)
+    .fetch_one(&mut *conn)
     .await
     .map_err(|e| {
         eprintln!("Error al actualizar cita: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        ErrorApi::from(e)
     })?;
 
-    Ok(Json(Cita {
-        id: Some(id),
-        paciente_id: cita.paciente_id,
-        usuario_id: cita.usuario_id,
-        fecha_hora: cita.fecha_hora,
-        estado: cita.estado,
-        motivo: cita.motivo,
-    }))
+    let actualizada = cita_desde_fila(&row);
+
+    auditar(
+        &mut *conn,
+        actor,
+        Evento::cambio(
+            "actualizar",
+            "citas",
+            id,
+            Some(actualizada.paciente_id),
+            auditoria::valor(&antes),
+            auditoria::valor(&actualizada),
+        ),
+    )
+    .await?;
+
+    Ok((actualizada, row.get("version")))
 }
 
 // DELETE /citas/{id}
 async fn delete_cita(
     Path(id): Path<i32>,
     State(pool): State<PgPool>,
-) -> Result<StatusCode, StatusCode> {
+    actor: Actor,
+) -> Result<StatusCode, ErrorApi> {
+    let mut tx = iniciar_transaccion(&pool).await?;
+    let (antes, _) = cita_actual(&mut *tx, id).await?;
+
     sqlx::query("DELETE FROM citas WHERE id = $1")
         .bind(id)
-        .execute(&pool)
+        .execute(&mut *tx)
         .await
         .map_err(|e| {
             eprintln!("Error al eliminar cita: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            ErrorApi::from(e)
         })?;
 
+    auditar(
+        &mut *tx,
+        &actor,
+        Evento::cambio(
+            "eliminar",
+            "citas",
+            id,
+            Some(antes.paciente_id),
+            auditoria::valor(&antes),
+            None,
+        ),
+    )
+    .await?;
+    confirmar_transaccion(tx).await?;
+
     Ok(StatusCode::NO_CONTENT)
 }
 
+// Registra el evento en la bitácora; si no se puede auditar, la operación falla
+async fn auditar<'e, E: sqlx::PgExecutor<'e>>(
+    executor: E,
+    actor: &Actor,
+    evento: Evento<'_>,
+) -> Result<(), ErrorApi> {
+    auditoria::registrar(executor, actor, evento).await.map_err(|e| {
+        eprintln!("Error al registrar auditoría: {}", e);
+        ErrorApi::from(e)
+    })
+}
+
+async fn auditar_lecturas<'e, E: sqlx::PgExecutor<'e>>(
+    executor: E,
+    actor: &Actor,
+    entidad: &str,
+    lecturas: &[(i32, i32)],
+) -> Result<(), ErrorApi> {
+    auditoria::registrar_lecturas(executor, actor, entidad, lecturas).await.map_err(|e| {
+        eprintln!("Error al registrar auditoría: {}", e);
+        ErrorApi::from(e)
+    })
+}
+
+async fn iniciar_transaccion(pool: &PgPool) -> Result<sqlx::Transaction<'static, sqlx::Postgres>, ErrorApi> {
+    pool.begin().await.map_err(|e| {
+        eprintln!("Error al iniciar transacción: {}", e);
+        ErrorApi::from(e)
+    })
+}
+
+async fn confirmar_transaccion(tx: sqlx::Transaction<'static, sqlx::Postgres>) -> Result<(), ErrorApi> {
+    tx.commit().await.map_err(|e| {
+        eprintln!("Error al confirmar transacción: {}", e);
+        ErrorApi::from(e)
+    })
+}
+
+// Hash contra el que se verifica cuando el email no existe, para que ambos
+// caminos tarden lo mismo
+static HASH_FICTICIO: OnceLock<String> = OnceLock::new();
+
+fn hash_ficticio(config: &Config) -> &'static str {
+    HASH_FICTICIO.get_or_init(|| {
+        config
+            .contrasenas
+            .hash("contrasena-ficticia")
+            .expect("No se pudo generar el hash ficticio")
+    })
+}
 
 //Login
 async fn login(
     State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    IpCliente(ip): IpCliente,
     Json(login_data): Json<LoginRequest>,
-) -> Result<Json<LoginResponse>, StatusCode> {
+) -> Result<Json<RespuestaLogin>, ErrorApi> {
+    let clave_cuenta = bloqueos::clave_cuenta(&login_data.email);
+    let clave_ip = bloqueos::clave_ip(&ip);
+
+    let bloqueado = bloqueos::esta_bloqueado(&pool, &[clave_cuenta.clone(), clave_ip.clone()])
+        .await
+        .map_err(|e| {
+            eprintln!("Error al consultar bloqueos: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    if bloqueado {
+        eprintln!("Login bloqueado temporalmente para {} desde {}", login_data.email, ip);
+        return Err(ErrorApi::DemasiadosIntentos(
+            "Demasiados intentos fallidos, intenta más tarde".to_string(),
+        ));
+    }
+
     // Buscar usuario por email
     let row = sqlx::query(
-    "SELECT u.id, u.contrasena_hash, u.rol_id, u.nombre, u.apellido, r.nombre AS rol_nombre FROM usuarios u JOIN roles r ON u.rol_id = r.id WHERE u.email = $1"
+    "SELECT u.id, u.contrasena_hash, u.nombre, u.apellido, u.totp_activo, u.activo, r.nombre AS rol_nombre, r.requiere_2fa FROM usuarios u JOIN roles r ON u.rol_id = r.id WHERE u.email = $1"
 )
     .bind(&login_data.email)
-    .fetch_one(&pool)
+    .fetch_optional(&pool)
     .await
     .map_err(|e| {
-        eprintln!("Usuario no encontrado: {}", e);
-        StatusCode::UNAUTHORIZED
+        eprintln!("Error al buscar usuario: {}", e);
+        ErrorApi::from(e)
     })?;
 
-    let contrasena_hash: String = row.get("contrasena_hash");
-    let rol_id: i32 = row.get("rol_id");
-    let usuario_id: i32 = row.get("id");
-    let nombre: String = row.get("nombre");    // ✅ Obtenido
-    let apellido: String = row.get("apellido"); // ✅ Obtenido
-    let rol_nombre: String = row.get("rol_nombre");
+    // Si el usuario no existe se verifica igualmente contra un hash ficticio
+    let contrasena_hash: &str = match &row {
+        Some(row) => row.get("contrasena_hash"),
+        None => hash_ficticio(&config),
+    };
 
     // Verificar contraseña
-    let password_valid = verify(&login_data.contrasena, &contrasena_hash)
+    let password_valid = verify(&login_data.contrasena, contrasena_hash)
         .map_err(|e| {
             eprintln!("Error al verificar contraseña: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            ErrorApi::Interno
         })?;
 
-    if !password_valid {
-        eprintln!("Contraseña incorrecta para usuario: {}", login_data.email);
-        return Err(StatusCode::UNAUTHORIZED);
+    let row = match row {
+        Some(row) if password_valid => row,
+        _ => {
+            eprintln!("Credenciales inválidas para {} desde {}", login_data.email, ip);
+            let registro = async {
+                bloqueos::registrar_fallo(&pool, &clave_cuenta, config.login.max_intentos_cuenta, &config.login).await?;
+                bloqueos::registrar_fallo(&pool, &clave_ip, config.login.max_intentos_ip, &config.login).await
+            };
+            registro.await.map_err(|e| {
+                eprintln!("Error al registrar intento fallido: {}", e);
+                ErrorApi::from(e)
+            })?;
+            return Err(ErrorApi::NoAutenticado("Credenciales inválidas".to_string()));
+        }
+    };
+
+    bloqueos::limpiar(&pool, &clave_cuenta).await.map_err(|e| {
+        eprintln!("Error al limpiar intentos fallidos: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    if !row.get::<bool, _>("activo") {
+        eprintln!("Intento de login de usuario desactivado: {}", login_data.email);
+        return Err(ErrorApi::Prohibido("El usuario está desactivado".to_string()));
     }
 
-    // Obtener nombre del rol
-    let rol_row = sqlx::query("SELECT nombre FROM roles WHERE id = $1")
-        .bind(rol_id)
-        .fetch_one(&pool)
-        .await
-        .map_err(|e| {
-            eprintln!("Error al obtener rol: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+    let usuario_id: i32 = row.get("id");
+    let nombre: String = row.get("nombre");    // ✅ Obtenido
+    let apellido: String = row.get("apellido"); // ✅ Obtenido
+    let rol_nombre: String = row.get("rol_nombre");
+
+    // Con 2FA activo (o exigido por el rol) aún no se emite el token de acceso
+    let tipo_pendiente = if row.get::<bool, _>("totp_activo") {
+        Some(TipoToken::Pendiente2fa)
+    } else if row.get::<bool, _>("requiere_2fa") {
+        Some(TipoToken::Enrolamiento2fa)
+    } else {
+        None
+    };
+
+    if let Some(tipo) = tipo_pendiente {
+        let claims = config.jwt.claims(
+            tipo,
+            login_data.email,
+            rol_nombre,
+            usuario_id,
+            Vec::new(),
+            config.jwt.duracion_2fa,
+        );
+        let token_2fa = config.jwt.firmar(&claims).map_err(|e| {
+            eprintln!("Error al crear token 2FA: {}", e);
+            ErrorApi::Interno
         })?;
 
-    let rol_nombre: String = rol_row.get("nombre");
+        return Ok(Json(RespuestaLogin::SegundoFactor(SegundoFactorResponse {
+            token_2fa,
+            enrolamiento_requerido: tipo == TipoToken::Enrolamiento2fa,
+        })));
+    }
+
+    let respuesta = emitir_tokens(&pool, &config, usuario_id, login_data.email, nombre, apellido, rol_nombre).await?;
+
+    Ok(Json(RespuestaLogin::Completa(respuesta)))
+}
+
+// Emite el token de acceso y abre una nueva sesión de refresco
+async fn emitir_tokens(
+    pool: &PgPool,
+    config: &Config,
+    usuario_id: i32,
+    email: String,
+    nombre: String,
+    apellido: String,
+    rol_nombre: String,
+) -> Result<LoginResponse, ErrorApi> {
+    let permisos = permisos::permisos_de_usuario(pool, usuario_id).await.map_err(|e| {
+        eprintln!("Error al obtener permisos: {}", e);
+        ErrorApi::from(e)
+    })?;
 
     // Crear token JWT
-    let expiration = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs() + 3600; // 1 hora de expiración
-
-    let claims = Claims {
-        sub: login_data.email,
-        exp: expiration as usize,
-        rol: rol_nombre.clone(),
+    let claims = config.jwt.claims(
+        TipoToken::Acceso,
+        email,
+        rol_nombre.clone(),
         usuario_id,
-    };
+        permisos,
+        config.jwt.duracion_acceso,
+    );
 
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret("tu_clave_secreta_aqui".as_ref()),
-    ).map_err(|e| {
+    let token = config.jwt.firmar(&claims).map_err(|e| {
         eprintln!("Error al crear token: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        ErrorApi::Interno
     })?;
 
-    Ok(Json(LoginResponse {
+    // Cada login abre una nueva familia de sesiones de refresco
+    let refresh_token = sesiones::crear_sesion(pool, usuario_id, Uuid::new_v4(), config.jwt.duracion_refresco)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al crear sesión: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    Ok(LoginResponse {
         token,
+        refresh_token,
         rol: rol_nombre,
         usuario_id,
         nombre,
         apellido,
-    }))
+    })
 }
 
 async fn recuperar_contrasena(
     State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    State(mailer): State<Arc<dyn Mailer>>,
     Json(request): Json<RecuperarContrasenaRequest>,
-) -> Result<Json<RecuperarContrasenaResponse>, StatusCode> {
-    // Verificar que el usuario exista
-    let row = sqlx::query("SELECT id FROM usuarios WHERE email = $1")
+) -> Result<Json<RecuperarContrasenaResponse>, ErrorApi> {
+    // La respuesta es la misma exista o no el usuario, para no revelar qué
+    // emails tienen cuenta; el correo solo se envía si existe
+    let respuesta = Json(RecuperarContrasenaResponse {
+        mensaje: "Si el email corresponde a una cuenta activa, recibirás un token de recuperación".to_string(),
+    });
+
+    let row = sqlx::query("SELECT id FROM usuarios WHERE email = $1 AND activo")
         .bind(&request.email)
-        .fetch_one(&pool)
+        .fetch_optional(&pool)
         .await
         .map_err(|e| {
-            eprintln!("Usuario no encontrado para recuperación de contraseña: {}", e);
-            StatusCode::BAD_REQUEST
+            eprintln!("Error al buscar usuario para recuperación de contraseña: {}", e);
+            ErrorApi::from(e)
         })?;
 
-    // Generar token temporal para recuperación (expiración corta, por ejemplo 15 minutos)
-    let expiration = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs() + 900; // 15 minutos
-
-    let claims = Claims {
-        sub: request.email.clone(),
-        exp: expiration as usize,
-        rol: "recuperacion".to_string(), // Rol temporal
-        usuario_id: row.get("id"),
+    let Some(row) = row else {
+        eprintln!("Recuperación de contraseña solicitada para un email sin cuenta activa");
+        return Ok(respuesta);
     };
 
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret("tu_clave_secreta_aqui".as_ref()),
-    ).map_err(|e| {
-        eprintln!("Error al crear token de recuperación: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+    let usuario_id: i32 = row.get("id");
+
+    // Generar token de un solo uso; solo se guarda su hash
+    let token = auth::generar_token();
+
+    sqlx::query(
+        "INSERT INTO tokens_recuperacion (usuario_id, token_hash, expira_en) VALUES ($1, $2, NOW() + make_interval(secs => $3))"
+    )
+    .bind(usuario_id)
+    .bind(auth::hash_token(&token))
+    .bind(config.jwt.duracion_recuperacion as f64)
+    .execute(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al guardar token de recuperación: {}", e);
+        ErrorApi::from(e)
     })?;
 
-    // Simular envío de correo (en consola por ahora)
-    println!("Token de recuperación para {}: {}", request.email, token);
+    let correo = Correo {
+        para: request.email.clone(),
+        asunto: "Recuperación de contraseña".to_string(),
+        cuerpo: format!(
+            "Recibimos una solicitud para cambiar tu contraseña.\n\nTu token de recuperación es:\n\n{}\n\nEl token vence en {} minutos y solo puede usarse una vez. Si no solicitaste el cambio, ignora este mensaje.",
+            token,
+            config.jwt.duracion_recuperacion / 60
+        ),
+    };
+
+    // El envío va aparte: ni su demora ni sus fallos deben distinguir la
+    // respuesta de la de un email sin cuenta
+    tokio::spawn(async move {
+        if let Err(e) = mailer.enviar(&correo).await {
+            eprintln!("Error al enviar correo de recuperación al usuario {}: {}", usuario_id, e);
+        }
+    });
 
-    Ok(Json(RecuperarContrasenaResponse {
-        mensaje: "Se ha enviado un token de recuperación a tu correo".to_string(),
-    }))
+    Ok(respuesta)
 }
 
 async fn cambiar_contrasena(
     State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
     Json(request): Json<CambiarContrasenaRequest>,
-) -> Result<Json<CambiarContrasenaResponse>, StatusCode> {
-    // Verificar token
-    let token_data = decode::<Claims>(
-        &request.token,
-        &DecodingKey::from_secret("tu_clave_secreta_aqui".as_ref()),
-        &Validation::default(),
-    ).map_err(|e| {
-        eprintln!("Token inválido o expirado: {}", e);
-        StatusCode::UNAUTHORIZED
+) -> Result<Json<CambiarContrasenaResponse>, ErrorApi> {
+    let mut tx = pool.begin().await.map_err(|e| {
+        eprintln!("Error al iniciar transacción: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    // Verificar token: debe existir, no estar usado ni vencido, y el usuario
+    // debe seguir activo, igual que en el login y el refresco
+    let row = sqlx::query(
+        "SELECT t.usuario_id, u.email, u.nombre, u.apellido FROM tokens_recuperacion t JOIN usuarios u ON t.usuario_id = u.id WHERE t.token_hash = $1 AND t.used_at IS NULL AND t.expira_en > NOW() AND u.activo FOR UPDATE OF t"
+    )
+    .bind(auth::hash_token(&request.token))
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al verificar token de recuperación: {}", e);
+        ErrorApi::from(e)
+    })?
+    .ok_or_else(|| {
+        eprintln!("Token de recuperación inválido, usado o expirado");
+        ErrorApi::NoAutenticado("Token de recuperación inválido, usado o expirado".to_string())
     })?;
 
-    if token_data.claims.rol != "recuperacion" {
-        return Err(StatusCode::UNAUTHORIZED);
+    let usuario_id: i32 = row.get("usuario_id");
+    let email: String = row.get("email");
+    let nombre: String = row.get("nombre");
+    let apellido: String = row.get("apellido");
+
+    let errores = config
+        .contrasenas
+        .validar(&request.nueva_contrasena, &[&email, &nombre, &apellido]);
+    if !errores.is_empty() {
+        return Err(ErrorApi::Validacion(
+            errores.into_iter().map(|mensaje| ErrorCampo::new("nueva_contrasena", mensaje)).collect(),
+        ));
     }
 
     // Encriptar nueva contraseña
-    let nueva_contrasena_hash = hash(&request.nueva_contrasena, DEFAULT_COST)
+    let nueva_contrasena_hash = config.contrasenas.hash(&request.nueva_contrasena)
         .map_err(|e| {
             eprintln!("Error al encriptar contraseña: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            ErrorApi::Interno
         })?;
 
     // Actualizar contraseña en la base de datos
     sqlx::query("UPDATE usuarios SET contrasena_hash = $1 WHERE id = $2")
         .bind(&nueva_contrasena_hash)
-        .bind(token_data.claims.usuario_id)
-        .execute(&pool)
+        .bind(usuario_id)
+        .execute(&mut *tx)
         .await
         .map_err(|e| {
             eprintln!("Error al actualizar contraseña: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            ErrorApi::from(e)
+        })?;
+
+    invalidar_tokens_recuperacion(&mut *tx, usuario_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al invalidar tokens de recuperación: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    // Tras un cambio de contraseña ninguna sesión previa debe seguir activa
+    sesiones::revocar_sesiones_usuario(&mut *tx, usuario_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al revocar sesiones: {}", e);
+            ErrorApi::from(e)
         })?;
 
+    tx.commit().await.map_err(|e| {
+        eprintln!("Error al confirmar transacción: {}", e);
+        ErrorApi::from(e)
+    })?;
+
     Ok(Json(CambiarContrasenaResponse {
         mensaje: "Contraseña actualizada exitosamente".to_string(),
     }))
 }
 
+// Marca como usados todos los tokens de recuperación pendientes del usuario.
+// Debe llamarse después de cualquier cambio de contraseña.
+async fn invalidar_tokens_recuperacion<'e, E: sqlx::PgExecutor<'e>>(
+    executor: E,
+    usuario_id: i32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE tokens_recuperacion SET used_at = NOW() WHERE usuario_id = $1 AND used_at IS NULL")
+        .bind(usuario_id)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
 // GET /perfiles_examenes
-async fn get_perfiles_examenes(State(pool): State<PgPool>) -> Result<Json<Vec<PerfilExamen>>, StatusCode> {
+async fn get_perfiles_examenes(State(pool): State<PgPool>) -> Result<Json<Vec<PerfilExamen>>, ErrorApi> {
     let rows = sqlx::query("SELECT id, nombre FROM perfiles_examenes")
         .fetch_all(&pool)
         .await
         .map_err(|e| {
             eprintln!("Error al obtener perfiles de exámenes: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            ErrorApi::from(e)
         })?;
 
     let perfiles: Vec<PerfilExamen> = rows
@@ -1031,17 +2589,37 @@ async fn get_perfiles_examenes(State(pool): State<PgPool>) -> Result<Json<Vec<Pe
     Ok(Json(perfiles))
 }
 
+#[derive(Deserialize)]
+struct FiltroExamenes {
+    nombre: Option<String>,
+    perfil_id: Option<i32>,
+}
+
 // GET /examenes
-async fn get_examenes(State(pool): State<PgPool>) -> Result<Json<Vec<ExamenConPerfil>>, StatusCode> {
-    let rows = sqlx::query(
-        "SELECT e.id, e.nombre, e.descripcion, e.referencia_resultado, p.nombre AS perfil_nombre FROM examenes e JOIN perfiles_examenes p ON e.perfil_id = p.id"
+async fn get_examenes(
+    State(pool): State<PgPool>,
+    OriginalUri(uri): OriginalUri,
+    Query(paginacion): Query<Paginacion>,
+    Query(filtro): Query<FiltroExamenes>,
+) -> Result<Response, ErrorApi> {
+    let (rows, total) = paginacion::listar(
+        &pool,
+        "exámenes",
+        "e.id, e.nombre, e.descripcion, e.referencia_resultado, p.nombre AS perfil_nombre",
+        "FROM examenes e JOIN perfiles_examenes p ON e.perfil_id = p.id WHERE TRUE",
+        |consulta| {
+            if let Some(nombre) = &filtro.nombre {
+                consulta.push(" AND e.nombre ILIKE ").push_bind(paginacion::contiene(nombre));
+            }
+            if let Some(perfil_id) = filtro.perfil_id {
+                consulta.push(" AND e.perfil_id = ").push_bind(perfil_id);
+            }
+        },
+        &paginacion,
+        &[("id", "e.id"), ("nombre", "e.nombre"), ("perfil", "p.nombre")],
+        "e.id",
     )
-        .fetch_all(&pool)
-        .await
-        .map_err(|e| {
-            eprintln!("Error al obtener exámenes: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+    .await?;
 
     let examenes: Vec<ExamenConPerfil> = rows
         .into_iter()
@@ -1054,7 +2632,7 @@ async fn get_examenes(State(pool): State<PgPool>) -> Result<Json<Vec<ExamenConPe
         })
         .collect();
 
-    Ok(Json(examenes))
+    Ok(Pagina::new(examenes, total, &paginacion).responder(&uri))
 }
 
 
@@ -1062,14 +2640,14 @@ async fn get_examenes(State(pool): State<PgPool>) -> Result<Json<Vec<ExamenConPe
 async fn get_examenes_por_perfil(
     Path(perfil_id): Path<i32>,
     State(pool): State<PgPool>,
-) -> Result<Json<Vec<Examen>>, StatusCode> {
+) -> Result<Json<Vec<Examen>>, ErrorApi> {
     let rows = sqlx::query("SELECT id, nombre, descripcion, referencia_resultado, perfil_id FROM examenes WHERE perfil_id = $1")
         .bind(perfil_id)
         .fetch_all(&pool)
         .await
         .map_err(|e| {
             eprintln!("Error al obtener exámenes por perfil: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            ErrorApi::from(e)
         })?;
 
     let examenes: Vec<Examen> = rows
@@ -1086,11 +2664,31 @@ async fn get_examenes_por_perfil(
     Ok(Json(examenes))
 }
 
+// Paciente al que pertenece un diagnóstico, para asociarlo en la auditoría
+async fn paciente_de_diagnostico(pool: &PgPool, diagnostico_id: i32) -> Result<i32, ErrorApi> {
+    let row = sqlx::query(
+        "SELECT e.paciente_id FROM expedientes_diagnosticos ed JOIN expedientes e ON ed.expediente_id = e.id WHERE ed.id = $1"
+    )
+    .bind(diagnostico_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al obtener paciente del diagnóstico: {}", e);
+        ErrorApi::from(e)
+    })?
+    .ok_or_else(|| ErrorApi::NoEncontrado("Diagnóstico no encontrado".to_string()))?;
+
+    Ok(row.get("paciente_id"))
+}
+
 // GET /examenes_por_diagnostico/{diagnostico_id}
 async fn get_examenes_por_diagnostico(
     Path(diagnostico_id): Path<i32>,
     State(pool): State<PgPool>,
-) -> Result<Json<Vec<ExamenDiagnosticoConDetalles>>, StatusCode> {
+    actor: Actor,
+) -> Result<Json<Vec<ExamenDiagnosticoConDetalles>>, ErrorApi> {
+    let paciente_id = paciente_de_diagnostico(&pool, diagnostico_id).await?;
+
     let rows = sqlx::query(
         "SELECT ede.id, ede.expediente_diagnostico_id, ede.examen_id, e.nombre AS examen_nombre, e.descripcion AS examen_descripcion, e.referencia_resultado AS examen_referencia, ede.resultado FROM expedientes_diagnosticos_examenes ede JOIN examenes e ON ede.examen_id = e.id WHERE ede.expediente_diagnostico_id = $1"
     )
@@ -1099,7 +2697,7 @@ async fn get_examenes_por_diagnostico(
         .await
         .map_err(|e| {
             eprintln!("Error al obtener exámenes por diagnóstico: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            ErrorApi::from(e)
         })?;
 
     let examenes: Vec<ExamenDiagnosticoConDetalles> = rows
@@ -1115,6 +2713,13 @@ async fn get_examenes_por_diagnostico(
         })
         .collect();
 
+    auditar(
+        &pool,
+        &actor,
+        Evento::lectura("expedientes_diagnosticos_examenes", Some(diagnostico_id), Some(paciente_id)),
+    )
+    .await?;
+
     Ok(Json(examenes))
 }
 
@@ -1122,9 +2727,13 @@ async fn get_examenes_por_diagnostico(
 async fn add_examenes_a_diagnostico(
     Path(diagnostico_id): Path<i32>,
     State(pool): State<PgPool>,
-    Json(examenes_data): Json<Vec<NuevoExamenDiagnostico>>,
-) -> Result<Json<Vec<ExamenDiagnostico>>, StatusCode> {
+    actor: Actor,
+    ValidatedJson(examenes_data): ValidatedJson<Vec<NuevoExamenDiagnostico>>,
+) -> Result<Json<Vec<ExamenDiagnostico>>, ErrorApi> {
+    let paciente_id = paciente_de_diagnostico(&pool, diagnostico_id).await?;
     let mut resultados = Vec::new();
+    let mut tx = iniciar_transaccion(&pool).await?;
+    exigir_paciente_activo(&mut tx, paciente_id).await?;
 
     for examen_data in examenes_data {
         let result = sqlx::query(
@@ -1133,26 +2742,43 @@ async fn add_examenes_a_diagnostico(
             .bind(diagnostico_id)
             .bind(examen_data.examen_id)
             .bind(examen_data.resultado.clone())
-            .fetch_one(&pool)
+            .fetch_one(&mut *tx)
             .await
             .map_err(|e| {
                 eprintln!("Error al agregar examen a diagnóstico: {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
+                ErrorApi::from(e)
             })?;
 
-        resultados.push(ExamenDiagnostico {
+        let examen = ExamenDiagnostico {
             id: result.get("id"),
             expediente_diagnostico_id: diagnostico_id,
             examen_id: examen_data.examen_id,
             resultado: examen_data.resultado,
-        });
+        };
+
+        auditar(
+            &mut *tx,
+            &actor,
+            Evento::cambio(
+                "crear",
+                "expedientes_diagnosticos_examenes",
+                examen.id,
+                Some(paciente_id),
+                None,
+                auditoria::valor(&examen),
+            ),
+        )
+        .await?;
+
+        resultados.push(examen);
     }
 
+    confirmar_transaccion(tx).await?;
+
     Ok(Json(resultados))
 }
 
 
-
 // --- FIN DE FUNCIONES ---
 
 
@@ -1169,19 +2795,71 @@ async fn main() {
     //generar_hash_temporal();
     dotenv::dotenv().ok();
 
-    let database_url = env::var("DATABASE_URL")
-        .expect("DATABASE_URL debe estar configurado");
+    let config = Config::desde_entorno()
+        .unwrap_or_else(|e| panic!("Configuración inválida: {}", e));
 
-    let pool = PgPool::connect(&database_url)
+    let pool = PgPool::connect(&config.database_url)
         .await
         .expect("No se pudo conectar a la base de datos");
 
-let app = Router::new()
+    sqlx::migrate!()
+        .run(&pool)
+        .await
+        .expect("No se pudieron aplicar las migraciones");
+
+    // Calcular el hash ficticio antes de atender el primer login
+    hash_ficticio(&config);
+
+    let mailer = mailer::desde_entorno(config.produccion)
+        .unwrap_or_else(|e| panic!("Configuración de correo inválida: {}", e));
+
+    let notifiers = notificaciones::desde_entorno(config.produccion, mailer.clone())
+        .unwrap_or_else(|e| panic!("Configuración de recordatorios inválida: {}", e));
+
+    let state = AppState {
+        pool,
+        config: Arc::new(config),
+        mailer,
+    };
+
+    recordatorios::iniciar(state.pool.clone(), state.config.clone(), notifiers);
+
+// Rutas públicas: no requieren token
+let publicas = Router::new()
     .route("/login", post(login))
+    .route("/login/2fa", post(dos_factores::login_2fa))
+    .route("/token/refresh", post(sesiones::refrescar_token))
+    .route("/logout", post(sesiones::logout))
     .route("/recuperar-contrasena", post(recuperar_contrasena))
-    .route("/cambiar-contrasena", post(cambiar_contrasena)) 
+    .route("/cambiar-contrasena", post(cambiar_contrasena));
+
+let protegidas = Router::new()
+    .route("/logout-all", post(sesiones::logout_all))
+    .route("/usuarios/me", get(get_usuario_actual))
+    .route("/usuarios/me/contrasena", put(cambiar_mi_contrasena))
+    .route("/usuarios/:id", get(get_usuario_by_id).put(update_usuario))
+    .route("/usuarios/:id/desbloquear", post(bloqueos::desbloquear_usuario))
+    .route("/usuarios/me/2fa/setup", post(dos_factores::setup_2fa))
+    .route("/usuarios/me/2fa/confirm", post(dos_factores::confirmar_2fa))
+    .route("/usuarios/me/2fa/disable", post(dos_factores::desactivar_2fa))
+    // rutas para roles y permisos
+    .route("/roles", get(permisos::get_roles).post(permisos::create_rol))
+    .route("/roles/:id", get(permisos::get_rol_by_id).put(permisos::update_rol).delete(permisos::delete_rol))
+    .route("/roles/:id/permisos", put(permisos::update_permisos_rol))
+    .route("/roles/:id/2fa", put(dos_factores::requerir_2fa_rol))
+    .route("/permisos", get(permisos::get_permisos))
+    .route("/auditoria", get(auditoria::get_auditoria))
     .route("/pacientes", get(get_pacientes).post(create_paciente))
-    .route("/pacientes/:id", get(get_paciente_by_id).put(update_paciente).delete(delete_paciente))
+    .route("/pacientes/buscar", get(buscar_pacientes))
+    .route("/pacientes/duplicados", get(fusiones::get_duplicados))
+    .route(
+        "/pacientes/:id",
+        get(get_paciente_by_id).put(update_paciente).patch(patch_paciente).delete(delete_paciente),
+    )
+    .route("/pacientes/:id/restaurar", post(restaurar_paciente))
+    .route("/pacientes/:id/purgar", delete(purgar_paciente))
+    .route("/pacientes/:id/fusionar", post(fusiones::fusionar_pacientes))
+    .route("/pacientes/:id/fusiones", get(fusiones::get_fusiones))
     // rutas para expedientes
     .route("/expedientes/:paciente_id", get(get_expediente_by_paciente))
     .route("/expedientes/:paciente_id/diagnosticos", get(get_diagnosticos_by_expediente).post(create_diagnostico))
@@ -1189,23 +2867,69 @@ let app = Router::new()
     .route("/usuarios", get(get_usuarios).post(create_usuario))
     // rutas para horarios
     .route("/horarios", get(get_horarios).post(create_horario))
-    .route("/horarios/{id}", get(get_horario_by_id).put(update_horario).delete(delete_horario))
+    .route(
+        "/horarios/:id",
+        get(get_horario_by_id).put(update_horario).patch(patch_horario).delete(delete_horario),
+    )
+    // rutas para disponibilidad
+    .route("/medicos/primer_hueco", get(disponibilidad::get_primer_hueco))
+    .route("/medicos/:usuario_id/disponibilidad", get(disponibilidad::get_disponibilidad))
+    .route(
+        "/medicos/:usuario_id/bloqueos",
+        get(disponibilidad::get_bloqueos).post(disponibilidad::create_bloqueo),
+    )
+    .route("/medicos/:usuario_id/bloqueos/:id", delete(disponibilidad::delete_bloqueo))
+    .route("/feriados", get(disponibilidad::get_feriados).post(disponibilidad::create_feriado))
+    .route("/feriados/:fecha", delete(disponibilidad::delete_feriado))
     // rutas para citas
     .route("/citas", get(get_citas).post(create_cita))
-    .route("/citas/{id}", get(get_cita_by_id).put(update_cita).delete(delete_cita))
+    .route("/citas/:id", get(get_cita_by_id).put(update_cita).patch(patch_cita).delete(delete_cita))
+    .route("/citas/:id/confirmar", post(estados_cita::confirmar_cita))
+    .route("/citas/:id/cancelar", post(estados_cita::cancelar_cita))
+    .route("/citas/:id/checkin", post(estados_cita::checkin_cita))
+    .route("/citas/:id/completar", post(estados_cita::completar_cita))
+    .route("/citas/:id/no_asistio", post(estados_cita::no_asistio_cita))
+    .route("/citas/:id/estados", get(estados_cita::get_estados_cita))
+    .route("/citas/:id/recordatorios", get(recordatorios::get_recordatorios_cita))
+    .route("/citas/series", post(series_citas::create_serie))
+    .route("/citas/series/:id", get(series_citas::get_serie))
+    .route("/citas/:id/serie", patch(series_citas::editar_serie))
+    .route("/citas/:id/serie/cancelar", post(series_citas::cancelar_serie))
     // rutas para exámenes
     .route("/perfiles_examenes", get(get_perfiles_examenes))
     .route("/examenes", get(get_examenes))
-    .route("/examenes_por_perfil/{perfil_id}", get(get_examenes_por_perfil))
+    .route("/examenes_por_perfil/:perfil_id", get(get_examenes_por_perfil))
    
-    .route("/examenes_por_diagnostico/{diagnostico_id}", get(get_examenes_por_diagnostico).post(add_examenes_a_diagnostico))
+    .route("/examenes_por_diagnostico/:diagnostico_id", get(get_examenes_por_diagnostico).post(add_examenes_a_diagnostico))
+    .route_layer(middleware::from_fn_with_state(state.clone(), auth::requerir_autenticacion));
+
+let app = Router::new()
+    .merge(publicas)
+    .merge(protegidas)
+    .layer(middleware::from_fn(errores::asignar_id_solicitud))
     .layer(
         CorsLayer::new()
             .allow_origin("http://localhost:5173".parse::<axum::http::HeaderValue>().unwrap())
-            .allow_methods([axum::http::Method::GET, axum::http::Method::POST, axum::http::Method::PUT, axum::http::Method::DELETE])
-            .allow_headers([axum::http::header::CONTENT_TYPE]),
+            .allow_methods([
+                axum::http::Method::GET,
+                axum::http::Method::POST,
+                axum::http::Method::PUT,
+                axum::http::Method::PATCH,
+                axum::http::Method::DELETE,
+            ])
+            .allow_headers([
+                axum::http::header::CONTENT_TYPE,
+                axum::http::header::AUTHORIZATION,
+                axum::http::header::IF_MATCH,
+                errores::CABECERA_ID_SOLICITUD,
+            ])
+            .expose_headers([
+                errores::CABECERA_ID_SOLICITUD,
+                axum::http::header::LINK,
+                axum::http::header::ETAG,
+            ]),
     )
-    .with_state(pool);
+    .with_state(state);
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000")
         .await
@@ -1213,5 +2937,7 @@ let app = Router::new()
 
     println!("Servidor corriendo en http://0.0.0.0:3000");
 
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .unwrap();
 }
\ No newline at end of file
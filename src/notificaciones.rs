@@ -0,0 +1,201 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use serde_json::json;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+
+use crate::mailer::{Correo, Mailer};
+
+pub struct Notificacion {
+    pub destino: String,
+    pub asunto: String,
+    pub mensaje: String,
+}
+
+// Dato de contacto del paciente al que escribe cada canal
+#[derive(Clone, Copy)]
+pub enum Contacto {
+    Email,
+    Telefono,
+}
+
+impl Contacto {
+    // Columna de `pacientes` con el dato
+    pub fn columna(self) -> &'static str {
+        match self {
+            Contacto::Email => "email",
+            Contacto::Telefono => "telefono",
+        }
+    }
+}
+
+// Canal de envío de notificaciones a pacientes. Los canales activos se
+// eligen con RECORDATORIOS_CANALES (lista separada por comas).
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    // Nombre con el que se guarda el canal en `recordatorios.canal`
+    fn canal(&self) -> &'static str;
+    fn contacto(&self) -> Contacto;
+    async fn enviar(&self, notificacion: &Notificacion) -> Result<(), String>;
+}
+
+pub fn desde_entorno(produccion: bool, mailer: Arc<dyn Mailer>) -> Result<Vec<Arc<dyn Notifier>>, String> {
+    let canales = env::var("RECORDATORIOS_CANALES").unwrap_or_else(|_| {
+        if produccion { "email" } else { "archivo" }.to_string()
+    });
+
+    let mut notifiers: Vec<Arc<dyn Notifier>> = Vec::new();
+    for canal in canales.split(',').map(str::trim).filter(|canal| !canal.is_empty()) {
+        match canal {
+            "email" => notifiers.push(Arc::new(EmailNotifier { mailer: mailer.clone() })),
+            "sms" => notifiers.push(Arc::new(SmsNotifier::desde_entorno()?)),
+            "archivo" => notifiers.push(Arc::new(ArchivoNotifier {
+                ruta: env::var("RECORDATORIOS_ARCHIVO").ok(),
+            })),
+            "ninguno" => {}
+            otro => return Err(format!("Canal de RECORDATORIOS_CANALES no soportado: {}", otro)),
+        }
+    }
+
+    Ok(notifiers)
+}
+
+// Correo por el mismo transporte SMTP que usa el resto de la aplicación
+pub struct EmailNotifier {
+    mailer: Arc<dyn Mailer>,
+}
+
+#[async_trait]
+impl Notifier for EmailNotifier {
+    fn canal(&self) -> &'static str {
+        "email"
+    }
+
+    fn contacto(&self) -> Contacto {
+        Contacto::Email
+    }
+
+    async fn enviar(&self, notificacion: &Notificacion) -> Result<(), String> {
+        self.mailer
+            .enviar(&Correo {
+                para: notificacion.destino.clone(),
+                asunto: notificacion.asunto.clone(),
+                cuerpo: notificacion.mensaje.clone(),
+            })
+            .await
+    }
+}
+
+// SMS a través de una pasarela HTTP: POST JSON {"to", "message"} a
+// SMS_GATEWAY_URL, con SMS_GATEWAY_TOKEN como Bearer si está definido.
+// Cualquier respuesta que no sea 2xx cuenta como fallo.
+pub struct SmsNotifier {
+    cliente: reqwest::Client,
+    url: String,
+    token: Option<String>,
+    remitente: Option<String>,
+}
+
+impl SmsNotifier {
+    fn desde_entorno() -> Result<Self, String> {
+        let url = env::var("SMS_GATEWAY_URL").map_err(|_| "SMS_GATEWAY_URL debe estar configurado".to_string())?;
+        let cliente = reqwest::Client::builder()
+            .timeout(Duration::from_secs(15))
+            .build()
+            .map_err(|e| format!("No se pudo crear el cliente HTTP: {}", e))?;
+
+        Ok(SmsNotifier {
+            cliente,
+            url,
+            token: env::var("SMS_GATEWAY_TOKEN").ok(),
+            remitente: env::var("SMS_REMITENTE").ok(),
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for SmsNotifier {
+    fn canal(&self) -> &'static str {
+        "sms"
+    }
+
+    fn contacto(&self) -> Contacto {
+        Contacto::Telefono
+    }
+
+    async fn enviar(&self, notificacion: &Notificacion) -> Result<(), String> {
+        let mut solicitud = self.cliente.post(&self.url).json(&json!({
+            "to": notificacion.destino,
+            "from": self.remitente,
+            "message": notificacion.mensaje,
+        }));
+        if let Some(token) = &self.token {
+            solicitud = solicitud.bearer_auth(token);
+        }
+
+        let respuesta = solicitud
+            .send()
+            .await
+            .map_err(|e| format!("No se pudo contactar la pasarela SMS: {}", e))?;
+
+        let estado = respuesta.status();
+        if estado.is_success() {
+            return Ok(());
+        }
+        let cuerpo = respuesta.text().await.unwrap_or_default();
+        Err(format!(
+            "La pasarela SMS respondió {}: {}",
+            estado,
+            cuerpo.chars().take(200).collect::<String>()
+        ))
+    }
+}
+
+// Para desarrollo y pruebas: escribe las notificaciones en un archivo o, si
+// no hay ruta, en la consola
+pub struct ArchivoNotifier {
+    ruta: Option<String>,
+}
+
+#[async_trait]
+impl Notifier for ArchivoNotifier {
+    fn canal(&self) -> &'static str {
+        "archivo"
+    }
+
+    fn contacto(&self) -> Contacto {
+        Contacto::Email
+    }
+
+    async fn enviar(&self, notificacion: &Notificacion) -> Result<(), String> {
+        let texto = format!(
+            "--- {} ---\nPara: {}\nAsunto: {}\n\n{}\n\n",
+            Utc::now().to_rfc3339(),
+            notificacion.destino,
+            notificacion.asunto,
+            notificacion.mensaje
+        );
+
+        match &self.ruta {
+            Some(ruta) => {
+                let mut archivo = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(ruta)
+                    .await
+                    .map_err(|e| format!("No se pudo abrir {}: {}", ruta, e))?;
+                archivo
+                    .write_all(texto.as_bytes())
+                    .await
+                    .map_err(|e| format!("No se pudo escribir en {}: {}", ruta, e))
+            }
+            None => {
+                println!("{}", texto);
+                Ok(())
+            }
+        }
+    }
+}
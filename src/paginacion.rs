@@ -0,0 +1,324 @@
+use axum::{
+    http::{header, HeaderValue, Uri},
+    response::{IntoResponse, Json, Response},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgRow, PgPool, Postgres, QueryBuilder, Row};
+
+use crate::errores::{ErrorApi, ErrorCampo};
+
+const POR_PAGINA_DEFECTO: i64 = 20;
+const POR_PAGINA_MAXIMO: i64 = 100;
+// Tope de `page`: mantiene OFFSET y los enlaces lejos del desbordamiento.
+// Una página más allá de la última simplemente llega vacía.
+const PAGINA_MAXIMA: i64 = 1_000_000;
+
+// Parámetros comunes de los listados: ?page=&per_page=&sort=&order=
+#[derive(Deserialize)]
+pub struct Paginacion {
+    page: Option<i64>,
+    per_page: Option<i64>,
+    sort: Option<String>,
+    order: Option<String>,
+}
+
+impl Paginacion {
+    pub fn pagina(&self) -> i64 {
+        self.page.unwrap_or(1).clamp(1, PAGINA_MAXIMA)
+    }
+
+    pub fn por_pagina(&self) -> i64 {
+        self.per_page.unwrap_or(POR_PAGINA_DEFECTO).clamp(1, POR_PAGINA_MAXIMO)
+    }
+
+    // Cláusula ORDER BY. `columnas` asocia cada nombre aceptado en `sort`
+    // con su expresión SQL; el id se agrega al final para que el orden sea
+    // estable entre páginas.
+    pub fn ordenar(
+        &self,
+        consulta: &mut QueryBuilder<'_, Postgres>,
+        columnas: &[(&str, &str)],
+        desempate: &str,
+    ) -> Result<(), ErrorApi> {
+        let mut errores = Vec::new();
+
+        let columna = match self.sort.as_deref() {
+            None => columnas[0].1,
+            Some(sort) => match columnas.iter().find(|(nombre, _)| *nombre == sort) {
+                Some((_, columna)) => columna,
+                None => {
+                    let aceptados: Vec<&str> = columnas.iter().map(|(nombre, _)| *nombre).collect();
+                    errores.push(ErrorCampo::new(
+                        "sort",
+                        format!("Solo se puede ordenar por: {}", aceptados.join(", ")),
+                    ));
+                    columnas[0].1
+                }
+            },
+        };
+
+        let direccion = match self.order.as_deref() {
+            None | Some("asc") => "ASC",
+            Some("desc") => "DESC",
+            Some(_) => {
+                errores.push(ErrorCampo::new("order", "El orden debe ser asc o desc"));
+                "ASC"
+            }
+        };
+
+        if !errores.is_empty() {
+            return Err(ErrorApi::Validacion(errores));
+        }
+
+        consulta
+            .push(" ORDER BY ")
+            .push(columna)
+            .push(" ")
+            .push(direccion)
+            .push(", ")
+            .push(desempate)
+            .push(" ")
+            .push(direccion);
+
+        Ok(())
+    }
+
+    pub fn limitar(&self, consulta: &mut QueryBuilder<'_, Postgres>) {
+        consulta
+            .push(" LIMIT ")
+            .push_bind(self.por_pagina())
+            .push(" OFFSET ")
+            .push_bind((self.pagina() - 1) * self.por_pagina());
+    }
+}
+
+// Ejecuta un listado paginado: primero el total y después la página.
+// `desde` es la parte FROM ... WHERE común a ambas consultas y `filtrar`
+// agrega las condiciones de la solicitud (cada una empezando por " AND ").
+#[allow(clippy::too_many_arguments)]
+pub async fn listar(
+    pool: &PgPool,
+    contexto: &str,
+    columnas: &str,
+    desde: &str,
+    filtrar: impl Fn(&mut QueryBuilder<'static, Postgres>),
+    paginacion: &Paginacion,
+    orden: &[(&str, &str)],
+    desempate: &str,
+) -> Result<(Vec<PgRow>, i64), ErrorApi> {
+    let mut conteo = QueryBuilder::new(format!("SELECT COUNT(*) AS total {}", desde));
+    filtrar(&mut conteo);
+
+    let mut consulta = QueryBuilder::new(format!("SELECT {} {}", columnas, desde));
+    filtrar(&mut consulta);
+    paginacion.ordenar(&mut consulta, orden, desempate)?;
+    paginacion.limitar(&mut consulta);
+
+    let total: i64 = conteo
+        .build()
+        .fetch_one(pool)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al contar {}: {}", contexto, e);
+            ErrorApi::from(e)
+        })?
+        .get("total");
+
+    let rows = consulta.build().fetch_all(pool).await.map_err(|e| {
+        eprintln!("Error en la consulta de {}: {}", contexto, e);
+        ErrorApi::from(e)
+    })?;
+
+    Ok((rows, total))
+}
+
+// Patrón ILIKE que busca el texto en cualquier posición, escapando comodines
+pub fn contiene(texto: &str) -> String {
+    let escapado = texto
+        .trim()
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escapado)
+}
+
+// Respuesta paginada: los datos de la página más los totales
+#[derive(Serialize)]
+pub struct Pagina<T> {
+    datos: Vec<T>,
+    total: i64,
+    pagina: i64,
+    por_pagina: i64,
+    paginas: i64,
+}
+
+impl<T: Serialize> Pagina<T> {
+    pub fn new(datos: Vec<T>, total: i64, paginacion: &Paginacion) -> Self {
+        let por_pagina = paginacion.por_pagina();
+        Pagina {
+            datos,
+            total,
+            pagina: paginacion.pagina(),
+            por_pagina,
+            paginas: (total + por_pagina - 1) / por_pagina,
+        }
+    }
+
+    // Responde el sobre JSON con la cabecera Link (first, prev, next, last)
+    // construida sobre la misma URI de la solicitud
+    pub fn responder(self, uri: &Uri) -> Response {
+        let enlaces = self.enlaces(uri);
+        let mut respuesta = Json(self).into_response();
+
+        if let Ok(valor) = HeaderValue::from_str(&enlaces) {
+            if !enlaces.is_empty() {
+                respuesta.headers_mut().insert(header::LINK, valor);
+            }
+        }
+
+        respuesta
+    }
+
+    fn enlaces(&self, uri: &Uri) -> String {
+        let ultima = self.paginas.max(1);
+        let mut relaciones = vec![("first", 1)];
+        if self.pagina > 1 {
+            relaciones.push(("prev", (self.pagina - 1).min(ultima)));
+        }
+        if self.pagina < ultima {
+            relaciones.push(("next", self.pagina + 1));
+        }
+        relaciones.push(("last", ultima));
+
+        relaciones
+            .into_iter()
+            .map(|(rel, pagina)| format!("<{}>; rel=\"{}\"", url_pagina(uri, pagina), rel))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+// Misma ruta y parámetros, reemplazando `page`
+fn url_pagina(uri: &Uri, pagina: i64) -> String {
+    let mut parametros: Vec<String> = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|par| !par.is_empty() && !par.starts_with("page="))
+        .map(str::to_string)
+        .collect();
+    parametros.push(format!("page={}", pagina));
+
+    format!("{}?{}", uri.path(), parametros.join("&"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paginacion(page: Option<i64>, per_page: Option<i64>) -> Paginacion {
+        Paginacion {
+            page,
+            per_page,
+            sort: None,
+            order: None,
+        }
+    }
+
+    #[test]
+    fn pagina_por_defecto_y_limites() {
+        assert_eq!(paginacion(None, None).pagina(), 1);
+        assert_eq!(paginacion(Some(0), None).pagina(), 1);
+        assert_eq!(paginacion(Some(-5), None).pagina(), 1);
+        assert_eq!(paginacion(Some(7), None).pagina(), 7);
+        assert_eq!(paginacion(Some(i64::MAX), None).pagina(), PAGINA_MAXIMA);
+    }
+
+    #[test]
+    fn por_pagina_por_defecto_y_limites() {
+        assert_eq!(paginacion(None, None).por_pagina(), POR_PAGINA_DEFECTO);
+        assert_eq!(paginacion(None, Some(0)).por_pagina(), 1);
+        assert_eq!(paginacion(None, Some(50)).por_pagina(), 50);
+        assert_eq!(paginacion(None, Some(i64::MAX)).por_pagina(), POR_PAGINA_MAXIMO);
+    }
+
+    #[test]
+    fn limitar_no_desborda_con_paginas_enormes() {
+        let mut consulta: QueryBuilder<Postgres> = QueryBuilder::new("SELECT 1");
+        paginacion(Some(i64::MAX), Some(i64::MAX)).limitar(&mut consulta);
+        assert_eq!(consulta.sql(), "SELECT 1 LIMIT $1 OFFSET $2");
+    }
+
+    #[test]
+    fn ordenar_valida_sort_y_order() {
+        let columnas = [("apellido", "p.apellido"), ("nombre", "p.nombre")];
+
+        let mut consulta: QueryBuilder<Postgres> = QueryBuilder::new("SELECT 1");
+        let mut orden = paginacion(None, None);
+        orden.sort = Some("nombre".to_string());
+        orden.order = Some("desc".to_string());
+        assert!(orden.ordenar(&mut consulta, &columnas, "p.id").is_ok());
+        assert_eq!(consulta.sql(), "SELECT 1 ORDER BY p.nombre DESC, p.id DESC");
+
+        let mut consulta: QueryBuilder<Postgres> = QueryBuilder::new("SELECT 1");
+        orden.sort = Some("ci; DROP TABLE pacientes".to_string());
+        orden.order = Some("arriba".to_string());
+        match orden.ordenar(&mut consulta, &columnas, "p.id") {
+            Err(ErrorApi::Validacion(errores)) => {
+                let campos: Vec<&str> = errores.iter().map(|error| error.campo.as_str()).collect();
+                assert_eq!(campos, vec!["sort", "order"]);
+            }
+            _ => panic!("se esperaba un error de validación"),
+        }
+    }
+
+    #[test]
+    fn url_pagina_reemplaza_solo_page() {
+        let uri: Uri = "/pacientes?page=3&per_page=10&sort=apellido".parse().unwrap();
+        assert_eq!(url_pagina(&uri, 4), "/pacientes?per_page=10&sort=apellido&page=4");
+
+        let uri: Uri = "/pacientes".parse().unwrap();
+        assert_eq!(url_pagina(&uri, 2), "/pacientes?page=2");
+
+        // `per_page` no se confunde con `page`
+        let uri: Uri = "/citas?per_page=5".parse().unwrap();
+        assert_eq!(url_pagina(&uri, 1), "/citas?per_page=5&page=1");
+    }
+
+    #[test]
+    fn enlaces_segun_la_pagina() {
+        let uri: Uri = "/citas?per_page=10".parse().unwrap();
+        let enlaces = |page, total| Pagina::new(Vec::<i32>::new(), total, &paginacion(Some(page), Some(10))).enlaces(&uri);
+
+        assert_eq!(
+            enlaces(1, 35),
+            "</citas?per_page=10&page=1>; rel=\"first\", </citas?per_page=10&page=2>; rel=\"next\", </citas?per_page=10&page=4>; rel=\"last\""
+        );
+        assert_eq!(
+            enlaces(2, 35),
+            "</citas?per_page=10&page=1>; rel=\"first\", </citas?per_page=10&page=1>; rel=\"prev\", </citas?per_page=10&page=3>; rel=\"next\", </citas?per_page=10&page=4>; rel=\"last\""
+        );
+        assert_eq!(
+            enlaces(4, 35),
+            "</citas?per_page=10&page=1>; rel=\"first\", </citas?per_page=10&page=3>; rel=\"prev\", </citas?per_page=10&page=4>; rel=\"last\""
+        );
+        // Sin resultados hay una sola página
+        assert_eq!(
+            enlaces(1, 0),
+            "</citas?per_page=10&page=1>; rel=\"first\", </citas?per_page=10&page=1>; rel=\"last\""
+        );
+        // Más allá de la última: prev apunta a la última y no hay next
+        assert_eq!(
+            enlaces(i64::MAX, 35),
+            "</citas?per_page=10&page=1>; rel=\"first\", </citas?per_page=10&page=4>; rel=\"prev\", </citas?per_page=10&page=4>; rel=\"last\""
+        );
+    }
+
+    #[test]
+    fn total_de_paginas() {
+        let paginas = |total| Pagina::new(Vec::<i32>::new(), total, &paginacion(None, Some(10))).paginas;
+        assert_eq!(paginas(0), 0);
+        assert_eq!(paginas(10), 1);
+        assert_eq!(paginas(11), 2);
+    }
+}
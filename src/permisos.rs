@@ -0,0 +1,276 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgExecutor, PgPool, Row};
+
+use crate::errores::{ErrorApi, ErrorCampo};
+use crate::{confirmar_transaccion, iniciar_transaccion};
+
+#[derive(Serialize, Deserialize)]
+pub struct Permiso {
+    id: i32,
+    codigo: String,
+    descripcion: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Rol {
+    id: i32,
+    nombre: String,
+    requiere_2fa: bool,
+    permisos: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NuevoRol {
+    nombre: String,
+    #[serde(default)]
+    permisos: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RenombrarRol {
+    nombre: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PermisosRol {
+    permisos: Vec<String>,
+}
+
+// Códigos de permiso del rol asignado al usuario
+pub async fn permisos_de_usuario<'e, E: PgExecutor<'e>>(
+    executor: E,
+    usuario_id: i32,
+) -> Result<Vec<String>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT p.codigo FROM usuarios u JOIN roles_permisos rp ON rp.rol_id = u.rol_id JOIN permisos p ON p.id = rp.permiso_id WHERE u.id = $1 ORDER BY p.codigo"
+    )
+    .bind(usuario_id)
+    .fetch_all(executor)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| row.get("codigo")).collect())
+}
+
+async fn obtener_rol(pool: &PgPool, id: i32) -> Result<Rol, ErrorApi> {
+    let row = sqlx::query(
+        "SELECT r.id, r.nombre, r.requiere_2fa, COALESCE(ARRAY_AGG(p.codigo ORDER BY p.codigo) FILTER (WHERE p.codigo IS NOT NULL), '{}') AS permisos FROM roles r LEFT JOIN roles_permisos rp ON rp.rol_id = r.id LEFT JOIN permisos p ON p.id = rp.permiso_id WHERE r.id = $1 GROUP BY r.id"
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al obtener rol: {}", e);
+        ErrorApi::from(e)
+    })?
+    .ok_or_else(|| ErrorApi::no_encontrado("Rol"))?;
+
+    Ok(Rol {
+        id: row.get("id"),
+        nombre: row.get("nombre"),
+        requiere_2fa: row.get("requiere_2fa"),
+        permisos: row.get("permisos"),
+    })
+}
+
+// Reemplaza los permisos del rol dentro de la transacción del llamador.
+// Devuelve 422 con los códigos que no existen.
+async fn asignar_permisos(conn: &mut PgConnection, rol_id: i32, permisos: &[String]) -> Result<(), ErrorApi> {
+    sqlx::query("DELETE FROM roles_permisos WHERE rol_id = $1")
+        .bind(rol_id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al limpiar permisos del rol: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    let resultado = sqlx::query(
+        "INSERT INTO roles_permisos (rol_id, permiso_id) SELECT $1, id FROM permisos WHERE codigo = ANY($2)"
+    )
+    .bind(rol_id)
+    .bind(permisos)
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al asignar permisos al rol: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    let mut unicos = permisos.to_vec();
+    unicos.sort();
+    unicos.dedup();
+    if resultado.rows_affected() != unicos.len() as u64 {
+        eprintln!("Permisos desconocidos en la asignación al rol {}", rol_id);
+        let conocidos: Vec<String> = sqlx::query("SELECT codigo FROM permisos WHERE codigo = ANY($1)")
+            .bind(&unicos)
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(|e| {
+                eprintln!("Error al obtener permisos: {}", e);
+                ErrorApi::from(e)
+            })?
+            .into_iter()
+            .map(|row| row.get("codigo"))
+            .collect();
+        let desconocidos: Vec<String> = unicos.into_iter().filter(|codigo| !conocidos.contains(codigo)).collect();
+        return Err(ErrorApi::Validacion(vec![ErrorCampo::new(
+            "permisos",
+            format!("Permisos desconocidos: {}", desconocidos.join(", ")),
+        )]));
+    }
+
+    Ok(())
+}
+
+// GET /permisos
+pub async fn get_permisos(State(pool): State<PgPool>) -> Result<Json<Vec<Permiso>>, ErrorApi> {
+    let rows = sqlx::query("SELECT id, codigo, descripcion FROM permisos ORDER BY codigo")
+        .fetch_all(&pool)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al obtener permisos: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    let permisos: Vec<Permiso> = rows
+        .into_iter()
+        .map(|row| Permiso {
+            id: row.get("id"),
+            codigo: row.get("codigo"),
+            descripcion: row.get("descripcion"),
+        })
+        .collect();
+
+    Ok(Json(permisos))
+}
+
+// GET /roles
+pub async fn get_roles(State(pool): State<PgPool>) -> Result<Json<Vec<Rol>>, ErrorApi> {
+    let rows = sqlx::query(
+        "SELECT r.id, r.nombre, r.requiere_2fa, COALESCE(ARRAY_AGG(p.codigo ORDER BY p.codigo) FILTER (WHERE p.codigo IS NOT NULL), '{}') AS permisos FROM roles r LEFT JOIN roles_permisos rp ON rp.rol_id = r.id LEFT JOIN permisos p ON p.id = rp.permiso_id GROUP BY r.id ORDER BY r.nombre"
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al obtener roles: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    let roles: Vec<Rol> = rows
+        .into_iter()
+        .map(|row| Rol {
+            id: row.get("id"),
+            nombre: row.get("nombre"),
+            requiere_2fa: row.get("requiere_2fa"),
+            permisos: row.get("permisos"),
+        })
+        .collect();
+
+    Ok(Json(roles))
+}
+
+// POST /roles
+pub async fn create_rol(
+    State(pool): State<PgPool>,
+    Json(rol): Json<NuevoRol>,
+) -> Result<Json<Rol>, ErrorApi> {
+    let mut tx = iniciar_transaccion(&pool).await?;
+
+    let result = sqlx::query("INSERT INTO roles (nombre) VALUES ($1) RETURNING id")
+        .bind(rol.nombre.trim())
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al crear rol: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    let id: i32 = result.get("id");
+    asignar_permisos(&mut tx, id, &rol.permisos).await?;
+
+    confirmar_transaccion(tx).await?;
+
+    Ok(Json(obtener_rol(&pool, id).await?))
+}
+
+// GET /roles/:id
+pub async fn get_rol_by_id(
+    Path(id): Path<i32>,
+    State(pool): State<PgPool>,
+) -> Result<Json<Rol>, ErrorApi> {
+    Ok(Json(obtener_rol(&pool, id).await?))
+}
+
+// PUT /roles/:id
+pub async fn update_rol(
+    Path(id): Path<i32>,
+    State(pool): State<PgPool>,
+    Json(rol): Json<RenombrarRol>,
+) -> Result<Json<Rol>, ErrorApi> {
+    let resultado = sqlx::query("UPDATE roles SET nombre = $1 WHERE id = $2")
+        .bind(rol.nombre.trim())
+        .bind(id)
+        .execute(&pool)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al actualizar rol: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    if resultado.rows_affected() == 0 {
+        return Err(ErrorApi::no_encontrado("Rol"));
+    }
+
+    Ok(Json(obtener_rol(&pool, id).await?))
+}
+
+// PUT /roles/:id/permisos
+pub async fn update_permisos_rol(
+    Path(id): Path<i32>,
+    State(pool): State<PgPool>,
+    Json(request): Json<PermisosRol>,
+) -> Result<Json<Rol>, ErrorApi> {
+    // Verificar que el rol exista antes de tocar sus permisos
+    obtener_rol(&pool, id).await?;
+
+    let mut tx = iniciar_transaccion(&pool).await?;
+
+    asignar_permisos(&mut tx, id, &request.permisos).await?;
+
+    confirmar_transaccion(tx).await?;
+
+    Ok(Json(obtener_rol(&pool, id).await?))
+}
+
+// DELETE /roles/:id
+pub async fn delete_rol(
+    Path(id): Path<i32>,
+    State(pool): State<PgPool>,
+) -> Result<StatusCode, ErrorApi> {
+    let resultado = sqlx::query("DELETE FROM roles WHERE id = $1")
+        .bind(id)
+        .execute(&pool)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al eliminar rol: {}", e);
+            // 23503: todavía hay usuarios con este rol
+            match ErrorApi::from(e) {
+                ErrorApi::ReferenciaInvalida { detalles, .. } => ErrorApi::ReferenciaInvalida {
+                    mensaje: "El rol todavía está asignado a usuarios".to_string(),
+                    detalles,
+                },
+                otro => otro,
+            }
+        })?;
+
+    if resultado.rows_affected() == 0 {
+        return Err(ErrorApi::no_encontrado("Rol"));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
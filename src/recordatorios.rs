@@ -0,0 +1,355 @@
+// Recordatorios de citas. Un trabajador dentro del runtime de tokio revisa
+// la cola cada `intervalo`: encola los recordatorios cuya antelación ya llegó
+// (uno por cita, antelación y canal) y entrega los pendientes, reintentando
+// los fallos con una espera que se duplica hasta agotar los intentos.
+
+use axum::{
+    extract::{Path, State},
+    response::Json,
+};
+use chrono::{Duration, Local, NaiveDateTime};
+use serde::Serialize;
+use sqlx::{PgPool, Row};
+use std::sync::Arc;
+use tokio::time::MissedTickBehavior;
+
+use crate::auditoria::{Actor, Evento};
+use crate::auditar;
+use crate::config::{Config, RecordatoriosConfig};
+use crate::errores::ErrorApi;
+use crate::estados_cita::EstadoCita;
+use crate::notificaciones::{Notificacion, Notifier};
+
+// Recordatorios que se toman de la cola en cada vuelta
+const LOTE: i64 = 50;
+// Tiempo que un recordatorio queda reservado mientras se envía, para que
+// otra instancia de la API no lo tome a la vez
+const RESERVA_MINUTOS: i64 = 10;
+
+#[derive(Serialize)]
+pub struct Recordatorio {
+    id: i32,
+    cita_id: i32,
+    fecha_cita: NaiveDateTime,
+    antelacion_minutos: i32,
+    canal: String,
+    destino: String,
+    // pendiente, enviado, fallido o descartado
+    estado: String,
+    intentos: i32,
+    ultimo_error: Option<String>,
+    proximo_intento: NaiveDateTime,
+    enviado_en: Option<NaiveDateTime>,
+    creado_en: NaiveDateTime,
+}
+
+pub fn iniciar(pool: PgPool, config: Arc<Config>, notifiers: Vec<Arc<dyn Notifier>>) {
+    if notifiers.is_empty() || config.recordatorios.antelaciones.is_empty() {
+        println!("Recordatorios de citas desactivados");
+        return;
+    }
+
+    tokio::spawn(async move {
+        let config = &config.recordatorios;
+        let mut reloj = tokio::time::interval(std::time::Duration::from_secs(config.intervalo.max(1)));
+        reloj.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            reloj.tick().await;
+
+            if let Err(e) = encolar(&pool, config, &notifiers).await {
+                eprintln!("Error al encolar recordatorios: {}", e);
+            }
+            if let Err(e) = entregar(&pool, config, &notifiers).await {
+                eprintln!("Error al entregar recordatorios: {}", e);
+            }
+        }
+    });
+}
+
+// Antelación cuyo recordatorio toca enviar a una cita que empieza dentro de
+// `falta`: la menor de las ya alcanzadas. Si para la cita ya toca también una
+// antelación menor, las mayores se saltan: una cita reservada con 3 h de
+// margen solo recibe el aviso de 2 h. `None` si aún no toca ninguna o si la
+// cita ya empezó.
+fn antelacion_vigente(antelaciones: &[i32], falta: Duration) -> Option<i32> {
+    if falta <= Duration::zero() {
+        return None;
+    }
+    antelaciones
+        .iter()
+        .copied()
+        .filter(|antelacion| Duration::minutes((*antelacion).into()) >= falta)
+        .min()
+}
+
+// Próximo intento tras el fallo número `intentos`, con una espera de
+// `reintento_base * 2^(intentos - 1)` segundos. `None` si se agotaron los
+// intentos o si el reintento llegaría cuando la cita ya empezó: el
+// recordatorio se da por fallido.
+fn proximo_reintento(
+    config: &RecordatoriosConfig,
+    intentos: i32,
+    ahora: NaiveDateTime,
+    fecha_cita: NaiveDateTime,
+) -> Option<NaiveDateTime> {
+    if intentos >= config.max_intentos {
+        return None;
+    }
+    let espera = config.reintento_base.saturating_mul(1 << (intentos - 1).clamp(0, 10));
+    let proximo = ahora + Duration::seconds(espera as i64);
+    (proximo < fecha_cita).then_some(proximo)
+}
+
+async fn encolar(
+    pool: &PgPool,
+    config: &RecordatoriosConfig,
+    notifiers: &[Arc<dyn Notifier>],
+) -> Result<(), sqlx::Error> {
+    let ahora = Local::now().naive_local();
+    let Some(mayor) = config.antelaciones.iter().max() else {
+        return Ok(());
+    };
+
+    let citas = sqlx::query(
+        "SELECT c.id, c.fecha_hora, trim(p.email) AS email, trim(p.telefono) AS telefono FROM citas c JOIN pacientes p ON p.id = c.paciente_id WHERE c.estado IN ('programada', 'confirmada') AND p.archivado_en IS NULL AND c.fecha_hora > $1 AND c.fecha_hora <= $1 + make_interval(mins => $2)"
+    )
+    .bind(ahora)
+    .bind(mayor)
+    .fetch_all(pool)
+    .await?;
+
+    for cita in citas {
+        let fecha_cita: NaiveDateTime = cita.get("fecha_hora");
+        let Some(antelacion) = antelacion_vigente(&config.antelaciones, fecha_cita - ahora) else {
+            continue;
+        };
+
+        for notifier in notifiers {
+            let destino: Option<String> = cita.get(notifier.contacto().columna());
+            let Some(destino) = destino.filter(|destino| !destino.is_empty()) else {
+                continue;
+            };
+
+            // La restricción única evita repetir el aviso en las siguientes vueltas
+            sqlx::query(
+                "INSERT INTO recordatorios (cita_id, fecha_cita, antelacion_minutos, canal, destino, proximo_intento) VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT DO NOTHING"
+            )
+            .bind(cita.get::<i32, _>("id"))
+            .bind(fecha_cita)
+            .bind(antelacion)
+            .bind(notifier.canal())
+            .bind(destino)
+            .bind(ahora)
+            .execute(pool)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn entregar(
+    pool: &PgPool,
+    config: &RecordatoriosConfig,
+    notifiers: &[Arc<dyn Notifier>],
+) -> Result<(), sqlx::Error> {
+    let ahora = Local::now().naive_local();
+
+    let rows = sqlx::query(
+        "WITH reservados AS (UPDATE recordatorios SET proximo_intento = $2 WHERE id IN (SELECT id FROM recordatorios WHERE estado = 'pendiente' AND proximo_intento <= $1 ORDER BY proximo_intento LIMIT $3 FOR UPDATE SKIP LOCKED) RETURNING id, cita_id, fecha_cita, canal, destino, intentos) SELECT r.id, r.fecha_cita, r.canal, r.destino, r.intentos, c.fecha_hora, c.estado, p.nombre, p.apellido, p.archivado_en IS NOT NULL AS archivado, u.nombre AS nombre_medico, u.apellido AS apellido_medico FROM reservados r JOIN citas c ON c.id = r.cita_id JOIN pacientes p ON p.id = c.paciente_id JOIN usuarios u ON u.id = c.usuario_id"
+    )
+    .bind(ahora)
+    .bind(ahora + Duration::minutes(RESERVA_MINUTOS))
+    .bind(LOTE)
+    .fetch_all(pool)
+    .await?;
+
+    for row in rows {
+        let id: i32 = row.get("id");
+        let canal: String = row.get("canal");
+        let fecha_cita: NaiveDateTime = row.get("fecha_cita");
+
+        // La cita pudo moverse, cancelarse o atenderse desde que se encoló
+        let estado = EstadoCita::desde_columna(row.get("estado"));
+        let vigente = row.get::<NaiveDateTime, _>("fecha_hora") == fecha_cita
+            && matches!(estado, EstadoCita::Programada | EstadoCita::Confirmada)
+            && fecha_cita > ahora;
+        if !vigente {
+            descartar(pool, id, "La cita cambió de hora o de estado").await?;
+            continue;
+        }
+        if row.get::<bool, _>("archivado") {
+            descartar(pool, id, "El paciente está archivado").await?;
+            continue;
+        }
+
+        let Some(notifier) = notifiers.iter().find(|notifier| notifier.canal() == canal) else {
+            descartar(pool, id, "El canal ya no está configurado").await?;
+            continue;
+        };
+
+        let notificacion = Notificacion {
+            destino: row.get("destino"),
+            asunto: "Recordatorio de cita".to_string(),
+            mensaje: format!(
+                "Hola {} {}, le recordamos su cita con {} {} el {} a las {}. Si no puede asistir, por favor avísenos.",
+                row.get::<String, _>("nombre"),
+                row.get::<String, _>("apellido"),
+                row.get::<String, _>("nombre_medico"),
+                row.get::<String, _>("apellido_medico"),
+                fecha_cita.format("%d/%m/%Y"),
+                fecha_cita.format("%H:%M"),
+            ),
+        };
+
+        match notifier.enviar(&notificacion).await {
+            Ok(()) => {
+                sqlx::query(
+                    "UPDATE recordatorios SET estado = 'enviado', intentos = intentos + 1, ultimo_error = NULL, enviado_en = $2 WHERE id = $1"
+                )
+                .bind(id)
+                .bind(Local::now().naive_local())
+                .execute(pool)
+                .await?;
+            }
+            Err(error) => {
+                eprintln!("Error al enviar recordatorio {} por {}: {}", id, canal, error);
+
+                let intentos = row.get::<i32, _>("intentos") + 1;
+                let proximo = proximo_reintento(config, intentos, Local::now().naive_local(), fecha_cita);
+
+                sqlx::query(
+                    "UPDATE recordatorios SET estado = CASE WHEN $2 THEN 'pendiente' ELSE 'fallido' END, intentos = $3, ultimo_error = $4, proximo_intento = COALESCE($5, proximo_intento) WHERE id = $1"
+                )
+                .bind(id)
+                .bind(proximo.is_some())
+                .bind(intentos)
+                .bind(&error)
+                .bind(proximo)
+                .execute(pool)
+                .await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn descartar(pool: &PgPool, id: i32, motivo: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE recordatorios SET estado = 'descartado', ultimo_error = $2 WHERE id = $1")
+        .bind(id)
+        .bind(motivo)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// GET /citas/:id/recordatorios
+pub async fn get_recordatorios_cita(
+    Path(id): Path<i32>,
+    State(pool): State<PgPool>,
+    actor: Actor,
+) -> Result<Json<Vec<Recordatorio>>, ErrorApi> {
+    let rows = sqlx::query(
+        "SELECT id, cita_id, fecha_cita, antelacion_minutos, canal, destino, estado, intentos, ultimo_error, proximo_intento, enviado_en, creado_en FROM recordatorios WHERE cita_id = $1 ORDER BY creado_en, id"
+    )
+    .bind(id)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al obtener recordatorios de la cita {}: {}", id, e);
+        ErrorApi::from(e)
+    })?;
+
+    let recordatorios: Vec<Recordatorio> = rows
+        .into_iter()
+        .map(|row| Recordatorio {
+            id: row.get("id"),
+            cita_id: row.get("cita_id"),
+            fecha_cita: row.get("fecha_cita"),
+            antelacion_minutos: row.get("antelacion_minutos"),
+            canal: row.get("canal"),
+            destino: row.get("destino"),
+            estado: row.get("estado"),
+            intentos: row.get("intentos"),
+            ultimo_error: row.get("ultimo_error"),
+            proximo_intento: row.get("proximo_intento"),
+            enviado_en: row.get("enviado_en"),
+            creado_en: row.get("creado_en"),
+        })
+        .collect();
+
+    auditar(&pool, &actor, Evento::lectura("recordatorios", Some(id), None)).await?;
+
+    Ok(Json(recordatorios))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn config() -> RecordatoriosConfig {
+        RecordatoriosConfig {
+            antelaciones: vec![1440, 120],
+            intervalo: 60,
+            max_intentos: 5,
+            reintento_base: 300,
+        }
+    }
+
+    fn ahora() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2025, 3, 3).unwrap().and_hms_opt(8, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn antelacion_vigente_es_la_menor_alcanzada() {
+        let antelaciones = config().antelaciones;
+
+        // Faltan más de 24 h: todavía nada
+        assert_eq!(antelacion_vigente(&antelaciones, Duration::hours(30)), None);
+        // Entre 24 h y 2 h: el aviso de 24 h
+        assert_eq!(antelacion_vigente(&antelaciones, Duration::hours(24)), Some(1440));
+        assert_eq!(antelacion_vigente(&antelaciones, Duration::hours(3)), Some(1440));
+        // Con 2 h o menos se salta el de 24 h aunque no se haya enviado
+        assert_eq!(antelacion_vigente(&antelaciones, Duration::hours(2)), Some(120));
+        assert_eq!(antelacion_vigente(&antelaciones, Duration::minutes(5)), Some(120));
+        // La cita ya empezó
+        assert_eq!(antelacion_vigente(&antelaciones, Duration::zero()), None);
+        assert_eq!(antelacion_vigente(&antelaciones, Duration::minutes(-10)), None);
+    }
+
+    #[test]
+    fn reintento_con_espera_que_se_duplica() {
+        let config = config();
+        let lejana = ahora() + Duration::days(1);
+
+        for (intentos, espera) in [(1, 300), (2, 600), (3, 1200), (4, 2400)] {
+            assert_eq!(
+                proximo_reintento(&config, intentos, ahora(), lejana),
+                Some(ahora() + Duration::seconds(espera)),
+                "intento {}",
+                intentos
+            );
+        }
+    }
+
+    #[test]
+    fn se_rinde_al_agotar_los_intentos() {
+        let lejana = ahora() + Duration::days(1);
+        assert_eq!(proximo_reintento(&config(), 5, ahora(), lejana), None);
+        assert_eq!(proximo_reintento(&config(), 6, ahora(), lejana), None);
+    }
+
+    #[test]
+    fn se_rinde_si_el_reintento_llegaria_tras_la_cita() {
+        // El tercer fallo espera 20 minutos
+        let cita = ahora() + Duration::minutes(20);
+        assert_eq!(proximo_reintento(&config(), 3, ahora(), cita), None);
+        assert_eq!(
+            proximo_reintento(&config(), 3, ahora(), cita + Duration::seconds(1)),
+            Some(cita)
+        );
+    }
+}
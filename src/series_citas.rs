@@ -0,0 +1,621 @@
+// Series de citas recurrentes para controles periódicos. La serie guarda la
+// regla (semanal o mensual, cada `intervalo`, hasta `repeticiones` citas o
+// hasta una fecha) y cada ocurrencia es una cita normal con `serie_id` y
+// `serie_indice`, que se puede editar o cancelar sola, junto con las
+// siguientes o con toda la serie.
+
+use axum::{
+    extract::{Path, Query, State},
+    response::Json,
+};
+use chrono::{Duration, Months, NaiveDate, NaiveDateTime, NaiveTime};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{PgConnection, PgPool, Row};
+use std::sync::Arc;
+
+use crate::agenda;
+use crate::auditoria::{self, Actor, Evento};
+use crate::config::Config;
+use crate::errores::{ErrorApi, ErrorCampo};
+use crate::estados_cita::{self, CancelarCitaRequest, EstadoCita};
+use crate::validacion::{Errores, ValidatedJson, Validar};
+use crate::{
+    actualizar_cita, auditar, cita_actual, cita_desde_fila, confirmar_transaccion, exigir_paciente_activo,
+    iniciar_transaccion, Cita,
+};
+
+const MAX_CITAS_SERIE: usize = 52;
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Frecuencia {
+    Semanal,
+    Mensual,
+}
+
+impl Frecuencia {
+    fn como_str(self) -> &'static str {
+        match self {
+            Frecuencia::Semanal => "semanal",
+            Frecuencia::Mensual => "mensual",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct NuevaSerie {
+    paciente_id: i32,
+    usuario_id: i32,
+    // Primera cita de la serie
+    fecha_hora: NaiveDateTime,
+    frecuencia: Frecuencia,
+    // Cada cuántas semanas o meses; por defecto 1
+    intervalo: Option<i32>,
+    // Se indica exactamente uno de los dos
+    repeticiones: Option<i32>,
+    hasta: Option<NaiveDate>,
+    motivo: Option<String>,
+}
+
+impl NuevaSerie {
+    // Fechas de las citas de la serie. Las mensuales se cuentan siempre desde
+    // la primera, de modo que un día 31 cae en el último día de los meses
+    // más cortos sin arrastrar el desfase a los siguientes.
+    fn ocurrencias(&self) -> Vec<NaiveDateTime> {
+        let intervalo = self.intervalo.unwrap_or(1).max(1) as u32;
+        let limite = self.repeticiones.map_or(MAX_CITAS_SERIE + 1, |n| n.max(0) as usize);
+
+        (0..)
+            .map_while(|n: u32| match self.frecuencia {
+                Frecuencia::Semanal => self
+                    .fecha_hora
+                    .checked_add_signed(Duration::weeks(i64::from(n * intervalo))),
+                Frecuencia::Mensual => self.fecha_hora.checked_add_months(Months::new(n * intervalo)),
+            })
+            .take_while(|fecha_hora| self.hasta.is_none_or(|hasta| fecha_hora.date() <= hasta))
+            .take(limite)
+            .collect()
+    }
+}
+
+impl Validar for NuevaSerie {
+    fn validar(&self) -> Vec<ErrorCampo> {
+        let mut errores = Errores::default();
+        errores.id("paciente_id", self.paciente_id);
+        errores.id("usuario_id", self.usuario_id);
+        errores.opcional("motivo", self.motivo.as_deref(), 500);
+
+        if let Some(intervalo) = self.intervalo {
+            if !(1..=12).contains(&intervalo) {
+                errores.agregar("intervalo", "El intervalo debe estar entre 1 y 12");
+            }
+        }
+
+        match (self.repeticiones, self.hasta) {
+            (Some(_), Some(_)) | (None, None) => {
+                errores.agregar("repeticiones", "Indica el número de repeticiones o la fecha final, pero no ambos");
+            }
+            (Some(repeticiones), None) => {
+                if !(2..=MAX_CITAS_SERIE as i32).contains(&repeticiones) {
+                    errores.agregar(
+                        "repeticiones",
+                        format!("Una serie debe tener entre 2 y {} citas", MAX_CITAS_SERIE),
+                    );
+                }
+            }
+            (None, Some(hasta)) => {
+                if hasta <= self.fecha_hora.date() {
+                    errores.agregar("hasta", "La fecha final debe ser posterior a la primera cita");
+                } else if self.ocurrencias().len() > MAX_CITAS_SERIE {
+                    errores.agregar("hasta", format!("Una serie no puede tener más de {} citas", MAX_CITAS_SERIE));
+                }
+            }
+        }
+
+        errores.terminar()
+    }
+}
+
+#[derive(Serialize)]
+pub struct Serie {
+    id: i32,
+    paciente_id: i32,
+    usuario_id: i32,
+    frecuencia: Frecuencia,
+    intervalo: i32,
+    inicio: NaiveDateTime,
+    repeticiones: Option<i32>,
+    hasta: Option<NaiveDate>,
+    motivo: Option<String>,
+    creada_por: Option<i32>,
+    creada_en: NaiveDateTime,
+    citas: Vec<Cita>,
+}
+
+// A qué citas de la serie se aplica una edición o cancelación, a partir de
+// la cita de la ruta
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Alcance {
+    Esta,
+    Siguientes,
+    Todas,
+}
+
+#[derive(Deserialize)]
+pub struct ConsultaAlcance {
+    alcance: Alcance,
+}
+
+#[derive(Deserialize)]
+pub struct EditarSerie {
+    // Nueva hora del día; cada cita conserva su fecha
+    hora: Option<NaiveTime>,
+    usuario_id: Option<i32>,
+    motivo: Option<String>,
+}
+
+impl Validar for EditarSerie {
+    fn validar(&self) -> Vec<ErrorCampo> {
+        let mut errores = Errores::default();
+        if self.hora.is_none() && self.usuario_id.is_none() && self.motivo.is_none() {
+            errores.agregar("cuerpo", "Indica al menos uno de: hora, usuario_id, motivo");
+        }
+        if let Some(usuario_id) = self.usuario_id {
+            errores.id("usuario_id", usuario_id);
+        }
+        errores.opcional("motivo", self.motivo.as_deref(), 500);
+        errores.terminar()
+    }
+}
+
+// POST /citas/series
+// Crea la serie y todas sus citas, o ninguna si alguna choca con la agenda
+pub async fn create_serie(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    actor: Actor,
+    ValidatedJson(serie): ValidatedJson<NuevaSerie>,
+) -> Result<Json<Serie>, ErrorApi> {
+    let fechas = serie.ocurrencias();
+    let intervalo = serie.intervalo.unwrap_or(1);
+
+    let mut tx = iniciar_transaccion(&pool).await?;
+    exigir_paciente_activo(&mut tx, serie.paciente_id).await?;
+
+    // Se revisan todas las fechas para informar de todos los choques a la vez
+    let mut conflictos = Vec::new();
+    for fecha_hora in &fechas {
+        match agenda::verificar_cita(&mut tx, serie.usuario_id, *fecha_hora, config.duracion_cita_minutos, None).await {
+            Ok(()) => {}
+            Err(ErrorApi::ConflictoAgenda { mensaje, detalles }) => conflictos.push(json!({
+                "fecha_hora": fecha_hora,
+                "mensaje": mensaje,
+                "detalles": detalles,
+            })),
+            Err(e) => return Err(e),
+        }
+    }
+    if !conflictos.is_empty() {
+        return Err(ErrorApi::ConflictoAgenda {
+            mensaje: format!(
+                "{} de las {} citas de la serie no caben en la agenda del médico",
+                conflictos.len(),
+                fechas.len()
+            ),
+            detalles: Some(json!({ "conflictos": conflictos })),
+        });
+    }
+
+    let row = sqlx::query(
+        "INSERT INTO series_citas (paciente_id, usuario_id, frecuencia, intervalo, inicio, repeticiones, hasta, motivo, creada_por) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING id, creada_en"
+    )
+    .bind(serie.paciente_id)
+    .bind(serie.usuario_id)
+    .bind(serie.frecuencia.como_str())
+    .bind(intervalo)
+    .bind(serie.fecha_hora)
+    .bind(serie.repeticiones)
+    .bind(serie.hasta)
+    .bind(&serie.motivo)
+    .bind(actor.usuario_id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al crear serie de citas: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    let serie_id: i32 = row.get("id");
+
+    let mut citas = Vec::with_capacity(fechas.len());
+    for (indice, fecha_hora) in fechas.into_iter().enumerate() {
+        let cita = sqlx::query(
+            "INSERT INTO citas (paciente_id, usuario_id, fecha_hora, estado, motivo, serie_id, serie_indice) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id, paciente_id, usuario_id, fecha_hora, estado, motivo, serie_id"
+        )
+        .bind(serie.paciente_id)
+        .bind(serie.usuario_id)
+        .bind(fecha_hora)
+        .bind(EstadoCita::Programada.como_str())
+        .bind(&serie.motivo)
+        .bind(serie_id)
+        .bind(indice as i32 + 1)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al crear cita de la serie {}: {}", serie_id, e);
+            ErrorApi::from(e)
+        })?;
+
+        let cita = cita_desde_fila(&cita);
+        auditar(
+            &mut *tx,
+            &actor,
+            Evento::cambio(
+                "crear",
+                "citas",
+                cita.id.unwrap_or_default(),
+                Some(cita.paciente_id),
+                None,
+                auditoria::valor(&cita),
+            ),
+        )
+        .await?;
+        citas.push(cita);
+    }
+
+    let creada = Serie {
+        id: serie_id,
+        paciente_id: serie.paciente_id,
+        usuario_id: serie.usuario_id,
+        frecuencia: serie.frecuencia,
+        intervalo,
+        inicio: serie.fecha_hora,
+        repeticiones: serie.repeticiones,
+        hasta: serie.hasta,
+        motivo: serie.motivo,
+        creada_por: Some(actor.usuario_id),
+        creada_en: row.get("creada_en"),
+        citas,
+    };
+
+    auditar(
+        &mut *tx,
+        &actor,
+        Evento::cambio(
+            "crear",
+            "series_citas",
+            serie_id,
+            Some(creada.paciente_id),
+            None,
+            auditoria::valor(&json!({
+                "frecuencia": creada.frecuencia,
+                "intervalo": creada.intervalo,
+                "inicio": creada.inicio,
+                "repeticiones": creada.repeticiones,
+                "hasta": creada.hasta,
+                "citas": creada.citas.len(),
+            })),
+        ),
+    )
+    .await?;
+    confirmar_transaccion(tx).await?;
+
+    Ok(Json(creada))
+}
+
+// GET /citas/series/:id
+pub async fn get_serie(
+    Path(id): Path<i32>,
+    State(pool): State<PgPool>,
+    actor: Actor,
+) -> Result<Json<Serie>, ErrorApi> {
+    let row = sqlx::query(
+        "SELECT id, paciente_id, usuario_id, frecuencia, intervalo, inicio, repeticiones, hasta, motivo, creada_por, creada_en FROM series_citas WHERE id = $1"
+    )
+    .bind(id)
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al obtener serie de citas: {}", e);
+        ErrorApi::from(e)
+    })?
+    .ok_or_else(|| ErrorApi::NoEncontrado("Serie de citas no encontrada".to_string()))?;
+
+    let citas = sqlx::query(
+        "SELECT id, paciente_id, usuario_id, fecha_hora, estado, motivo, serie_id FROM citas WHERE serie_id = $1 ORDER BY serie_indice"
+    )
+    .bind(id)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al obtener citas de la serie {}: {}", id, e);
+        ErrorApi::from(e)
+    })?;
+
+    let frecuencia = match row.get::<&str, _>("frecuencia") {
+        "mensual" => Frecuencia::Mensual,
+        _ => Frecuencia::Semanal,
+    };
+
+    let serie = Serie {
+        id: row.get("id"),
+        paciente_id: row.get("paciente_id"),
+        usuario_id: row.get("usuario_id"),
+        frecuencia,
+        intervalo: row.get("intervalo"),
+        inicio: row.get("inicio"),
+        repeticiones: row.get("repeticiones"),
+        hasta: row.get("hasta"),
+        motivo: row.get("motivo"),
+        creada_por: row.get("creada_por"),
+        creada_en: row.get("creada_en"),
+        citas: citas.iter().map(cita_desde_fila).collect(),
+    };
+
+    auditar(&pool, &actor, Evento::lectura("series_citas", Some(id), Some(serie.paciente_id))).await?;
+
+    Ok(Json(serie))
+}
+
+// Serie de la cita `id` y citas a las que llega el alcance, bloqueadas y en
+// el orden de la serie
+async fn citas_afectadas(conn: &mut PgConnection, id: i32, alcance: Alcance) -> Result<(i32, Vec<i32>), ErrorApi> {
+    let row = sqlx::query("SELECT serie_id, serie_indice FROM citas WHERE id = $1 FOR UPDATE")
+        .bind(id)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al obtener cita: {}", e);
+            ErrorApi::from(e)
+        })?
+        .ok_or_else(|| ErrorApi::NoEncontrado("Cita no encontrada".to_string()))?;
+
+    let Some(serie_id) = row.get::<Option<i32>, _>("serie_id") else {
+        return Err(ErrorApi::EstadoInvalido {
+            mensaje: "La cita no pertenece a ninguna serie".to_string(),
+            detalles: None,
+        });
+    };
+
+    let desde_indice: Option<i32> = match alcance {
+        Alcance::Esta => return Ok((serie_id, vec![id])),
+        Alcance::Siguientes => row.get("serie_indice"),
+        Alcance::Todas => None,
+    };
+
+    let ids = sqlx::query(
+        "SELECT id FROM citas WHERE serie_id = $1 AND ($2::INTEGER IS NULL OR serie_indice >= $2) ORDER BY serie_indice FOR UPDATE"
+    )
+    .bind(serie_id)
+    .bind(desde_indice)
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al obtener citas de la serie {}: {}", serie_id, e);
+        ErrorApi::from(e)
+    })?
+    .iter()
+    .map(|row| row.get("id"))
+    .collect();
+
+    Ok((serie_id, ids))
+}
+
+// PATCH /citas/:id/serie?alcance=esta|siguientes|todas
+// Las citas ya cerradas de la serie se dejan como están
+pub async fn editar_serie(
+    Path(id): Path<i32>,
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    actor: Actor,
+    Query(consulta): Query<ConsultaAlcance>,
+    ValidatedJson(cambios): ValidatedJson<EditarSerie>,
+) -> Result<Json<Vec<Cita>>, ErrorApi> {
+    let mut tx = iniciar_transaccion(&pool).await?;
+    let (serie_id, ids) = citas_afectadas(&mut tx, id, consulta.alcance).await?;
+
+    let mut actualizadas = Vec::with_capacity(ids.len());
+    let mut conflictos = Vec::new();
+    for cita_id in ids {
+        let (antes, _) = cita_actual(&mut *tx, cita_id).await?;
+        if consulta.alcance != Alcance::Esta && antes.estado.cerrada() {
+            continue;
+        }
+
+        let cita = Cita {
+            id: antes.id,
+            paciente_id: antes.paciente_id,
+            usuario_id: cambios.usuario_id.unwrap_or(antes.usuario_id),
+            fecha_hora: cambios
+                .hora
+                .map_or(antes.fecha_hora, |hora| antes.fecha_hora.date().and_time(hora)),
+            estado: antes.estado,
+            motivo: cambios.motivo.clone().or_else(|| antes.motivo.clone()),
+            serie_id: antes.serie_id,
+        };
+
+        match actualizar_cita(&mut tx, &actor, cita_id, antes, cita, config.duracion_cita_minutos).await {
+            Ok((actualizada, _)) => actualizadas.push(actualizada),
+            Err(ErrorApi::ConflictoAgenda { mensaje, detalles }) => conflictos.push(json!({
+                "cita_id": cita_id,
+                "mensaje": mensaje,
+                "detalles": detalles,
+            })),
+            Err(e) => return Err(e),
+        }
+    }
+
+    if !conflictos.is_empty() {
+        return Err(ErrorApi::ConflictoAgenda {
+            mensaje: format!("{} citas de la serie no caben en la agenda con esos cambios", conflictos.len()),
+            detalles: Some(json!({ "conflictos": conflictos })),
+        });
+    }
+
+    // La regla de la serie refleja los cambios que afectan a toda ella
+    if consulta.alcance == Alcance::Todas {
+        sqlx::query(
+            "UPDATE series_citas SET usuario_id = COALESCE($1, usuario_id), motivo = COALESCE($2, motivo), inicio = COALESCE(inicio::DATE + $3::TIME, inicio) WHERE id = $4"
+        )
+        .bind(cambios.usuario_id)
+        .bind(&cambios.motivo)
+        .bind(cambios.hora)
+        .bind(serie_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al actualizar serie de citas: {}", e);
+            ErrorApi::from(e)
+        })?;
+    }
+
+    confirmar_transaccion(tx).await?;
+
+    Ok(Json(actualizadas))
+}
+
+// POST /citas/:id/serie/cancelar?alcance=esta|siguientes|todas
+// Las citas de la serie que ya no admiten cancelación se dejan como están
+pub async fn cancelar_serie(
+    Path(id): Path<i32>,
+    State(pool): State<PgPool>,
+    actor: Actor,
+    Query(consulta): Query<ConsultaAlcance>,
+    ValidatedJson(request): ValidatedJson<CancelarCitaRequest>,
+) -> Result<Json<Vec<Cita>>, ErrorApi> {
+    let mut tx = iniciar_transaccion(&pool).await?;
+    let (_, ids) = citas_afectadas(&mut tx, id, consulta.alcance).await?;
+
+    let mut canceladas = Vec::with_capacity(ids.len());
+    for cita_id in ids {
+        let (antes, _) = cita_actual(&mut *tx, cita_id).await?;
+        if consulta.alcance != Alcance::Esta && !antes.estado.siguientes().contains(&EstadoCita::Cancelada) {
+            continue;
+        }
+
+        let (cancelada, _) = estados_cita::transicion(
+            &mut tx,
+            &actor,
+            cita_id,
+            antes,
+            EstadoCita::Cancelada,
+            Some(request.motivo.trim()),
+        )
+        .await?;
+        canceladas.push(cancelada);
+    }
+
+    confirmar_transaccion(tx).await?;
+
+    Ok(Json(canceladas))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fecha(anio: i32, mes: u32, dia: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(anio, mes, dia).unwrap()
+    }
+
+    fn a_las_9(anio: i32, mes: u32, dia: u32) -> NaiveDateTime {
+        fecha(anio, mes, dia).and_hms_opt(9, 0, 0).unwrap()
+    }
+
+    fn serie(
+        fecha_hora: NaiveDateTime,
+        frecuencia: Frecuencia,
+        intervalo: Option<i32>,
+        repeticiones: Option<i32>,
+        hasta: Option<NaiveDate>,
+    ) -> NuevaSerie {
+        NuevaSerie {
+            paciente_id: 1,
+            usuario_id: 1,
+            fecha_hora,
+            frecuencia,
+            intervalo,
+            repeticiones,
+            hasta,
+            motivo: None,
+        }
+    }
+
+    #[test]
+    fn semanal_cada_intervalo_semanas() {
+        let serie = serie(a_las_9(2025, 3, 3), Frecuencia::Semanal, Some(2), Some(4), None);
+        assert_eq!(
+            serie.ocurrencias(),
+            vec![a_las_9(2025, 3, 3), a_las_9(2025, 3, 17), a_las_9(2025, 3, 31), a_las_9(2025, 4, 14)]
+        );
+    }
+
+    #[test]
+    fn mensual_conserva_dia_y_hora() {
+        let serie = serie(a_las_9(2025, 11, 15), Frecuencia::Mensual, None, Some(3), None);
+        assert_eq!(
+            serie.ocurrencias(),
+            vec![a_las_9(2025, 11, 15), a_las_9(2025, 12, 15), a_las_9(2026, 1, 15)]
+        );
+    }
+
+    #[test]
+    fn dia_31_cae_al_final_de_los_meses_cortos_sin_desfase() {
+        let serie = serie(a_las_9(2025, 1, 31), Frecuencia::Mensual, None, Some(4), None);
+        assert_eq!(
+            serie.ocurrencias(),
+            vec![a_las_9(2025, 1, 31), a_las_9(2025, 2, 28), a_las_9(2025, 3, 31), a_las_9(2025, 4, 30)]
+        );
+
+        // Año bisiesto
+        let serie = NuevaSerie {
+            fecha_hora: a_las_9(2024, 1, 31),
+            ..serie
+        };
+        assert_eq!(serie.ocurrencias()[1], a_las_9(2024, 2, 29));
+        assert_eq!(serie.ocurrencias()[2], a_las_9(2024, 3, 31));
+    }
+
+    #[test]
+    fn hasta_incluye_la_ocurrencia_de_ese_dia() {
+        let serie = serie(a_las_9(2025, 3, 3), Frecuencia::Semanal, None, None, Some(fecha(2025, 3, 24)));
+        assert_eq!(serie.ocurrencias().len(), 4);
+        assert_eq!(serie.ocurrencias().last(), Some(&a_las_9(2025, 3, 24)));
+
+        let serie = NuevaSerie {
+            hasta: Some(fecha(2025, 3, 23)),
+            ..serie
+        };
+        assert_eq!(serie.ocurrencias().last(), Some(&a_las_9(2025, 3, 17)));
+    }
+
+    #[test]
+    fn tope_de_citas_por_serie() {
+        // Un `hasta` lejano se corta en MAX + 1 para poder detectar el exceso
+        let lejana = serie(a_las_9(2025, 1, 6), Frecuencia::Semanal, None, None, Some(fecha(2035, 1, 1)));
+        assert_eq!(lejana.ocurrencias().len(), MAX_CITAS_SERIE + 1);
+        let errores = lejana.validar();
+        assert_eq!(errores.len(), 1);
+        assert_eq!(errores[0].campo, "hasta");
+
+        // Exactamente MAX semanas es válido
+        let justa = NuevaSerie {
+            hasta: Some(fecha(2025, 1, 6) + Duration::weeks(MAX_CITAS_SERIE as i64 - 1)),
+            ..lejana
+        };
+        assert_eq!(justa.ocurrencias().len(), MAX_CITAS_SERIE);
+        assert!(justa.validar().is_empty());
+
+        let repeticiones = serie(a_las_9(2025, 1, 6), Frecuencia::Mensual, None, Some(MAX_CITAS_SERIE as i32), None);
+        assert_eq!(repeticiones.ocurrencias().len(), MAX_CITAS_SERIE);
+        assert!(repeticiones.validar().is_empty());
+
+        let demasiadas = NuevaSerie {
+            repeticiones: Some(MAX_CITAS_SERIE as i32 + 1),
+            ..repeticiones
+        };
+        assert_eq!(demasiadas.validar()[0].campo, "repeticiones");
+    }
+}
@@ -0,0 +1,234 @@
+use axum::{extract::State, http::StatusCode, response::Json};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgExecutor, PgPool, Row};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::auth::{generar_token, hash_token, AuthUser, TipoToken};
+use crate::config::Config;
+use crate::errores::ErrorApi;
+use crate::permisos::permisos_de_usuario;
+use crate::{confirmar_transaccion, iniciar_transaccion};
+
+fn sesion_invalida() -> ErrorApi {
+    ErrorApi::NoAutenticado("Sesión inválida, revocada o expirada".to_string())
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RefrescarTokenRequest {
+    refresh_token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TokenResponse {
+    token: String,
+    refresh_token: String,
+}
+
+// Registra una nueva sesión dentro de `familia_id` y devuelve el refresh token
+pub async fn crear_sesion<'e, E: PgExecutor<'e>>(
+    executor: E,
+    usuario_id: i32,
+    familia_id: Uuid,
+    duracion: u64,
+) -> Result<String, sqlx::Error> {
+    let token = generar_token();
+
+    sqlx::query(
+        "INSERT INTO sesiones (id, familia_id, usuario_id, token_hash, expira_en) VALUES ($1, $2, $3, $4, NOW() + make_interval(secs => $5))"
+    )
+    .bind(Uuid::new_v4())
+    .bind(familia_id)
+    .bind(usuario_id)
+    .bind(hash_token(&token))
+    .bind(duracion as f64)
+    .execute(executor)
+    .await?;
+
+    Ok(token)
+}
+
+// Sesión que sucede a una rotada dentro de la misma familia. Hereda su
+// vencimiento, de modo que la familia caduca a la `duracion_refresco` del
+// inicio de sesión por mucho que se use
+async fn continuar_sesion<'e, E: PgExecutor<'e>>(
+    executor: E,
+    usuario_id: i32,
+    familia_id: Uuid,
+    expira_en: NaiveDateTime,
+) -> Result<String, sqlx::Error> {
+    let token = generar_token();
+
+    sqlx::query(
+        "INSERT INTO sesiones (id, familia_id, usuario_id, token_hash, expira_en) VALUES ($1, $2, $3, $4, $5)"
+    )
+    .bind(Uuid::new_v4())
+    .bind(familia_id)
+    .bind(usuario_id)
+    .bind(hash_token(&token))
+    .bind(expira_en)
+    .execute(executor)
+    .await?;
+
+    Ok(token)
+}
+
+// POST /token/refresh
+pub async fn refrescar_token(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Json(request): Json<RefrescarTokenRequest>,
+) -> Result<Json<TokenResponse>, ErrorApi> {
+    let mut tx = iniciar_transaccion(&pool).await?;
+
+    let sesion = sqlx::query(
+        "SELECT s.familia_id, s.usuario_id, s.usado_en IS NOT NULL AS usado, s.revocado_en IS NOT NULL AS revocado, s.expira_en < NOW() AS expirado, u.email, u.totp_activo, r.nombre AS rol_nombre, r.requiere_2fa FROM sesiones s JOIN usuarios u ON s.usuario_id = u.id JOIN roles r ON u.rol_id = r.id WHERE s.token_hash = $1 AND u.activo FOR UPDATE OF s"
+    )
+    .bind(hash_token(&request.refresh_token))
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al buscar sesión: {}", e);
+        ErrorApi::from(e)
+    })?
+    .ok_or_else(sesion_invalida)?;
+
+    let familia_id: Uuid = sesion.get("familia_id");
+    let usuario_id: i32 = sesion.get("usuario_id");
+
+    if sesion.get::<bool, _>("revocado") || sesion.get::<bool, _>("expirado") {
+        return Err(sesion_invalida());
+    }
+
+    // Un token ya rotado que vuelve a presentarse indica robo: se revoca toda la familia
+    if sesion.get::<bool, _>("usado") {
+        eprintln!(
+            "Reutilización de refresh token detectada para usuario {}, revocando sesión",
+            usuario_id
+        );
+        revocar_familia(&mut *tx, familia_id).await.map_err(|e| {
+            eprintln!("Error al revocar sesión: {}", e);
+            ErrorApi::from(e)
+        })?;
+        confirmar_transaccion(tx).await?;
+        return Err(sesion_invalida());
+    }
+
+    // Si el rol pasó a exigir 2FA después de abrir la sesión y el usuario aún
+    // no lo configuró, la sesión no se renueva: debe volver a iniciar sesión
+    // y enrolarse
+    if sesion.get::<bool, _>("requiere_2fa") && !sesion.get::<bool, _>("totp_activo") {
+        eprintln!(
+            "Refresco rechazado para usuario {}: su rol exige 2FA y no está configurado",
+            usuario_id
+        );
+        revocar_familia(&mut *tx, familia_id).await.map_err(|e| {
+            eprintln!("Error al revocar sesión: {}", e);
+            ErrorApi::from(e)
+        })?;
+        confirmar_transaccion(tx).await?;
+        return Err(ErrorApi::NoAutenticado(
+            "Tu rol exige verificación en dos pasos; inicia sesión de nuevo para configurarla".to_string(),
+        ));
+    }
+
+    let expira_en: NaiveDateTime = sqlx::query("UPDATE sesiones SET usado_en = NOW() WHERE token_hash = $1 RETURNING expira_en")
+        .bind(hash_token(&request.refresh_token))
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al rotar sesión: {}", e);
+            ErrorApi::from(e)
+        })?
+        .get("expira_en");
+
+    let refresh_token = continuar_sesion(&mut *tx, usuario_id, familia_id, expira_en)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al crear sesión: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    // Los permisos se vuelven a resolver en cada refresco
+    let permisos = permisos_de_usuario(&mut *tx, usuario_id).await.map_err(|e| {
+        eprintln!("Error al obtener permisos: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    let claims = config.jwt.claims(
+        TipoToken::Acceso,
+        sesion.get("email"),
+        sesion.get("rol_nombre"),
+        usuario_id,
+        permisos,
+        config.jwt.duracion_acceso,
+    );
+
+    let token = config.jwt.firmar(&claims).map_err(|e| {
+        eprintln!("Error al crear token: {}", e);
+        ErrorApi::Interno
+    })?;
+
+    confirmar_transaccion(tx).await?;
+
+    Ok(Json(TokenResponse {
+        token,
+        refresh_token,
+    }))
+}
+
+// POST /logout
+pub async fn logout(
+    State(pool): State<PgPool>,
+    Json(request): Json<RefrescarTokenRequest>,
+) -> Result<StatusCode, ErrorApi> {
+    sqlx::query(
+        "UPDATE sesiones SET revocado_en = NOW() WHERE revocado_en IS NULL AND familia_id = (SELECT familia_id FROM sesiones WHERE token_hash = $1)"
+    )
+    .bind(hash_token(&request.refresh_token))
+    .execute(&pool)
+    .await
+    .map_err(|e| {
+        eprintln!("Error al cerrar sesión: {}", e);
+        ErrorApi::from(e)
+    })?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// POST /logout-all
+pub async fn logout_all(
+    usuario: AuthUser,
+    State(pool): State<PgPool>,
+) -> Result<StatusCode, ErrorApi> {
+    revocar_sesiones_usuario(&pool, usuario.usuario_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Error al cerrar todas las sesiones: {}", e);
+            ErrorApi::from(e)
+        })?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn revocar_familia<'e, E: PgExecutor<'e>>(executor: E, familia_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE sesiones SET revocado_en = NOW() WHERE familia_id = $1 AND revocado_en IS NULL")
+        .bind(familia_id)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn revocar_sesiones_usuario<'e, E: PgExecutor<'e>>(
+    executor: E,
+    usuario_id: i32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE sesiones SET revocado_en = NOW() WHERE usuario_id = $1 AND revocado_en IS NULL")
+        .bind(usuario_id)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,367 @@
+use axum::{
+    async_trait,
+    extract::{rejection::JsonRejection, FromRequest, Request},
+    response::Json,
+};
+use chrono::{Local, NaiveDate};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::errores::{ErrorApi, ErrorCampo};
+
+// Reglas de validación de un DTO de entrada. Devuelve un error por cada
+// campo inválido; vacío si los datos son aceptables.
+pub trait Validar {
+    fn validar(&self) -> Vec<ErrorCampo>;
+}
+
+// En las listas cada error se prefija con la posición del elemento
+impl<T: Validar> Validar for Vec<T> {
+    fn validar(&self) -> Vec<ErrorCampo> {
+        self.iter()
+            .enumerate()
+            .flat_map(|(i, elemento)| {
+                elemento.validar().into_iter().map(move |error| ErrorCampo {
+                    campo: format!("[{}].{}", i, error.campo),
+                    mensaje: error.mensaje,
+                })
+            })
+            .collect()
+    }
+}
+
+// Igual que `Json<T>`, pero ejecuta `Validar` y responde 422 con los
+// errores por campo antes de llegar al handler
+pub struct ValidatedJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validar,
+    S: Send + Sync,
+{
+    type Rejection = ErrorApi;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(valor) = Json::<T>::from_request(req, state).await.map_err(|rechazo| {
+            eprintln!("Cuerpo JSON rechazado: {}", rechazo.body_text());
+            match rechazo {
+                JsonRejection::JsonDataError(_) => ErrorApi::Validacion(vec![ErrorCampo::new(
+                    "cuerpo",
+                    "Faltan campos obligatorios o alguno tiene un tipo incorrecto",
+                )]),
+                JsonRejection::MissingJsonContentType(_) => {
+                    ErrorApi::SolicitudInvalida("El cuerpo debe enviarse como application/json".to_string())
+                }
+                _ => ErrorApi::SolicitudInvalida("El cuerpo no es un JSON válido".to_string()),
+            }
+        })?;
+
+        let errores = valor.validar();
+        if !errores.is_empty() {
+            return Err(ErrorApi::Validacion(errores));
+        }
+
+        Ok(ValidatedJson(valor))
+    }
+}
+
+// Cuerpo de un PATCH como JSON Merge Patch (RFC 7396): los campos ausentes
+// no cambian, `null` borra un campo opcional y los objetos anidados se
+// combinan campo a campo
+#[derive(Deserialize)]
+#[serde(transparent)]
+pub struct Parche(Map<String, Value>);
+
+impl Validar for Parche {
+    fn validar(&self) -> Vec<ErrorCampo> {
+        let mut errores = Errores::default();
+        if self.0.is_empty() {
+            errores.agregar("cuerpo", "Indica al menos un campo a modificar");
+        }
+        if self.0.contains_key("id") {
+            errores.agregar("id", "El identificador no se puede modificar");
+        }
+        errores.terminar()
+    }
+}
+
+impl Parche {
+    // Aplica el parche sobre el estado actual y valida el resultado como si
+    // se hubiera enviado completo
+    pub fn aplicar<T: Serialize + DeserializeOwned + Validar>(&self, actual: &T) -> Result<T, ErrorApi> {
+        let Ok(Value::Object(mut destino)) = serde_json::to_value(actual) else {
+            return Err(ErrorApi::Interno);
+        };
+
+        let desconocidos: Vec<ErrorCampo> = self
+            .0
+            .keys()
+            .filter(|campo| !destino.contains_key(*campo))
+            .map(|campo| ErrorCampo::new(campo, "Campo desconocido"))
+            .collect();
+        if !desconocidos.is_empty() {
+            return Err(ErrorApi::Validacion(desconocidos));
+        }
+
+        combinar(&mut destino, &self.0);
+
+        let resultado: T = serde_json::from_value(Value::Object(destino)).map_err(|e| {
+            eprintln!("Parche rechazado: {}", e);
+            ErrorApi::Validacion(vec![ErrorCampo::new(
+                "cuerpo",
+                "Falta algún campo obligatorio o alguno tiene un tipo incorrecto",
+            )])
+        })?;
+
+        let errores = resultado.validar();
+        if !errores.is_empty() {
+            return Err(ErrorApi::Validacion(errores));
+        }
+
+        Ok(resultado)
+    }
+}
+
+fn combinar(destino: &mut Map<String, Value>, parche: &Map<String, Value>) {
+    for (campo, valor) in parche {
+        match (valor, destino.get_mut(campo)) {
+            (Value::Null, _) => {
+                destino.remove(campo);
+            }
+            (Value::Object(parcial), Some(Value::Object(actual))) => combinar(actual, parcial),
+            (Value::Object(parcial), _) => {
+                let mut nuevo = Map::new();
+                combinar(&mut nuevo, parcial);
+                destino.insert(campo.clone(), Value::Object(nuevo));
+            }
+            _ => {
+                destino.insert(campo.clone(), valor.clone());
+            }
+        }
+    }
+}
+
+// Acumulador de errores con las reglas comunes a varios DTOs
+#[derive(Default)]
+pub struct Errores(Vec<ErrorCampo>);
+
+impl Errores {
+    pub fn agregar(&mut self, campo: &str, mensaje: impl Into<String>) {
+        self.0.push(ErrorCampo::new(campo, mensaje));
+    }
+
+    pub fn requerido(&mut self, campo: &str, valor: &str, maximo: usize) {
+        let valor = valor.trim();
+        if valor.is_empty() {
+            self.agregar(campo, "Este campo es obligatorio");
+        } else if valor.chars().count() > maximo {
+            self.agregar(campo, format!("No puede superar {} caracteres", maximo));
+        }
+    }
+
+    pub fn opcional(&mut self, campo: &str, valor: Option<&str>, maximo: usize) {
+        if valor.is_some_and(|valor| valor.chars().count() > maximo) {
+            self.agregar(campo, format!("No puede superar {} caracteres", maximo));
+        }
+    }
+
+    pub fn email(&mut self, campo: &str, valor: &str) {
+        if !email_valido(valor.trim()) {
+            self.agregar(campo, "El email no tiene un formato válido");
+        }
+    }
+
+    pub fn telefono(&mut self, campo: &str, valor: &str) {
+        let digitos = valor.chars().filter(char::is_ascii_digit).count();
+        let caracteres_validos = valor
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '+' | '-' | ' ' | '(' | ')'));
+        if !caracteres_validos || !(7..=15).contains(&digitos) {
+            self.agregar(campo, "El teléfono debe tener entre 7 y 15 dígitos");
+        }
+    }
+
+    pub fn sexo(&mut self, campo: &str, valor: &str) {
+        if !matches!(valor, "M" | "F") {
+            self.agregar(campo, "El sexo debe ser M o F");
+        }
+    }
+
+    pub fn fecha_nacimiento(&mut self, campo: &str, fecha: NaiveDate) {
+        let hoy = Local::now().date_naive();
+        if fecha > hoy {
+            self.agregar(campo, "La fecha de nacimiento no puede estar en el futuro");
+        } else if hoy.years_since(fecha).is_some_and(|anios| anios > 130) {
+            self.agregar(campo, "La fecha de nacimiento no es válida");
+        }
+    }
+
+    pub fn id(&mut self, campo: &str, valor: i32) {
+        if valor <= 0 {
+            self.agregar(campo, "Debe ser un identificador válido");
+        }
+    }
+
+    pub fn terminar(self) -> Vec<ErrorCampo> {
+        self.0
+    }
+}
+
+fn email_valido(email: &str) -> bool {
+    let Some((local, dominio)) = email.split_once('@') else {
+        return false;
+    };
+
+    !local.is_empty()
+        && email.len() <= 254
+        && !dominio.contains('@')
+        && !email.chars().any(char::is_whitespace)
+        && dominio.contains('.')
+        && dominio.split('.').all(|parte| !parte.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Direccion {
+        ciudad: String,
+        calle: Option<String>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Ficha {
+        nombre: String,
+        telefono: Option<String>,
+        direccion: Option<Direccion>,
+    }
+
+    impl Validar for Ficha {
+        fn validar(&self) -> Vec<ErrorCampo> {
+            let mut errores = Errores::default();
+            errores.requerido("nombre", &self.nombre, 10);
+            errores.terminar()
+        }
+    }
+
+    fn ficha() -> Ficha {
+        Ficha {
+            nombre: "Ana".to_string(),
+            telefono: Some("0414-1234567".to_string()),
+            direccion: Some(Direccion {
+                ciudad: "Mérida".to_string(),
+                calle: Some("Av. 3".to_string()),
+            }),
+        }
+    }
+
+    fn parche(valor: Value) -> Parche {
+        serde_json::from_value(valor).unwrap()
+    }
+
+    fn aplicar(valor: Value, actual: &Ficha) -> Ficha {
+        match parche(valor).aplicar(actual) {
+            Ok(resultado) => resultado,
+            Err(error) => panic!("parche rechazado: {:?}", campos(error)),
+        }
+    }
+
+    fn campos(error: ErrorApi) -> Vec<String> {
+        match error {
+            ErrorApi::Validacion(errores) => errores.into_iter().map(|error| error.campo).collect(),
+            _ => panic!("se esperaba un error de validación"),
+        }
+    }
+
+    #[test]
+    fn parche_cambia_solo_los_campos_enviados() {
+        let resultado = aplicar(json!({ "nombre": "Beatriz" }), &ficha());
+        assert_eq!(resultado, Ficha { nombre: "Beatriz".to_string(), ..ficha() });
+    }
+
+    #[test]
+    fn parche_con_null_borra_el_campo_opcional() {
+        let resultado = aplicar(json!({ "telefono": null }), &ficha());
+        assert_eq!(resultado, Ficha { telefono: None, ..ficha() });
+    }
+
+    #[test]
+    fn parche_combina_objetos_anidados() {
+        let resultado = aplicar(json!({ "direccion": { "calle": null } }), &ficha());
+        assert_eq!(
+            resultado.direccion,
+            Some(Direccion { ciudad: "Mérida".to_string(), calle: None })
+        );
+
+        // Sin objeto previo el anidado se crea con lo que trae el parche
+        let sin_direccion = Ficha { direccion: None, ..ficha() };
+        let resultado = aplicar(json!({ "direccion": { "ciudad": "Caracas" } }), &sin_direccion);
+        assert_eq!(
+            resultado.direccion,
+            Some(Direccion { ciudad: "Caracas".to_string(), calle: None })
+        );
+    }
+
+    #[test]
+    fn parche_rechaza_campos_desconocidos() {
+        let error = parche(json!({ "nombre": "Beatriz", "edad": 30 })).aplicar(&ficha()).unwrap_err();
+        assert_eq!(campos(error), vec!["edad"]);
+    }
+
+    #[test]
+    fn parche_valida_el_resultado() {
+        let error = parche(json!({ "nombre": "  " })).aplicar(&ficha()).unwrap_err();
+        assert_eq!(campos(error), vec!["nombre"]);
+
+        // Borrar un campo obligatorio deja la ficha incompleta
+        let error = parche(json!({ "nombre": null })).aplicar(&ficha()).unwrap_err();
+        assert_eq!(campos(error), vec!["cuerpo"]);
+    }
+
+    #[test]
+    fn parche_vacio_o_con_id_no_es_valido() {
+        let errores: Vec<String> = parche(json!({})).validar().into_iter().map(|e| e.campo).collect();
+        assert_eq!(errores, vec!["cuerpo"]);
+
+        let errores: Vec<String> = parche(json!({ "id": 7 })).validar().into_iter().map(|e| e.campo).collect();
+        assert_eq!(errores, vec!["id"]);
+    }
+
+    #[test]
+    fn emails_validos_e_invalidos() {
+        assert!(email_valido("ana@clinica.com"));
+        assert!(email_valido("ana.perez+citas@correo.clinica.com.ve"));
+
+        assert!(!email_valido("ana.clinica.com"));
+        assert!(!email_valido("@clinica.com"));
+        assert!(!email_valido("ana@clinica"));
+        assert!(!email_valido("ana@@clinica.com"));
+        assert!(!email_valido("ana@clinica..com"));
+        assert!(!email_valido("ana@.com"));
+        assert!(!email_valido("ana perez@clinica.com"));
+        assert!(!email_valido(&format!("{}@clinica.com", "a".repeat(250))));
+    }
+
+    #[test]
+    fn telefonos_validos_e_invalidos() {
+        let errores = |telefono: &str| {
+            let mut errores = Errores::default();
+            errores.telefono("telefono", telefono);
+            errores.terminar().len()
+        };
+
+        assert_eq!(errores("0414-1234567"), 0);
+        assert_eq!(errores("+58 (274) 123 4567"), 0);
+        assert_eq!(errores("1234567"), 0);
+        assert_eq!(errores("123456789012345"), 0);
+
+        assert_eq!(errores("123456"), 1);
+        assert_eq!(errores("1234567890123456"), 1);
+        assert_eq!(errores("0414.1234567"), 1);
+        assert_eq!(errores("0414-CLINICA"), 1);
+        assert_eq!(errores(""), 1);
+    }
+}